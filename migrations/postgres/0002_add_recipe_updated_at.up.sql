@@ -0,0 +1 @@
+ALTER TABLE recipes ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
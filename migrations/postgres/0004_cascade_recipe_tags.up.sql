@@ -0,0 +1,4 @@
+ALTER TABLE recipe_tags
+  DROP CONSTRAINT recipe_tags_recipe_id_fkey,
+  ADD CONSTRAINT recipe_tags_recipe_id_fkey
+    FOREIGN KEY (recipe_id) REFERENCES recipes(id) ON DELETE CASCADE;
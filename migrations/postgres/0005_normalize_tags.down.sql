@@ -0,0 +1,15 @@
+ALTER TABLE recipe_tags RENAME TO recipe_tags_new;
+
+CREATE TABLE recipe_tags (
+  recipe_id TEXT NOT NULL,
+  tag TEXT NOT NULL,
+  CONSTRAINT recipe_tags_recipe_id_fkey
+    FOREIGN KEY (recipe_id) REFERENCES recipes(id) ON DELETE CASCADE
+);
+
+INSERT INTO recipe_tags (recipe_id, tag)
+SELECT recipe_tags_new.recipe_id, tags.display_name FROM recipe_tags_new
+JOIN tags ON tags.id = recipe_tags_new.tag_id;
+
+DROP TABLE recipe_tags_new;
+DROP TABLE tags;
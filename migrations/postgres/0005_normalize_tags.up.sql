@@ -0,0 +1,29 @@
+-- Each tag is stored once, under its canonical (trimmed, lower-case)
+-- name, keeping the least of its spellings for display. Recipes refer to
+-- tags by id, at most once each.
+CREATE TABLE tags (
+  id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  display_name TEXT NOT NULL
+);
+
+INSERT INTO tags (name, display_name)
+SELECT LOWER(TRIM(tag)), MIN(TRIM(tag)) FROM recipe_tags
+WHERE TRIM(tag) <> ''
+GROUP BY LOWER(TRIM(tag));
+
+ALTER TABLE recipe_tags RENAME TO recipe_tags_old;
+
+CREATE TABLE recipe_tags (
+  recipe_id TEXT NOT NULL REFERENCES recipes(id) ON DELETE CASCADE,
+  tag_id BIGINT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+  PRIMARY KEY (recipe_id, tag_id)
+);
+
+INSERT INTO recipe_tags (recipe_id, tag_id)
+SELECT DISTINCT recipe_tags_old.recipe_id, tags.id FROM recipe_tags_old
+JOIN tags ON tags.name = LOWER(TRIM(recipe_tags_old.tag));
+
+DROP TABLE recipe_tags_old;
+
+CREATE INDEX recipe_tags_tag_id ON recipe_tags (tag_id);
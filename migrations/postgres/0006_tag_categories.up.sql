@@ -0,0 +1,52 @@
+-- Categories group tags stating one kind of fact about a recipe. A
+-- single-valued category allows a recipe at most one of its tags.
+CREATE TABLE tag_categories (
+  name TEXT PRIMARY KEY NOT NULL,
+  multi_valued BOOLEAN NOT NULL,
+  description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE categorized_tags (
+  tag_id BIGINT PRIMARY KEY NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+  category TEXT NOT NULL REFERENCES tag_categories(name) ON DELETE CASCADE
+);
+
+CREATE INDEX categorized_tags_category ON categorized_tags (category);
+
+INSERT INTO tag_categories (name, multi_valued, description) VALUES
+  ('difficulty', FALSE, 'How hard the recipe is to make'),
+  ('cost', FALSE, 'How much the ingredients cost'),
+  ('cuisine', TRUE, 'Culinary tradition'),
+  ('course', TRUE, 'Meal or course the dish is served as'),
+  ('diet', TRUE, 'Dietary character');
+
+-- Classify the tags in use so far, adding any not yet seen.
+INSERT INTO tags (name, display_name) VALUES
+  ('easy-to-make', 'easy-to-make'),
+  ('moderate-difficulty', 'moderate-difficulty'),
+  ('hard-to-make', 'hard-to-make'),
+  ('cheap', 'cheap'),
+  ('moderate', 'moderate'),
+  ('expensive', 'expensive'),
+  ('breakfast', 'breakfast'),
+  ('dessert', 'dessert'),
+  ('pasta', 'pasta'),
+  ('salad', 'salad'),
+  ('soup', 'soup'),
+  ('meat', 'meat'),
+  ('seafood', 'seafood'),
+  ('vegan', 'vegan')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO categorized_tags (tag_id, category)
+SELECT id, 'difficulty' FROM tags
+WHERE name IN ('easy-to-make', 'moderate-difficulty', 'hard-to-make');
+
+INSERT INTO categorized_tags (tag_id, category)
+SELECT id, 'cost' FROM tags WHERE name IN ('cheap', 'moderate', 'expensive');
+
+INSERT INTO categorized_tags (tag_id, category)
+SELECT id, 'course' FROM tags WHERE name IN ('breakfast', 'dessert', 'pasta', 'salad', 'soup');
+
+INSERT INTO categorized_tags (tag_id, category)
+SELECT id, 'diet' FROM tags WHERE name IN ('meat', 'seafood', 'vegan');
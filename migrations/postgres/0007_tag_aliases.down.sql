@@ -0,0 +1,3 @@
+-- Tags merged through aliases stay merged.
+DROP VIEW tag_names;
+DROP TABLE tag_aliases;
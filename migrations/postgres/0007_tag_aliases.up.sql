@@ -0,0 +1,36 @@
+-- An alias is a further name for a tag, resolving to it wherever a tag is
+-- given. No alias has the name of a tag.
+CREATE TABLE tag_aliases (
+  alias TEXT PRIMARY KEY NOT NULL,
+  tag_id BIGINT NOT NULL REFERENCES tags(id) ON DELETE CASCADE
+);
+
+CREATE INDEX tag_aliases_tag_id ON tag_aliases (tag_id);
+
+-- Every name a tag is known by.
+CREATE VIEW tag_names (name, tag_id) AS
+SELECT name, id FROM tags
+UNION ALL
+SELECT alias, tag_id FROM tag_aliases;
+
+-- Fold "veggie" and "meatless" into "vegetarian".
+INSERT INTO tags (name, display_name) VALUES ('vegetarian', 'vegetarian')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO recipe_tags (recipe_id, tag_id)
+SELECT recipe_id, (SELECT id FROM tags WHERE name = 'vegetarian')
+FROM recipe_tags JOIN tags ON tags.id = recipe_tags.tag_id
+WHERE tags.name IN ('veggie', 'meatless')
+ON CONFLICT DO NOTHING;
+
+DELETE FROM tags WHERE name IN ('veggie', 'meatless');
+
+INSERT INTO tag_aliases (alias, tag_id)
+SELECT alias, tags.id
+FROM (SELECT 'veggie' AS alias UNION ALL SELECT 'meatless') AS aliases, tags
+WHERE tags.name = 'vegetarian';
+
+INSERT INTO categorized_tags (tag_id, category)
+SELECT tags.id, tag_categories.name FROM tags, tag_categories
+WHERE tags.name = 'vegetarian' AND tag_categories.name = 'diet'
+ON CONFLICT (tag_id) DO NOTHING;
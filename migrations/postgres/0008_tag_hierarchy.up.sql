@@ -0,0 +1,27 @@
+-- Tags form a directed acyclic graph: a tag may sit under several parents,
+-- and selecting a tag selects the recipes carrying any tag beneath it.
+CREATE TABLE tag_parents (
+  tag_id BIGINT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+  parent_id BIGINT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+  PRIMARY KEY (tag_id, parent_id),
+  CHECK (tag_id <> parent_id)
+);
+
+CREATE INDEX tag_parents_parent_id ON tag_parents (parent_id);
+
+-- Place "pasta" under "italian" and "main-course".
+INSERT INTO tags (name, display_name) VALUES
+  ('italian', 'italian'),
+  ('main-course', 'main-course'),
+  ('pasta', 'pasta')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO tag_parents (tag_id, parent_id)
+SELECT child.id, parent.id FROM tags AS child, tags AS parent
+WHERE child.name = 'pasta' AND parent.name IN ('italian', 'main-course');
+
+INSERT INTO categorized_tags (tag_id, category)
+SELECT tags.id, tag_categories.name FROM tags, tag_categories
+WHERE (tags.name = 'italian' AND tag_categories.name = 'cuisine')
+   OR (tags.name = 'main-course' AND tag_categories.name = 'course')
+ON CONFLICT (tag_id) DO NOTHING;
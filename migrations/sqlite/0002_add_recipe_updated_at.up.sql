@@ -0,0 +1,2 @@
+ALTER TABLE recipes ADD COLUMN updated_at TEXT NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE recipes SET updated_at = CURRENT_TIMESTAMP;
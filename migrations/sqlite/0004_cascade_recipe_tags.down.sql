@@ -0,0 +1,10 @@
+CREATE TABLE recipe_tags_old (
+  recipe_id TEXT NOT NULL,
+  tag TEXT NOT NULL,
+  FOREIGN KEY (recipe_id) REFERENCES recipes(id)
+);
+
+INSERT INTO recipe_tags_old (recipe_id, tag) SELECT recipe_id, tag FROM recipe_tags;
+
+DROP TABLE recipe_tags;
+ALTER TABLE recipe_tags_old RENAME TO recipe_tags;
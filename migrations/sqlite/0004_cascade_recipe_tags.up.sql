@@ -0,0 +1,15 @@
+-- SQLite cannot alter a constraint, so the table is rebuilt. Tags left
+-- behind by recipes deleted while foreign keys were not enforced are
+-- dropped on the way.
+CREATE TABLE recipe_tags_new (
+  recipe_id TEXT NOT NULL,
+  tag TEXT NOT NULL,
+  FOREIGN KEY (recipe_id) REFERENCES recipes(id) ON DELETE CASCADE
+);
+
+INSERT INTO recipe_tags_new (recipe_id, tag)
+SELECT recipe_id, tag FROM recipe_tags
+WHERE recipe_id IN (SELECT id FROM recipes);
+
+DROP TABLE recipe_tags;
+ALTER TABLE recipe_tags_new RENAME TO recipe_tags;
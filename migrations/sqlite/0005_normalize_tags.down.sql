@@ -0,0 +1,13 @@
+CREATE TABLE recipe_tags_old (
+  recipe_id TEXT NOT NULL,
+  tag TEXT NOT NULL,
+  FOREIGN KEY (recipe_id) REFERENCES recipes(id) ON DELETE CASCADE
+);
+
+INSERT INTO recipe_tags_old (recipe_id, tag)
+SELECT recipe_tags.recipe_id, tags.display_name FROM recipe_tags
+JOIN tags ON tags.id = recipe_tags.tag_id;
+
+DROP TABLE recipe_tags;
+ALTER TABLE recipe_tags_old RENAME TO recipe_tags;
+DROP TABLE tags;
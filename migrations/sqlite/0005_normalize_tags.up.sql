@@ -0,0 +1,30 @@
+-- Each tag is stored once, under its canonical (trimmed, lower-case)
+-- name, keeping the least of its spellings for display. Recipes refer to
+-- tags by id, at most once each.
+CREATE TABLE tags (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  display_name TEXT NOT NULL
+);
+
+INSERT INTO tags (name, display_name)
+SELECT LOWER(TRIM(tag)), MIN(TRIM(tag)) FROM recipe_tags
+WHERE TRIM(tag) <> ''
+GROUP BY LOWER(TRIM(tag));
+
+CREATE TABLE recipe_tags_new (
+  recipe_id TEXT NOT NULL,
+  tag_id INTEGER NOT NULL,
+  PRIMARY KEY (recipe_id, tag_id),
+  FOREIGN KEY (recipe_id) REFERENCES recipes(id) ON DELETE CASCADE,
+  FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+);
+
+INSERT INTO recipe_tags_new (recipe_id, tag_id)
+SELECT DISTINCT recipe_tags.recipe_id, tags.id FROM recipe_tags
+JOIN tags ON tags.name = LOWER(TRIM(recipe_tags.tag));
+
+DROP TABLE recipe_tags;
+ALTER TABLE recipe_tags_new RENAME TO recipe_tags;
+
+CREATE INDEX recipe_tags_tag_id ON recipe_tags (tag_id);
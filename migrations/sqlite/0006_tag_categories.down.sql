@@ -0,0 +1,2 @@
+DROP TABLE categorized_tags;
+DROP TABLE tag_categories;
@@ -6,9 +6,16 @@ use axum::{
     http,
     response::{self, IntoResponse},
 };
+use futures_util::StreamExt;
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::sync::RwLock;
-use utoipa::OpenApi;
+use tracing::Instrument;
+use utoipa::{IntoParams, OpenApi};
+
+#[cfg(test)]
+mod tests;
 
 /// OpenAPI documentation definition for the Recipe Server API.
 #[derive(OpenApi)]
@@ -27,66 +34,138 @@ pub fn router() -> OpenApiRouter<Arc<RwLock<AppState>>> {
         .routes(routes!(get_random_recipe))
         .routes(routes!(register))
         .routes(routes!(add_recipe))
+        .routes(routes!(update_recipe, delete_recipe))
+        .routes(routes!(import_recipes))
+        .routes(routes!(export_recipes))
+        .routes(routes!(search_recipes))
+        .routes(routes!(match_pantry))
+        .routes(routes!(get_cache_stats))
+        .routes(routes!(create_backup))
+        .routes(routes!(get_tag_categories))
+        .routes(routes!(put_tag_category, delete_tag_category))
+        .routes(routes!(set_tag_category))
+        .routes(routes!(get_tags))
+        .routes(routes!(get_tag_tree))
+        .routes(routes!(get_tag_facets))
+        .routes(routes!(suggest_tags))
+        .routes(routes!(get_tag_aliases))
+        .routes(routes!(put_tag_alias, delete_tag_alias))
+        .routes(routes!(merge_tags))
+        .routes(routes!(put_tag_parent, delete_tag_parent))
+}
+
+/// Load a recipe by its ID, consulting the cache before the database.
+async fn fetch_recipe(app_state: &AppState, recipe_id: &str) -> Result<JsonRecipe, sqlx::Error> {
+    if let Some(recipe) = app_state.cache.get(recipe_id) {
+        return Ok(recipe);
+    }
+    let generation = app_state.cache.generation();
+    let (recipe, tags) = app_state.store.get(recipe_id).await?;
+    let recipe = JsonRecipe::new(recipe, tags);
+    app_state.cache.insert(recipe.clone(), generation);
+    Ok(recipe)
 }
 
-/// Fetch a recipe by its ID from the database.
+/// Fetch a recipe by its ID and render it with cache validators.
 async fn get_recipe_by_id(
-    db: &SqlitePool,
+    app_state: &AppState,
     recipe_id: &str,
 ) -> Result<response::Response, http::StatusCode> {
-    let recipe_result = recipe::get(db, recipe_id).await;
-    match recipe_result {
-        Ok((recipe, tags)) => Ok(JsonRecipe::new(recipe, tags).into_response()),
+    match fetch_recipe(app_state, recipe_id).await {
+        Ok(recipe) => {
+            let validators = Validators::new(&recipe, "json");
+            Ok(validators.apply(recipe.into_response()))
+        }
         Err(e) => {
-            log::warn!("Recipe fetch failed: {}", e);
+            tracing::warn!("Recipe fetch failed: {}", e);
             Err(http::StatusCode::NOT_FOUND)
         }
     }
 }
 
+/// Returns `true` if a write's `If-Match` allows it over the stored
+/// `current` recipe.
+fn if_match_passes(headers: &http::HeaderMap, current: &JsonRecipe) -> bool {
+    Validators::new(current, "json").write_allowed(headers)
+}
+
 /// Utoipa-documented handler for fetching a recipe by its ID.
+///
+/// Honors `If-None-Match` and `If-Modified-Since`, answering
+/// `304 Not Modified` when the client's copy is current.
 #[utoipa::path(
     get,
     path = "/recipe/{recipe_id}",
     responses(
         (status = 200, description = "Get a recipe by id", body = [JsonRecipe]),
+        (status = 304, description = "Recipe not modified"),
         (status = 404, description = "No matching recipe"),
     )
 )]
 pub async fn get_recipe(
     State(app_state): State<Arc<RwLock<AppState>>>,
     Path(recipe_id): Path<String>,
+    headers: http::HeaderMap,
 ) -> Result<response::Response, http::StatusCode> {
     let app_reader = app_state.read().await;
-    let db = &app_reader.db;
-    get_recipe_by_id(db, &recipe_id).await
+    let recipe = fetch_recipe(&app_reader, &recipe_id).await.map_err(|e| {
+        tracing::warn!("Recipe fetch failed: {}", e);
+        http::StatusCode::NOT_FOUND
+    })?;
+    let validators = Validators::new(&recipe, "json");
+    if validators.not_modified(&headers) {
+        return Ok(validators.not_modified_response());
+    }
+    Ok(validators.apply(recipe.into_response()))
+}
+
+/// Body of a tagged lookup that found nothing.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NoTaggedRecipe {
+    status: u16,
+    error: String,
+    /// Canonical names of tags that might have been meant.
+    did_you_mean: Vec<String>,
 }
 
 /// Utoipa-documented handler for fetching a recipe by tags.
+///
+/// When no recipe carries any of the tags, the `404` body suggests tags
+/// that might have been meant.
 #[utoipa::path(
     get,
     path = "/tagged-recipe",
     responses(
         (status = 200, description = "Get a recipe by tags", body = [JsonRecipe]),
-        (status = 404, description = "No matching recipes"),
+        (status = 404, description = "No matching recipes", body = NoTaggedRecipe),
     )
 )]
 pub async fn get_tagged_recipe(
     State(app_state): State<Arc<RwLock<AppState>>>,
     Json(tags): Json<Vec<String>>,
 ) -> Result<response::Response, http::StatusCode> {
-    log::info!("Get tagged recipe: {:?}", tags);
+    tracing::info!("Get tagged recipe: {:?}", tags);
     let app_reader = app_state.read().await;
-    let db = &app_reader.db;
-    let recipe_result = recipe::get_tagged(db, tags.iter().map(String::as_ref)).await;
+    let recipe_result = app_reader.store.get_tagged(&tags).await;
     match recipe_result {
-        Ok(Some(recipe_id)) => get_recipe_by_id(db, &recipe_id).await,
+        Ok(Some(recipe_id)) => get_recipe_by_id(&app_reader, &recipe_id).await,
         Ok(None) => {
-            log::warn!("Recipe tag fetch failed tagging");
-            Err(http::StatusCode::NOT_FOUND)
+            tracing::warn!("Recipe tag fetch failed tagging");
+            let suggestions = tags::did_you_mean(app_reader.store.as_ref(), &tags)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Tag suggestion failed: {}", e);
+                    Vec::new()
+                });
+            let body = NoTaggedRecipe {
+                status: StatusCode::NOT_FOUND.as_u16(),
+                error: "No matching recipes".to_string(),
+                did_you_mean: suggestions.into_iter().map(|tag| tag.name).collect(),
+            };
+            Ok((StatusCode::NOT_FOUND, Json(body)).into_response())
         }
         Err(e) => {
-            log::warn!("Recipe tag fetch failed: {}", e);
+            tracing::warn!("Recipe tag fetch failed: {}", e);
             Err(http::StatusCode::NOT_FOUND)
         }
     }
@@ -105,12 +184,11 @@ pub async fn get_random_recipe(
     State(app_state): State<Arc<RwLock<AppState>>>,
 ) -> Result<response::Response, http::StatusCode> {
     let app_reader = app_state.read().await;
-    let db = &app_reader.db;
-    let recipe_result = recipe::get_random(db).await;
+    let recipe_result = app_reader.store.get_random().await;
     match recipe_result {
-        Ok(recipe_id) => get_recipe_by_id(db, &recipe_id).await,
+        Ok(recipe_id) => get_recipe_by_id(&app_reader, &recipe_id).await,
         Err(e) => {
-            log::warn!("Get random recipe failed: {}", e);
+            tracing::warn!("Get random recipe failed: {}", e);
             Err(http::StatusCode::NOT_FOUND)
         }
     }
@@ -140,6 +218,25 @@ pub async fn register(
     }
 }
 
+/// Check a recipe's tags against the tag categories, so it carries at most
+/// one tag from each single-valued category.
+async fn check_tag_categories(
+    store: &dyn store::Store,
+    recipe: &JsonRecipe,
+) -> Result<(), axum::response::Response> {
+    let tags: Vec<String> = recipe
+        .sorted_tags()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let categorized = store
+        .categorized(&tags)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    tags::check_categories(&categorized)
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg).into_response())
+}
+
 /// Utoipa-documented handler for adding a new recipe to the database.
 #[utoipa::path(
     post,
@@ -152,16 +249,1019 @@ pub async fn register(
         (status = 201, description = "Added recipe", body = ()),
         (status = 400, description = "Bad request", body = String),
         (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an editor", body = authjwt::AuthError),
+        (status = 409, description = "A recipe with this ID already exists", body = String),
     )
 )]
 pub async fn add_recipe(
-    _claims: authjwt::Claims,
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Json(recipe): Json<JsonRecipe>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Editor) {
+        return e.into_response();
+    }
+    let appstate = appstate.read().await;
+    if let Err(response) = check_tag_categories(appstate.store.as_ref(), &recipe).await {
+        return response;
+    }
+    let recipe_id = recipe.id().to_string();
+    match appstate.store.add(recipe).await {
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|e| e.kind() == sqlx::error::ErrorKind::UniqueViolation) =>
+        {
+            (StatusCode::CONFLICT, e.to_string()).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Ok(()) => {
+            appstate.cache.invalidate(&recipe_id);
+            StatusCode::CREATED.into_response()
+        }
+    }
+}
+
+/// Utoipa-documented handler for taking an online database backup.
+///
+/// The backup is written to the configured backup directory, and old
+/// backups there are rotated out. Requires an admin API key.
+#[utoipa::path(
+    post,
+    path = "/admin/backup",
+    responses(
+        (status = 201, description = "Backup written", body = backup::Backup),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+        (status = 500, description = "Backup failed", body = String),
+    )
+)]
+pub async fn create_backup(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let (store, config) = {
+        let appstate = appstate.read().await;
+        (appstate.store.clone(), appstate.config.clone())
+    };
+    match backup::rotate(store.as_ref(), &config.backup).await {
+        Ok(backup) => (StatusCode::CREATED, Json(backup)).into_response(),
+        Err(e) => {
+            tracing::error!("Backup failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Utoipa-documented handler for reporting recipe cache counters.
+#[utoipa::path(
+    get,
+    path = "/cache-stats",
+    responses(
+        (status = 200, description = "Recipe cache counters", body = cache::CacheStats),
+    )
+)]
+pub async fn get_cache_stats(State(appstate): State<SharedAppState>) -> axum::response::Response {
+    let appstate = appstate.read().await;
+    Json(appstate.cache.stats()).into_response()
+}
+
+/// Utoipa-documented handler for replacing an existing recipe.
+///
+/// Honors `If-Match`, so concurrent editors get `412 Precondition Failed`
+/// instead of overwriting each other's changes.
+#[utoipa::path(
+    put,
+    path = "/recipe/{recipe_id}",
+    request_body(
+        content = inline(JsonRecipe),
+        description = "Replacement recipe"
+    ),
+    responses(
+        (status = 200, description = "Updated recipe", body = JsonRecipe),
+        (status = 400, description = "Bad request", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an editor", body = authjwt::AuthError),
+        (status = 404, description = "No matching recipe"),
+        (status = 412, description = "Recipe changed since it was fetched"),
+    )
+)]
+pub async fn update_recipe(
+    claims: authjwt::Claims,
     State(appstate): State<SharedAppState>,
+    Path(recipe_id): Path<String>,
+    headers: http::HeaderMap,
     Json(recipe): Json<JsonRecipe>,
 ) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Editor) {
+        return e.into_response();
+    }
+    if recipe.id() != recipe_id {
+        let msg = "Recipe id does not match path".to_string();
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
     let appstate = appstate.read().await;
-    match recipe::add(&appstate.db, recipe).await {
+    if let Err(response) = check_tag_categories(appstate.store.as_ref(), &recipe).await {
+        return response;
+    }
+    // Checked by the store within the write, so that writes made in the
+    // meantime are not lost. Writes without `If-Match` are unconditional.
+    let if_match = |current: &JsonRecipe| if_match_passes(&headers, current);
+    let precondition = headers
+        .contains_key(http::header::IF_MATCH)
+        .then_some(&if_match as _);
+    match appstate.store.update(recipe, precondition).await {
         Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
-        Ok(()) => StatusCode::CREATED.into_response(),
+        Ok(store::Written::NotFound) => StatusCode::NOT_FOUND.into_response(),
+        Ok(store::Written::PreconditionFailed) => StatusCode::PRECONDITION_FAILED.into_response(),
+        Ok(store::Written::Done) => {
+            appstate.cache.invalidate(&recipe_id);
+            get_recipe_by_id(&appstate, &recipe_id)
+                .await
+                .unwrap_or_else(IntoResponse::into_response)
+        }
+    }
+}
+
+/// Utoipa-documented handler for deleting a recipe.
+///
+/// Honors `If-Match` in the same way as `update_recipe`.
+#[utoipa::path(
+    delete,
+    path = "/recipe/{recipe_id}",
+    responses(
+        (status = 204, description = "Deleted recipe"),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an editor", body = authjwt::AuthError),
+        (status = 404, description = "No matching recipe"),
+        (status = 412, description = "Recipe changed since it was fetched"),
+    )
+)]
+pub async fn delete_recipe(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path(recipe_id): Path<String>,
+    headers: http::HeaderMap,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Editor) {
+        return e.into_response();
+    }
+    let appstate = appstate.read().await;
+    let if_match = |current: &JsonRecipe| if_match_passes(&headers, current);
+    let precondition = headers
+        .contains_key(http::header::IF_MATCH)
+        .then_some(&if_match as _);
+    match appstate.store.delete(&recipe_id, precondition).await {
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Ok(store::Written::NotFound) => StatusCode::NOT_FOUND.into_response(),
+        Ok(store::Written::PreconditionFailed) => StatusCode::PRECONDITION_FAILED.into_response(),
+        Ok(store::Written::Done) => {
+            appstate.cache.invalidate(&recipe_id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+/// Query parameters for a bulk import.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    /// How to treat recipes whose id already exists.
+    #[serde(default)]
+    mode: import::ImportMode,
+    /// Report what would happen without committing anything.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Utoipa-documented handler for importing many recipes at once.
+///
+/// Accepts either a JSON array of recipes or newline-delimited JSON
+/// (`application/x-ndjson`), parsed as it streams in; a recipe larger
+/// than `import::MAX_RECIPE_BYTES` is reported as failed. Recipes are
+/// committed in batches of `import::BATCH_RECIPES`, so that no
+/// transaction is held open while waiting on the client. A body declared
+/// larger than `import::MAX_IMPORT_BYTES` is refused, and one that turns
+/// out larger stops the import. The response is NDJSON: one
+/// `ImportResult` per input recipe once its batch is committed, followed
+/// by a `{"summary": ...}` line, or an `{"error": ...}` line if a batch
+/// failed and was rolled back. Batches committed before it remain.
+#[utoipa::path(
+    post,
+    path = "/recipes/import",
+    params(ImportParams),
+    request_body(
+        description = "Recipes to import",
+        content(
+            (Vec<JsonRecipe> = "application/json"),
+            (JsonRecipe = "application/x-ndjson"),
+        ),
+    ),
+    responses(
+        (status = 200, description = "Per-recipe import report", body = import::ImportResult,
+         content_type = "application/x-ndjson"),
+        (status = 400, description = "Bad request", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an editor, or not an admin for `replace`",
+         body = authjwt::AuthError),
+        (status = 413, description = "Import too large", body = String),
+    )
+)]
+pub async fn import_recipes(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Query(params): Query<ImportParams>,
+    headers: http::HeaderMap,
+    body: axum::body::Body,
+) -> axum::response::Response {
+    // Replacing wipes the catalogue first, so only admins may.
+    let role = match params.mode {
+        import::ImportMode::Replace => users::Role::Admin,
+        _ => users::Role::Editor,
+    };
+    if let Err(e) = claims.require(role) {
+        return e.into_response();
+    }
+    let (store, cache, shutdown) = {
+        let appstate = appstate.read().await;
+        (
+            appstate.store.clone(),
+            appstate.cache.clone(),
+            appstate.shutdown.clone(),
+        )
+    };
+
+    let ndjson = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.starts_with("application/x-ndjson")
+                || content_type.starts_with("application/ndjson")
+        });
+    let too_large = (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("Imports are limited to {} bytes", import::MAX_IMPORT_BYTES),
+    );
+    let declared = headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > import::MAX_IMPORT_BYTES) {
+        return too_large.into_response();
+    }
+    // Counts the body as it arrives, failing the read past the limit.
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let mut received = 0;
+    let flag = exceeded.clone();
+    let stream = body.into_data_stream().map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        received += chunk.len();
+        if received > import::MAX_IMPORT_BYTES {
+            flag.store(true, Ordering::Relaxed);
+            return Err(std::io::Error::other("Import is too large"));
+        }
+        Ok(chunk)
+    });
+    let reader = tokio_util::io::StreamReader::new(stream);
+    let items = if ndjson {
+        import::ndjson_items(reader)
+    } else {
+        match import::json_array_items(reader).await {
+            Ok(items) => items,
+            Err(_) if exceeded.load(Ordering::Relaxed) => return too_large.into_response(),
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        }
+    };
+    // Each batch is read in full before its transaction begins, so that a
+    // slow client cannot hold the database's write lock.
+    let mut batches = items.chunks(import::BATCH_RECIPES);
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<String>(32);
+    let import_task = async move {
+        let report = async {
+            let mut import = import::Import::new(store.as_ref(), params.mode, params.dry_run);
+            let mut index = 0;
+            while let Some(batch) = batches.next().await {
+                if exceeded.load(Ordering::Relaxed) {
+                    return Err(too_large.1.into());
+                }
+                let mut results = Vec::with_capacity(batch.len());
+                for item in batch {
+                    results.push(import.recipe(index, item).await?);
+                    index += 1;
+                }
+                import.commit(Some(&cache)).await?;
+                for result in results {
+                    result.log();
+                    if tx.send(import::ndjson_line(&result)).await.is_err() {
+                        tracing::warn!("Import client disconnected; stopping");
+                        return Ok(None);
+                    }
+                }
+            }
+            let summary = import.finish(Some(&cache)).await?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Some(summary))
+        };
+        let line = match report.await {
+            Ok(Some(summary)) => import::ndjson_line(&serde_json::json!({ "summary": summary })),
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!("Import failed: {}", e);
+                let error = serde_json::json!({ "error": e.to_string() });
+                import::ndjson_line(&error)
+            }
+        };
+        let _ = tx.send(line).await;
+    };
+    shutdown.spawn(import_task.instrument(tracing::Span::current()));
+
+    let lines = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok::<_, Infallible>(line), rx))
+    });
+    (
+        [(http::header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+/// Largest number of recipes a search returns.
+const SEARCH_LIMIT: i64 = 100;
+
+/// Query parameters for a recipe search.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    /// Text to find in recipe names, ingredients or tags, ignoring case.
+    q: String,
+    /// Most recipes to return, at most 100.
+    #[serde(default = "default_search_limit")]
+    limit: i64,
+}
+
+fn default_search_limit() -> i64 {
+    20
+}
+
+/// Utoipa-documented handler for searching recipes by text.
+#[utoipa::path(
+    get,
+    path = "/recipes/search",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Matching recipes in id order", body = Vec<JsonRecipe>),
+        (status = 400, description = "Empty search text"),
+    )
+)]
+pub async fn search_recipes(
+    State(appstate): State<SharedAppState>,
+    Query(params): Query<SearchParams>,
+) -> axum::response::Response {
+    let text = params.q.trim();
+    if text.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty search text").into_response();
+    }
+    let limit = params.limit.clamp(0, SEARCH_LIMIT);
+    let store = appstate.read().await.store.clone();
+    match store.search(text, limit).await {
+        Ok(recipes) => Json(recipes).into_response(),
+        Err(e) => {
+            tracing::warn!("Recipe search failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Largest number of recipes a pantry match returns.
+const PANTRY_LIMIT: usize = 100;
+
+/// Ingredients on hand, to match recipes against.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Pantry {
+    /// Ingredients on hand, in any spelling.
+    #[schema(example = json!(["spaghetti", "eggs", "parmesan", "bacon"]))]
+    ingredients: Vec<String>,
+    /// Least fraction of a recipe's ingredients that must be on hand,
+    /// from 0 to 1.
+    #[serde(default = "default_pantry_threshold")]
+    #[schema(example = 0.5)]
+    threshold: f64,
+    /// Most recipes to return, at most 100.
+    #[serde(default = "default_pantry_limit")]
+    limit: usize,
+}
+
+fn default_pantry_threshold() -> f64 {
+    0.5
+}
+
+fn default_pantry_limit() -> usize {
+    20
+}
+
+/// Utoipa-documented handler for finding recipes to cook from a pantry.
+///
+/// Recipes using the most of the ingredients on hand come first, each
+/// with the ingredients it still needs. Recipes using none are left out.
+#[utoipa::path(
+    post,
+    path = "/recipes/match-pantry",
+    request_body(content = Pantry, description = "Ingredients on hand"),
+    responses(
+        (status = 200, description = "Matching recipes, best first", body = Vec<pantry::PantryMatch>),
+        (status = 400, description = "No ingredients, or a threshold outside 0 to 1", body = String),
+    )
+)]
+pub async fn match_pantry(
+    State(appstate): State<SharedAppState>,
+    Json(pantry): Json<Pantry>,
+) -> axum::response::Response {
+    if pantry.ingredients.iter().all(|i| pantry::key(i).is_empty()) {
+        return (StatusCode::BAD_REQUEST, "No ingredients given").into_response();
+    }
+    if !(0.0..=1.0).contains(&pantry.threshold) {
+        return (StatusCode::BAD_REQUEST, "Threshold must be from 0 to 1").into_response();
+    }
+    let limit = pantry.limit.min(PANTRY_LIMIT);
+    let store = appstate.read().await.store.clone();
+    match pantry::match_pantry(store.as_ref(), &pantry.ingredients, pantry.threshold, limit).await {
+        Ok(matches) => Json(matches).into_response(),
+        Err(e) => {
+            tracing::warn!("Pantry match failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Query parameters for a catalogue export.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// Output encoding.
+    #[serde(default)]
+    format: export::ExportFormat,
+    /// Comma-separated tags; only recipes carrying at least one are exported.
+    tags: Option<String>,
+    /// Only export recipes modified at or after this RFC 3339 time.
+    #[param(value_type = Option<String>, format = DateTime)]
+    since: Option<DateTime<Utc>>,
+}
+
+/// Utoipa-documented handler for exporting the recipe catalogue.
+///
+/// The export is streamed as it is read from the database. The `json`
+/// format can be imported again unchanged.
+#[utoipa::path(
+    get,
+    path = "/recipes/export",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "Exported recipes", content(
+            (Vec<JsonRecipe> = "application/json"),
+            (JsonRecipe = "application/x-ndjson"),
+            ("text/csv"),
+            ("application/yaml"),
+        )),
+    )
+)]
+pub async fn export_recipes(
+    State(appstate): State<SharedAppState>,
+    Query(params): Query<ExportParams>,
+) -> axum::response::Response {
+    let (store, shutdown) = {
+        let appstate = appstate.read().await;
+        (appstate.store.clone(), appstate.shutdown.clone())
+    };
+    let tags: Vec<String> = params
+        .tags
+        .iter()
+        .flat_map(|tags| tags.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect();
+    let filter = RecipeFilter::new(&tags, params.since);
+    let format = params.format;
+
+    let body = piped_body(&shutdown, move |mut writer| async move {
+        export::export(store.as_ref(), &filter, format, &mut writer)
+            .await
+            .map(|_| ())
+    });
+
+    let disposition = format!("attachment; filename=\"recipes.{}\"", format.extension());
+    (
+        [
+            (
+                http::header::CONTENT_TYPE,
+                format.content_type().to_string(),
+            ),
+            (http::header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// A body carrying what `write` writes, which runs as a shutdown task.
+///
+/// If `write` fails, or is cancelled at shutdown, the body ends in an
+/// error rather than cleanly, so the connection is aborted and the client
+/// can tell that what it received is truncated.
+fn piped_body<F, Fut>(shutdown: &shutdown::Shutdown, write: F) -> axum::body::Body
+where
+    F: FnOnce(tokio::io::DuplexStream) -> Fut,
+    Fut: Future<Output = Result<(), export::ExportError>> + Send + 'static,
+{
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    let (done_tx, done) = tokio::sync::oneshot::channel();
+    let written = write(writer);
+    let task = async move {
+        let result = written.await;
+        if let Err(e) = &result {
+            tracing::warn!("Export failed: {}", e);
+        }
+        let _ = done_tx.send(result.map_err(|e| e.to_string()));
+    };
+    shutdown.spawn(task.instrument(tracing::Span::current()));
+
+    // Read once the writer is gone, so after everything it wrote.
+    let failure = futures_util::stream::once(done).filter_map(|done| async move {
+        let message = match done {
+            Ok(Ok(())) => return None,
+            Ok(Err(message)) => message,
+            Err(_) => "cancelled at shutdown".to_string(),
+        };
+        Some(Err(std::io::Error::other(message)))
+    });
+    axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(reader).chain(failure))
+}
+
+/// Utoipa-documented handler for listing the tag categories.
+#[utoipa::path(
+    get,
+    path = "/tag-categories",
+    responses(
+        (status = 200, description = "Tag categories in name order", body = Vec<tags::Category>),
+    )
+)]
+pub async fn get_tag_categories(
+    State(appstate): State<SharedAppState>,
+) -> axum::response::Response {
+    let store = appstate.read().await.store.clone();
+    match store.categories().await {
+        Ok(categories) => Json(categories).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Definition of a tag category.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CategorySpec {
+    /// Whether a recipe may carry more than one tag of the category.
+    multi_valued: bool,
+    #[serde(default)]
+    description: String,
+}
+
+/// Utoipa-documented handler for creating or redefining a tag category.
+///
+/// Requires an admin API key.
+#[utoipa::path(
+    put,
+    path = "/admin/tag-categories/{name}",
+    request_body(content = CategorySpec, description = "Category definition"),
+    responses(
+        (status = 204, description = "Category stored"),
+        (status = 400, description = "Bad category name", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+    )
+)]
+pub async fn put_tag_category(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path(name): Path<String>,
+    Json(spec): Json<CategorySpec>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let name = tags::canonical(&name);
+    if name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty category name").into_response();
+    }
+    let store = appstate.read().await.store.clone();
+    match store
+        .put_category(&name, spec.multi_valued, spec.description.trim())
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for deleting a tag category.
+///
+/// Its tags are kept but no longer belong to any category. Requires an
+/// admin API key.
+#[utoipa::path(
+    delete,
+    path = "/admin/tag-categories/{name}",
+    responses(
+        (status = 204, description = "Category deleted"),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+        (status = 404, description = "No such category"),
+    )
+)]
+pub async fn delete_tag_category(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path(name): Path<String>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let store = appstate.read().await.store.clone();
+    match store.delete_category(&tags::canonical(&name)).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Category to place a tag in.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TagCategory {
+    /// Category name, or `null` to remove the tag from its category.
+    category: Option<String>,
+}
+
+/// Utoipa-documented handler for placing a tag in a category.
+///
+/// Requires an admin API key.
+#[utoipa::path(
+    put,
+    path = "/admin/tags/{tag}/category",
+    request_body(content = TagCategory, description = "Category for the tag"),
+    responses(
+        (status = 204, description = "Tag category set"),
+        (status = 400, description = "Empty tag", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+        (status = 404, description = "No such category"),
+    )
+)]
+pub async fn set_tag_category(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path(tag): Path<String>,
+    Json(body): Json<TagCategory>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    if tags::canonical(&tag).is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty tag").into_response();
+    }
+    let category = body.category.as_deref().map(tags::canonical);
+    let store = appstate.read().await.store.clone();
+    match store.set_tag_category(&tag, category.as_deref()).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for listing the tag aliases.
+#[utoipa::path(
+    get,
+    path = "/tag-aliases",
+    responses(
+        (status = 200, description = "Tag aliases in alias order", body = Vec<tags::Alias>),
+    )
+)]
+pub async fn get_tag_aliases(State(appstate): State<SharedAppState>) -> axum::response::Response {
+    let store = appstate.read().await.store.clone();
+    match store.aliases().await {
+        Ok(aliases) => Json(aliases).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Tag an alias resolves to.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AliasTarget {
+    #[schema(example = "vegetarian")]
+    tag: String,
+}
+
+/// Utoipa-documented handler for creating or retargeting a tag alias.
+///
+/// A tag already named by the alias is merged into the target first.
+/// Requires an admin API key.
+#[utoipa::path(
+    put,
+    path = "/admin/tag-aliases/{alias}",
+    request_body(content = AliasTarget, description = "Tag for the alias"),
+    responses(
+        (status = 204, description = "Alias stored"),
+        (status = 400, description = "Empty or self-referring alias", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+    )
+)]
+pub async fn put_tag_alias(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path(alias): Path<String>,
+    Json(target): Json<AliasTarget>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let (alias, tag) = (tags::canonical(&alias), tags::canonical(&target.tag));
+    if alias.is_empty() || tag.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty alias or tag").into_response();
+    }
+    let appstate = appstate.read().await;
+    match appstate.store.put_alias(&alias, &tag).await {
+        Ok(true) => {
+            appstate.cache.clear();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::BAD_REQUEST, "A tag cannot be its own alias").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for deleting a tag alias.
+///
+/// Requires an admin API key.
+#[utoipa::path(
+    delete,
+    path = "/admin/tag-aliases/{alias}",
+    responses(
+        (status = 204, description = "Alias deleted"),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+        (status = 404, description = "No such alias"),
+    )
+)]
+pub async fn delete_tag_alias(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path(alias): Path<String>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let store = appstate.read().await.store.clone();
+    match store.delete_alias(&tags::canonical(&alias)).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Tags to merge into another.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TagMerge {
+    /// Tags to merge away.
+    #[schema(example = json!(["veggie", "meatless"]))]
+    tags: Vec<String>,
+    /// Tag to merge them into, created if new.
+    #[schema(example = "vegetarian")]
+    into: String,
+}
+
+/// Utoipa-documented handler for merging tags.
+///
+/// Recipes carrying a merged tag carry the target instead, and each merged
+/// tag's name becomes an alias of the target. Requires an admin API key.
+#[utoipa::path(
+    post,
+    path = "/admin/tags/merge",
+    request_body(content = TagMerge, description = "Tags to merge"),
+    responses(
+        (status = 204, description = "Tags merged"),
+        (status = 400, description = "Empty target tag", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+    )
+)]
+pub async fn merge_tags(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Json(merge): Json<TagMerge>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let into = tags::canonical(&merge.into);
+    if into.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty target tag").into_response();
+    }
+    let appstate = appstate.read().await;
+    let from = tags::canonical_all(merge.tags.iter().map(String::as_str));
+    match appstate.store.merge_tags(&from, &into).await {
+        Ok(merged) => {
+            tracing::info!("Merged {} tags into {:?}", merged, into);
+            appstate.cache.clear();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for placing a tag beneath a parent tag.
+///
+/// Either tag is created if new. Recipes carrying the tag are then
+/// selected by the parent too. Requires an admin API key.
+#[utoipa::path(
+    put,
+    path = "/admin/tags/{tag}/parents/{parent}",
+    responses(
+        (status = 204, description = "Tag placed beneath the parent"),
+        (status = 400, description = "Empty tag, or the parent is beneath the tag", body = String),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+    )
+)]
+pub async fn put_tag_parent(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path((tag, parent)): Path<(String, String)>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let (tag, parent) = (tags::canonical(&tag), tags::canonical(&parent));
+    if tag.is_empty() || parent.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty tag or parent").into_response();
+    }
+    let store = appstate.read().await.store.clone();
+    match store.add_tag_parent(&tag, &parent).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (
+            StatusCode::BAD_REQUEST,
+            "That would put a tag beneath itself",
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for taking a tag from beneath a parent tag.
+///
+/// Requires an admin API key.
+#[utoipa::path(
+    delete,
+    path = "/admin/tags/{tag}/parents/{parent}",
+    responses(
+        (status = 204, description = "Tag taken from beneath the parent"),
+        (status = 401, description = "Auth Error", body = authjwt::AuthError),
+        (status = 403, description = "Not an admin", body = authjwt::AuthError),
+        (status = 404, description = "The tag is not directly beneath the parent"),
+    )
+)]
+pub async fn delete_tag_parent(
+    claims: authjwt::Claims,
+    State(appstate): State<SharedAppState>,
+    Path((tag, parent)): Path<(String, String)>,
+) -> axum::response::Response {
+    if let Err(e) = claims.require(users::Role::Admin) {
+        return e.into_response();
+    }
+    let (tag, parent) = (tags::canonical(&tag), tags::canonical(&parent));
+    let store = appstate.read().await.store.clone();
+    match store.remove_tag_parent(&tag, &parent).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for listing every tag with its recipe count.
+#[utoipa::path(
+    get,
+    path = "/tags",
+    responses(
+        (status = 200, description = "Tags in canonical order", body = Vec<tags::TagCount>),
+    )
+)]
+pub async fn get_tags(State(appstate): State<SharedAppState>) -> axum::response::Response {
+    let store = appstate.read().await.store.clone();
+    match store.tag_counts().await {
+        Ok(tag_counts) => Json(tag_counts).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Utoipa-documented handler for the tag hierarchy.
+///
+/// Tags with no parent are at the roots. A tag with several parents
+/// appears beneath each. Counts are of the recipes carrying each tag
+/// itself.
+#[utoipa::path(
+    get,
+    path = "/tags/tree",
+    responses(
+        (status = 200, description = "Tag trees in canonical order", body = Vec<tags::TagNode>),
+    )
+)]
+pub async fn get_tag_tree(State(appstate): State<SharedAppState>) -> axum::response::Response {
+    let store = appstate.read().await.store.clone();
+    let tree = async {
+        let tag_counts = store.tag_counts().await?;
+        let edges = store.tag_edges().await?;
+        Ok::<_, sqlx::Error>(tags::build_tree(&tag_counts, &edges))
+    };
+    match tree.await {
+        Ok(tree) => Json(tree).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Query parameters for tag facets.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FacetParams {
+    /// Comma-separated tags already selected; recipes must carry them all.
+    tags: Option<String>,
+}
+
+/// Utoipa-documented handler for faceted tag counts.
+///
+/// Counts the recipes carrying every selected tag, and for each further
+/// tag, how many of them would remain were it selected too.
+#[utoipa::path(
+    get,
+    path = "/tags/facets",
+    params(FacetParams),
+    responses(
+        (status = 200, description = "Counts for the selection", body = tags::Facets),
+    )
+)]
+pub async fn get_tag_facets(
+    State(appstate): State<SharedAppState>,
+    Query(params): Query<FacetParams>,
+) -> axum::response::Response {
+    let selected: Vec<String> = params
+        .tags
+        .iter()
+        .flat_map(|tags| tags.split(','))
+        .map(str::to_string)
+        .collect();
+    let store = appstate.read().await.store.clone();
+    match store.facets(&selected).await {
+        Ok(facets) => Json(facets).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Most tags a suggestion request returns.
+const SUGGEST_LIMIT: usize = 50;
+
+/// Query parameters for tag suggestions.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SuggestParams {
+    /// Partial or misspelt tag name.
+    #[serde(default)]
+    prefix: String,
+    /// Most tags to return, at most 50.
+    #[serde(default = "default_suggest_limit")]
+    limit: usize,
+}
+
+fn default_suggest_limit() -> usize {
+    10
+}
+
+/// Utoipa-documented handler for suggesting tags as they are typed.
+///
+/// Tags, or their aliases, beginning with the prefix come first, then
+/// those a few typos from it, each group most used first. Only tags
+/// carried by some recipe are suggested.
+#[utoipa::path(
+    get,
+    path = "/tags/suggest",
+    params(SuggestParams),
+    responses(
+        (status = 200, description = "Suggested tags, best first", body = Vec<tags::TagCount>),
+    )
+)]
+pub async fn suggest_tags(
+    State(appstate): State<SharedAppState>,
+    Query(params): Query<SuggestParams>,
+) -> axum::response::Response {
+    let limit = params.limit.min(SUGGEST_LIMIT);
+    let store = appstate.read().await.store.clone();
+    match tags::suggest(store.as_ref(), &params.prefix, limit).await {
+        Ok(suggestions) => Json(suggestions).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
@@ -0,0 +1,436 @@
+//! End-to-end tests of the API, driving the full router over an in-memory
+//! store without a network listener.
+
+use super::*;
+
+use axum::body::Body;
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+use tower::ServiceExt;
+
+/// Registration password the test server accepts.
+const REG_KEY: &str = "let me in";
+
+/// A test server: the application and the state behind it.
+struct TestServer {
+    app: axum::Router,
+    state: SharedAppState,
+}
+
+/// A response, with its body read.
+struct Reply {
+    status: StatusCode,
+    headers: http::HeaderMap,
+    body: axum::body::Bytes,
+}
+
+impl Reply {
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+
+    /// The body as NDJSON lines.
+    fn lines(&self) -> Vec<Value> {
+        std::str::from_utf8(&self.body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+impl TestServer {
+    /// Starts a server on an empty `memory://` store.
+    async fn new() -> Self {
+        let mut config = Config::default();
+        config.database.uri = "memory://".to_string();
+        let config = Arc::new(config);
+        let store = store::connect(&config.database).await.unwrap();
+        let cache = Arc::new(cache::RecipeCache::new(true, 100, Duration::from_secs(60)));
+        let state = AppState::new(
+            store,
+            authjwt::JwtKeys::new(b"test secret"),
+            REG_KEY.to_string(),
+            cache,
+            config.clone(),
+            shutdown::Shutdown::new(&config.server),
+        );
+        let state = Arc::new(RwLock::new(state));
+        Self {
+            app: crate::router(state.clone(), &config),
+            state,
+        }
+    }
+
+    /// A registration token, which acts as an editor's.
+    async fn register(&self) -> String {
+        let registration = json!({
+            "full_name": "Ed Itor",
+            "email": "ed@example.org",
+            "password": REG_KEY,
+        });
+        let reply = self.send(post("/api/v1/register", &registration)).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        reply.json()["access_token"].as_str().unwrap().to_string()
+    }
+
+    /// An API key for a new user with `role`.
+    async fn api_key(&self, email: &str, role: users::Role) -> users::NewApiKey {
+        let store = self.state.read().await.store.clone();
+        store.add_user(email, "Test User", role).await.unwrap();
+        let key = users::create_api_key(store.as_ref(), email).await.unwrap();
+        key.unwrap()
+    }
+
+    async fn send(&self, request: http::Request<Body>) -> Reply {
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        Reply {
+            status: parts.status,
+            headers: parts.headers,
+            body: axum::body::to_bytes(body, usize::MAX).await.unwrap(),
+        }
+    }
+}
+
+/// A request without a body.
+fn request(method: http::Method, uri: &str) -> http::request::Builder {
+    http::Request::builder().method(method).uri(uri)
+}
+
+fn get(uri: &str) -> http::Request<Body> {
+    request(http::Method::GET, uri).body(Body::empty()).unwrap()
+}
+
+fn post(uri: &str, body: &Value) -> http::Request<Body> {
+    with_json(request(http::Method::POST, uri), body)
+}
+
+fn with_json(builder: http::request::Builder, body: &Value) -> http::Request<Body> {
+    builder
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// `builder` carrying `token` as its bearer token.
+fn authorized(builder: http::request::Builder, token: &str) -> http::request::Builder {
+    builder.header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+}
+
+fn recipe(id: &str, dish_name: &str, tags: &[&str]) -> Value {
+    json!({
+        "id": id,
+        "dish_name": dish_name,
+        "ingredients": "pasta, eggs, parmesan",
+        "time_to_prepare": "20m",
+        "tags": tags,
+        "source": "test",
+    })
+}
+
+fn add(token: &str, recipe: &Value) -> http::Request<Body> {
+    let builder = authorized(request(http::Method::POST, "/api/v1/add-recipe"), token);
+    with_json(builder, recipe)
+}
+
+fn update(token: &str, recipe: &Value, if_match: Option<&str>) -> http::Request<Body> {
+    let uri = format!("/api/v1/recipe/{}", recipe["id"].as_str().unwrap());
+    let mut builder = authorized(request(http::Method::PUT, &uri), token);
+    if let Some(etag) = if_match {
+        builder = builder.header(http::header::IF_MATCH, etag);
+    }
+    with_json(builder, recipe)
+}
+
+fn delete(token: &str, id: &str, if_match: Option<&str>) -> http::Request<Body> {
+    let uri = format!("/api/v1/recipe/{}", id);
+    let mut builder = authorized(request(http::Method::DELETE, &uri), token);
+    if let Some(etag) = if_match {
+        builder = builder.header(http::header::IF_MATCH, etag);
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+fn import(token: &str, query: &str, content_type: &str, body: String) -> http::Request<Body> {
+    let uri = format!("/api/v1/recipes/import{}", query);
+    authorized(request(http::Method::POST, &uri), token)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn recipe_crud() {
+    let server = TestServer::new().await;
+    let token = server.register().await;
+
+    let carbonara = recipe("carbonara", "Carbonara", &["Pasta"]);
+    let reply = server.send(add(&token, &carbonara)).await;
+    assert_eq!(reply.status, StatusCode::CREATED);
+
+    let reply = server.send(get("/api/v1/recipe/carbonara")).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.json()["dish_name"], "Carbonara");
+    let etag = reply.headers[http::header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let not_modified = request(http::Method::GET, "/api/v1/recipe/carbonara")
+        .header(http::header::IF_NONE_MATCH, &etag)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        server.send(not_modified).await.status,
+        StatusCode::NOT_MODIFIED
+    );
+
+    // The cached copy is dropped on update.
+    let changed = recipe("carbonara", "Spaghetti Carbonara", &["Pasta"]);
+    let reply = server.send(update(&token, &changed, Some(&etag))).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = server.send(get("/api/v1/recipe/carbonara")).await;
+    assert_eq!(reply.json()["dish_name"], "Spaghetti Carbonara");
+    let current = reply.headers[http::header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_ne!(current, etag);
+
+    let filter = get("/api/v1/recipes/search?q=spaghetti");
+    let found = server.send(filter).await.json();
+    assert_eq!(found.as_array().unwrap().len(), 1);
+
+    let reply = server
+        .send(delete(&token, "carbonara", Some(&current)))
+        .await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+    let reply = server.send(get("/api/v1/recipe/carbonara")).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn error_statuses() {
+    let server = TestServer::new().await;
+    let token = server.register().await;
+    let soup = recipe("soup", "Soup", &["Soup"]);
+    server.send(add(&token, &soup)).await;
+
+    let reply = server.send(get("/api/v1/recipe/missing")).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let missing = recipe("missing", "Missing", &[]);
+    let reply = server.send(update(&token, &missing, None)).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = server.send(delete(&token, "missing", None)).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+
+    let reply = server.send(add(&token, &soup)).await;
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    // The request id is only in the header; the body stays plain text.
+    assert!(reply.headers.contains_key(request_id::HEADER));
+    assert!(serde_json::from_slice::<Value>(&reply.body).is_err());
+
+    let clash = recipe("clash", "Clash", &["Cheap", "Expensive"]);
+    let reply = server.send(add(&token, &clash)).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+
+    let stale = "\"0123456789abcdef0123456789abcdef\"";
+    let changed = recipe("soup", "Better Soup", &["Soup"]);
+    let reply = server.send(update(&token, &changed, Some(stale))).await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+    let reply = server.send(delete(&token, "soup", Some(stale))).await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+    let reply = server.send(delete(&token, "missing", Some("*"))).await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+    let reply = server.send(get("/api/v1/recipe/soup")).await;
+    assert_eq!(reply.json()["dish_name"], "Soup");
+}
+
+#[tokio::test]
+async fn auth_failures() {
+    let server = TestServer::new().await;
+    let soup = recipe("soup", "Soup", &[]);
+
+    let unauthenticated = with_json(request(http::Method::POST, "/api/v1/add-recipe"), &soup);
+    let reply = server.send(unauthenticated).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    let reply = server.send(add("not-a-token", &soup)).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+
+    let registration = json!({
+        "full_name": "Mal",
+        "email": "mal@example.org",
+        "password": "wrong",
+    });
+    let reply = server.send(post("/api/v1/register", &registration)).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+
+    let viewer = server
+        .api_key("viewer@example.org", users::Role::Viewer)
+        .await
+        .key;
+    let reply = server.send(add(&viewer, &soup)).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let editor_key = server
+        .api_key("editor@example.org", users::Role::Editor)
+        .await;
+    let editor = editor_key.key;
+    let reply = server.send(add(&editor, &soup)).await;
+    assert_eq!(reply.status, StatusCode::CREATED);
+    let reply = server.send(delete(&viewer, "soup", None)).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let merge = json!({ "tags": ["soups"], "into": "soup" });
+    let builder = authorized(
+        request(http::Method::POST, "/api/v1/admin/tags/merge"),
+        &editor,
+    );
+    let reply = server.send(with_json(builder, &merge)).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let body = serde_json::to_string(&[&soup]).unwrap();
+    let replace = import(&editor, "?mode=replace", "application/json", body);
+    assert_eq!(server.send(replace).await.status, StatusCode::FORBIDDEN);
+
+    // A revoked key stops working at once.
+    let store = server.state.read().await.store.clone();
+    assert!(store.revoke_api_key(&editor_key.id).await.unwrap());
+    let reply = server.send(delete(&editor, "soup", None)).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn imports() {
+    let server = TestServer::new().await;
+    let token = server.register().await;
+    server.send(add(&token, &recipe("soup", "Soup", &[]))).await;
+
+    let lines = [
+        recipe("pie", "Pie", &["Dessert"]).to_string(),
+        "{not json".to_string(),
+        String::new(),
+        recipe("soup", "Soup Again", &[]).to_string(),
+        recipe("clash", "Clash", &["Cheap", "Expensive"]).to_string(),
+    ];
+    let reply = server
+        .send(import(&token, "", "application/x-ndjson", lines.join("\n")))
+        .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let lines = reply.lines();
+    let statuses: Vec<&str> = lines[..4]
+        .iter()
+        .map(|line| line["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["inserted", "error", "skipped", "error"]);
+    let summary = &lines[4]["summary"];
+    assert_eq!(summary["inserted"], 1);
+    assert_eq!(summary["errors"], 2);
+    assert_eq!(summary["committed"], true);
+    let reply = server.send(get("/api/v1/recipe/pie")).await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    let array = json!([
+        recipe("soup", "Soup Again", &[]),
+        recipe("stew", "Stew", &[])
+    ]);
+    let query = "?mode=upsert&dry_run=true";
+    let reply = server
+        .send(import(&token, query, "application/json", array.to_string()))
+        .await;
+    let lines = reply.lines();
+    assert_eq!(lines[0]["status"], "updated");
+    assert_eq!(lines[1]["status"], "inserted");
+    assert_eq!(lines[2]["summary"]["committed"], false);
+    let reply = server.send(get("/api/v1/recipe/stew")).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+
+    let reply = server
+        .send(import(
+            &token,
+            "?mode=upsert",
+            "application/json",
+            array.to_string(),
+        ))
+        .await;
+    assert_eq!(reply.lines()[2]["summary"]["updated"], 1);
+    let reply = server.send(get("/api/v1/recipe/soup")).await;
+    assert_eq!(reply.json()["dish_name"], "Soup Again");
+
+    let reply = server
+        .send(import(&token, "", "application/json", "{}".to_string()))
+        .await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+
+    let admin = server
+        .api_key("admin@example.org", users::Role::Admin)
+        .await
+        .key;
+    let only = json!([recipe("only", "Only", &[])]);
+    let reply = server
+        .send(import(
+            &admin,
+            "?mode=replace",
+            "application/json",
+            only.to_string(),
+        ))
+        .await;
+    assert_eq!(reply.lines()[1]["summary"]["inserted"], 1);
+    let reply = server.send(get("/api/v1/recipe/pie")).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn imports_in_batches() {
+    let server = TestServer::new().await;
+    let admin = server
+        .api_key("admin@example.org", users::Role::Admin)
+        .await
+        .key;
+    let count = import::BATCH_RECIPES * 2 + 1;
+    let lines: Vec<String> = (0..count)
+        .map(|n| recipe(&format!("r{}", n), "Dish", &[]).to_string())
+        .collect();
+    let reply = server
+        .send(import(&admin, "", "application/x-ndjson", lines.join("\n")))
+        .await;
+    let lines = reply.lines();
+    assert_eq!(lines.len(), count + 1);
+    assert_eq!(lines[count - 1]["index"], count - 1);
+    assert_eq!(lines[count]["summary"]["inserted"], count);
+    let reply = server.send(get("/api/v1/recipe/r0")).await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    let reply = server
+        .send(import(
+            &admin,
+            "?mode=replace",
+            "application/json",
+            "[]".to_string(),
+        ))
+        .await;
+    assert_eq!(reply.lines()[0]["summary"]["committed"], true);
+    let reply = server.send(get("/api/v1/recipe/r0")).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn failed_export_aborts_the_body() {
+    let shutdown = shutdown::Shutdown::new(&Config::default().server);
+    let body = piped_body(&shutdown, |mut writer| async move {
+        writer.write_all(b"[{\"id\":\"r1\"}").await?;
+        Err("database went away".into())
+    });
+    let mut chunks = body.into_data_stream();
+    let first = chunks.next().await.unwrap().unwrap();
+    assert_eq!(&first[..], b"[{\"id\":\"r1\"}");
+    assert!(chunks.next().await.unwrap().is_err());
+
+    let body = piped_body(&shutdown, |mut writer| async move {
+        writer.write_all(b"[]").await?;
+        Ok(())
+    });
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    assert_eq!(&bytes[..], b"[]");
+}
@@ -22,19 +22,25 @@ impl JwtKeys {
     }
 }
 
-/// Reads a secret value from the environment or from a fallback file.
-pub async fn read_secret(
-    env_var: &str,
-    default: &str,
+/// Returns an inline secret if given, otherwise reads it from `path`.
+pub async fn load_secret(
+    inline: Option<&str>,
+    path: &std::path::Path,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let secretf = std::env::var(env_var).unwrap_or_else(|_| default.to_owned());
-    let secret = tokio::fs::read_to_string(secretf).await?;
+    let secret = match inline {
+        Some(secret) => secret.to_string(),
+        None => tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+    };
     Ok(secret.trim().to_string())
 }
 
-/// Constructs JWT keys from a secret file.
-pub async fn make_jwt_keys() -> Result<JwtKeys, Box<dyn std::error::Error>> {
-    let secret = read_secret("JWT_SECRETFILE", "secrets/jwt_secret.txt").await?;
+/// Constructs JWT keys from the configured secret.
+pub async fn make_jwt_keys(
+    config: &config::AuthConfig,
+) -> Result<JwtKeys, Box<dyn std::error::Error>> {
+    let secret = config.load_jwt_secret().await?;
     Ok(JwtKeys::new(secret.as_bytes()))
 }
 
@@ -52,6 +58,10 @@ pub enum AuthError {
     /// The registration failed.
     #[error("Registration error")]
     Registration,
+
+    /// The caller's role does not allow the operation.
+    #[error("Forbidden")]
+    Forbidden,
 }
 
 impl utoipa::PartialSchema for AuthError {
@@ -101,10 +111,19 @@ impl axum::extract::FromRequestParts<SharedAppState> for Claims {
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| AuthError::InvalidToken)?;
-        // Decode the user data
         let appstate = state.read().await;
+        // API keys are looked up rather than decoded
+        if users::is_api_key(bearer.token()) {
+            let user = users::verify_api_key(appstate.store.as_ref(), bearer.token())
+                .await
+                .map_err(|_| AuthError::InvalidToken)?
+                .ok_or(AuthError::InvalidToken)?;
+            return Ok(Claims::for_user(&user, &appstate.config.auth));
+        }
+        // Decode the user data
         let decoding_key = &appstate.jwt_keys.decoding;
-        let validation = Validation::new(Algorithm::HS512);
+        let mut validation = Validation::new(Algorithm::HS512);
+        validation.set_issuer(&[&appstate.config.auth.issuer]);
         let result = decode::<Claims>(bearer.token(), decoding_key, &validation);
         let token_data = result.map_err(|_| AuthError::Registration)?;
         Ok(token_data.claims)
@@ -113,10 +132,12 @@ impl axum::extract::FromRequestParts<SharedAppState> for Claims {
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> axum::response::Response {
+        metrics::auth_failure(&format!("{:?}", self));
         let (status, error_message) = match self {
             AuthError::Registration => (StatusCode::UNAUTHORIZED, "Invalid registration"),
             AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
             AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
+            AuthError::Forbidden => (StatusCode::FORBIDDEN, "Insufficient role"),
         };
         let body = Json(serde_json::json!({
             "Status": status.as_u16(),
@@ -156,6 +177,34 @@ pub struct Claims {
     /// Expiration time as a Unix timestamp.
     #[schema(example = "1717630066")]
     exp: u64,
+
+    /// Role of a user authenticated by API key. Registration tokens carry
+    /// no role, and act as editors, since registering takes the shared
+    /// registration password.
+    #[serde(skip)]
+    role: Option<users::Role>,
+}
+
+impl Claims {
+    /// Builds claims for a user authenticated by API key.
+    fn for_user(user: &users::User, config: &config::AuthConfig) -> Self {
+        let exp = (Utc::now() + config.token_lifetime()).timestamp();
+        Self {
+            iss: config.issuer.clone(),
+            sub: format!("{} <{}>", user.full_name, user.email),
+            exp: u64::try_from(exp).unwrap(),
+            role: Some(user.role),
+        }
+    }
+
+    /// Fails unless the caller holds at least `role`.
+    pub fn require(&self, role: users::Role) -> Result<(), AuthError> {
+        if self.role.unwrap_or(users::Role::Editor) >= role {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
 }
 
 /// Generates a JWT token from the given registration information.
@@ -169,11 +218,17 @@ pub fn make_jwt_token(
         return Err(AuthError::Registration);
     }
 
-    let iss = "recipe-server.po8.org".to_string();
+    let config = &appstate.config.auth;
+    let iss = config.issuer.clone();
     let sub = format!("{} <{}>", registration.full_name, registration.email);
-    let exp = (Utc::now() + TimeDelta::days(1)).timestamp();
+    let exp = (Utc::now() + config.token_lifetime()).timestamp();
     let exp = u64::try_from(exp).unwrap();
-    let claims = Claims { iss, sub, exp };
+    let claims = Claims {
+        iss,
+        sub,
+        exp,
+        role: None,
+    };
     let header = Header::new(Algorithm::HS512);
     let token = encode(&header, &claims, &appstate.jwt_keys.encoding)
         .map_err(|_| AuthError::TokenCreation)?;
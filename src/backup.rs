@@ -0,0 +1,347 @@
+//! Online backups and validated restores.
+//!
+//! Backups and restores are supported for SQLite only; PostgreSQL has its
+//! own tools for this. A backup is taken with `VACUUM INTO`, which writes
+//! a consistent, compacted copy of the database while the server keeps
+//! serving. Backups
+//! may be gzip-compressed. Those written to the configured backup
+//! directory are rotated, keeping only the newest `backup.keep`.
+//!
+//! A restore first stages the backup next to the live database and checks
+//! that it is intact and that this build knows every migration applied to
+//! it. It refuses to go ahead while anything else, such as a running
+//! server, has the live database open; otherwise the live file's
+//! write-ahead log is removed and the staged file renamed over it.
+
+use crate::*;
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteLockingMode};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use std::path::{Path, PathBuf};
+
+/// Error type for backups, which can fail in the database or the file
+/// system.
+pub type BackupError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Start of the names of backups in the backup directory.
+const PREFIX: &str = "recipes-";
+
+/// A backup that has been written.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Backup {
+    /// File written.
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub bytes: u64,
+    /// Whether the file is gzip-compressed.
+    pub compressed: bool,
+    /// Older backups deleted by rotation.
+    #[schema(value_type = Vec<String>)]
+    pub removed: Vec<PathBuf>,
+}
+
+/// Returns `true` if `path` names a gzip-compressed file.
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+/// Returns `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Copies `from` to `to`, compressing or decompressing on the way.
+fn copy_gzip(from: &Path, to: &Path, compress: bool) -> std::io::Result<()> {
+    let mut input = std::fs::File::open(from)?;
+    let output = std::fs::File::create(to)?;
+    if compress {
+        let mut encoder = GzEncoder::new(output, Compression::default());
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()
+    } else {
+        let mut output = output;
+        std::io::copy(&mut GzDecoder::new(input), &mut output)?;
+        output.sync_all()
+    }
+}
+
+/// Writes a backup of `db` to `path`, which must not exist. The backup is
+/// gzip-compressed if `path` ends in `.gz`.
+pub async fn write(store: &dyn store::Maintenance, path: &Path) -> Result<Backup, BackupError> {
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()).into());
+    }
+    let compressed = is_gzip(path);
+    let staging = with_suffix(path, ".partial");
+    let _ = tokio::fs::remove_file(&staging).await;
+    let staging_name = staging
+        .to_str()
+        .ok_or_else(|| format!("{} is not valid UTF-8", path.display()))?;
+
+    store.snapshot(staging_name).await?;
+    if compressed {
+        let (from, to) = (staging.clone(), path.to_path_buf());
+        let result = tokio::task::spawn_blocking(move || copy_gzip(&from, &to, true)).await?;
+        let _ = tokio::fs::remove_file(&staging).await;
+        result?;
+    } else {
+        tokio::fs::rename(&staging, path).await?;
+    }
+
+    let bytes = tokio::fs::metadata(path).await?.len();
+    tracing::info!("Backed up database to {} ({} bytes)", path.display(), bytes);
+    Ok(Backup {
+        path: path.to_path_buf(),
+        bytes,
+        compressed,
+        removed: Vec::new(),
+    })
+}
+
+/// Writes a timestamped backup into the configured directory, then
+/// deletes all but the newest `config.keep` backups there.
+pub async fn rotate(
+    store: &dyn store::Maintenance,
+    config: &config::BackupConfig,
+) -> Result<Backup, BackupError> {
+    tokio::fs::create_dir_all(&config.dir).await?;
+    let extension = if config.compress { "db.gz" } else { "db" };
+    let name = format!(
+        "{}{}.{}",
+        PREFIX,
+        Utc::now().format("%Y%m%dT%H%M%S%3fZ"),
+        extension
+    );
+    let mut backup = write(store, &config.dir.join(name)).await?;
+
+    if config.keep > 0 {
+        let mut backups = Vec::new();
+        let mut entries = tokio::fs::read_dir(&config.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(PREFIX) && (name.ends_with(".db") || name.ends_with(".db.gz")) {
+                backups.push(entry.path());
+            }
+        }
+        // Timestamped names sort oldest first.
+        backups.sort();
+        let excess = backups.len().saturating_sub(config.keep);
+        for path in backups.into_iter().take(excess) {
+            tokio::fs::remove_file(&path).await?;
+            tracing::info!("Removed old backup {}", path.display());
+            backup.removed.push(path);
+        }
+    }
+    Ok(backup)
+}
+
+/// Failure of a restore.
+#[derive(Debug, thiserror::Error)]
+pub enum RestoreError {
+    /// The backup is damaged or does not match this build.
+    #[error("Invalid backup: {0}")]
+    Invalid(String),
+
+    /// The database is not an SQLite file.
+    #[error("Restores are only supported for SQLite databases")]
+    Unsupported,
+
+    /// Another connection has the database open.
+    #[error("The database is in use; stop the server before restoring")]
+    InUse,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+
+    #[error(transparent)]
+    DbUri(#[from] RecipeServerError),
+}
+
+/// A restore that has been carried out.
+#[derive(Debug, Serialize)]
+pub struct Restored {
+    /// Backup restored from.
+    pub backup: PathBuf,
+    /// Database file replaced.
+    pub path: PathBuf,
+    /// Latest migration applied to the backup.
+    pub migration_version: i64,
+}
+
+/// Checks that the database at `path` is intact and that every migration
+/// applied to it is one of this build's. Returns its latest migration.
+async fn check(path: &Path) -> Result<i64, RestoreError> {
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    let db = SqlitePool::connect_with(options)
+        .await
+        .map_err(|e| RestoreError::Invalid(format!("not a readable database: {}", e)))?;
+    let result = async {
+        let integrity: String = sqlx::query_scalar("PRAGMA integrity_check;")
+            .fetch_one(&db)
+            .await
+            .map_err(|e| RestoreError::Invalid(e.to_string()))?;
+        if integrity != "ok" {
+            return Err(RestoreError::Invalid(format!(
+                "integrity check failed: {}",
+                integrity
+            )));
+        }
+
+        let applied: Vec<(i64, bool, Vec<u8>)> =
+            sqlx::query_as("SELECT version, success, checksum FROM _sqlx_migrations;")
+                .fetch_all(&db)
+                .await
+                .map_err(|_| RestoreError::Invalid("no migration history".to_string()))?;
+        for (version, success, checksum) in &applied {
+            if !success {
+                return Err(RestoreError::Invalid(format!(
+                    "migration {} is only partly applied",
+                    version
+                )));
+            }
+            let known = store::sqlite::MIGRATOR
+                .iter()
+                .find(|m| m.version == *version && m.migration_type.is_up_migration());
+            match known {
+                None => {
+                    return Err(RestoreError::Invalid(format!(
+                        "migration {} is newer than this build",
+                        version
+                    )));
+                }
+                Some(m) if *m.checksum != checksum[..] => {
+                    return Err(RestoreError::Invalid(format!(
+                        "migration {} differs from this build's",
+                        version
+                    )));
+                }
+                Some(_) => (),
+            }
+        }
+        applied
+            .iter()
+            .map(|(version, _, _)| *version)
+            .max()
+            .ok_or_else(|| RestoreError::Invalid("no applied migrations".to_string()))
+    }
+    .await;
+    db.close().await;
+    result
+}
+
+/// Fails if another connection has the database at `path` open. Ours is
+/// then the last to close, which checkpoints and removes the write-ahead
+/// log.
+async fn ensure_unused(path: &Path) -> Result<(), RestoreError> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .locking_mode(SqliteLockingMode::Exclusive)
+        .busy_timeout(Duration::ZERO);
+    let mut conn = SqliteConnection::connect_with(&options).await?;
+    // An exclusive lock can't be had while any other connection, even an
+    // idle one, has a write-ahead-logged database open.
+    let locked = sqlx::query("BEGIN EXCLUSIVE;").execute(&mut conn).await;
+    if locked.is_ok() {
+        sqlx::query("COMMIT;").execute(&mut conn).await?;
+    }
+    conn.close().await?;
+    locked.map(|_| ()).map_err(|_| RestoreError::InUse)
+}
+
+/// Replaces the database at `db_uri` with the backup at `input`, which is
+/// decompressed first if it ends in `.gz`. The server must be stopped;
+/// the restore fails with [`RestoreError::InUse`] otherwise.
+///
+/// Migrations older than this build's are left pending, to be applied when
+/// the server next starts.
+pub async fn restore(db_uri: &str, input: &Path) -> Result<Restored, RestoreError> {
+    if store::Backend::from_uri(db_uri)? != store::Backend::Sqlite {
+        return Err(RestoreError::Unsupported);
+    }
+    let target = PathBuf::from(store::sqlite::extract_db_path(db_uri)?);
+    let staging = with_suffix(&target, ".restore");
+    let (from, to) = (input.to_path_buf(), staging.clone());
+    let staged = if is_gzip(input) {
+        tokio::task::spawn_blocking(move || copy_gzip(&from, &to, false))
+            .await
+            .map_err(std::io::Error::other)?
+    } else {
+        tokio::fs::copy(&from, &to).await.map(|_| ())
+    };
+    if let Err(e) = staged {
+        let _ = tokio::fs::remove_file(&staging).await;
+        return Err(e.into());
+    }
+
+    let prepared = async {
+        let version = check(&staging).await?;
+        if tokio::fs::try_exists(&target).await? {
+            ensure_unused(&target).await?;
+        }
+        // A log left beside the live file would be replayed into the
+        // restored one.
+        for suffix in ["-wal", "-shm"] {
+            match tokio::fs::remove_file(with_suffix(&target, suffix)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+        Ok(version)
+    };
+    let migration_version = match prepared.await {
+        Ok(version) => version,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staging).await;
+            return Err(e);
+        }
+    };
+    tokio::fs::rename(&staging, &target).await?;
+    tracing::info!("Restored {} to {}", input.display(), target.display());
+    Ok(Restored {
+        backup: input.to_path_buf(),
+        path: target,
+        migration_version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use store::tests::{TempDb, recipe};
+
+    #[tokio::test]
+    async fn backs_up_checks_and_restores() {
+        let live = TempDb::new("backup-live");
+        let copy = TempDb::new("backup-copy");
+        let config = live.config();
+        let store = store::connect(&config).await.unwrap();
+        store.migrate().await.unwrap();
+        store.add(recipe("kept", &[])).await.unwrap();
+
+        let backup = write(store.as_ref(), copy.path()).await.unwrap();
+        assert!(!backup.compressed && backup.bytes > 0);
+        let latest = store::sqlite::MIGRATOR.iter().map(|m| m.version).max();
+        assert_eq!(check(copy.path()).await.ok(), latest);
+
+        store.add(recipe("lost", &[])).await.unwrap();
+        assert!(matches!(
+            restore(&config.uri, copy.path()).await,
+            Err(RestoreError::InUse)
+        ));
+        store.close().await;
+
+        let restored = restore(&config.uri, copy.path()).await.unwrap();
+        assert_eq!(Some(restored.migration_version), latest);
+        let store = store::connect(&config).await.unwrap();
+        assert!(store.get("kept").await.is_ok());
+        assert!(store.get("lost").await.is_err());
+        store.close().await;
+    }
+}
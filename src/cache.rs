@@ -0,0 +1,163 @@
+//! In-process response cache for the recipe server.
+//!
+//! Keeps a bounded LRU of `JsonRecipe` values keyed by recipe id so that
+//! repeated lookups of the same recipe do not hit SQLite. Entries expire
+//! after a fixed time-to-live, and every write path must invalidate the
+//! ids it touches.
+//!
+//! A reader takes the cache's [generation](RecipeCache::generation) before
+//! going to the database and hands it back on insert; a recipe read before
+//! an invalidation is then not cached, since it may predate the write.
+
+use crate::*;
+
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use lru::LruCache;
+
+/// Bounded, time-limited cache of recipes keyed by id.
+pub struct RecipeCache {
+    entries: Option<Mutex<LruCache<String, (Instant, JsonRecipe)>>>,
+    capacity: usize,
+    ttl: Duration,
+    /// Bumped by every invalidation, under the entries lock.
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Snapshot of cache counters reported by the API.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheStats {
+    /// Whether the cache is switched on.
+    enabled: bool,
+    /// Maximum number of recipes held.
+    capacity: usize,
+    /// Number of recipes currently held.
+    entries: usize,
+    /// Number of lookups answered from the cache.
+    hits: u64,
+    /// Number of lookups that had to go to the database.
+    misses: u64,
+}
+
+impl RecipeCache {
+    /// Creates a cache holding at most `capacity` recipes for `ttl` each.
+    ///
+    /// A zero capacity or `enabled == false` produces a cache that never
+    /// stores anything.
+    pub fn new(enabled: bool, capacity: usize, ttl: Duration) -> Self {
+        let entries = NonZeroUsize::new(capacity)
+            .filter(|_| enabled)
+            .map(|cap| Mutex::new(LruCache::new(cap)));
+        Self {
+            entries,
+            capacity,
+            ttl,
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Looks up a recipe, dropping it if its time-to-live has passed.
+    pub fn get(&self, recipe_id: &str) -> Option<JsonRecipe> {
+        let entries = self.entries.as_ref()?;
+        let mut entries = entries.lock().unwrap();
+        let result = match entries.get(recipe_id) {
+            Some((stored, recipe)) if stored.elapsed() < self.ttl => Some(recipe.clone()),
+            Some(_) => {
+                entries.pop(recipe_id);
+                None
+            }
+            None => None,
+        };
+        let counter = if result.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        metrics::cache_lookup(result.is_some());
+        result
+    }
+
+    /// The current generation, to be taken before reading a recipe from
+    /// the database and passed to [`insert`](Self::insert).
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Stores a recipe read at `generation`, evicting the least recently
+    /// used one if full. Nothing is stored if anything has been
+    /// invalidated since.
+    pub fn insert(&self, recipe: JsonRecipe, generation: u64) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock().unwrap();
+            if self.generation.load(Ordering::SeqCst) == generation {
+                let recipe_id = recipe.id().to_string();
+                entries.put(recipe_id, (Instant::now(), recipe));
+            }
+        }
+    }
+
+    /// Drops a single recipe after it has been written.
+    pub fn invalidate(&self, recipe_id: &str) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock().unwrap();
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.pop(recipe_id);
+        }
+    }
+
+    /// Drops every cached recipe.
+    pub fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock().unwrap();
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.clear();
+        }
+    }
+
+    /// Reports current size and hit/miss counters.
+    pub fn stats(&self) -> CacheStats {
+        let entries = self
+            .entries
+            .as_ref()
+            .map(|entries| entries.lock().unwrap().len())
+            .unwrap_or(0);
+        CacheStats {
+            enabled: self.entries.is_some(),
+            capacity: self.capacity,
+            entries,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use store::tests::recipe;
+
+    #[test]
+    fn skips_reads_from_before_an_invalidation() {
+        let cache = RecipeCache::new(true, 10, Duration::from_secs(60));
+        let before = cache.generation();
+        cache.invalidate("r1");
+        cache.insert(recipe("r1", &[]), before);
+        assert!(cache.get("r1").is_none());
+
+        let before = cache.generation();
+        cache.clear();
+        cache.insert(recipe("r1", &[]), before);
+        assert!(cache.get("r1").is_none());
+
+        cache.insert(recipe("r1", &[]), cache.generation());
+        assert_eq!(cache.get("r1").unwrap().id(), "r1");
+    }
+}
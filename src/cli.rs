@@ -0,0 +1,744 @@
+//! Command-line interface for the recipe server.
+//!
+//! With no subcommand the binary serves, accepting the `serve` flags at the
+//! top level. Every other subcommand runs once against the database and
+//! exits with a status from [`exit`], printing a human-readable result or,
+//! with `--json`, a single JSON object on standard output.
+//!
+//! Flags given here are the last layer of the [`Config`], overriding the
+//! configuration file and environment.
+
+use crate::*;
+
+use futures_util::StreamExt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio::io::BufReader;
+
+/// Exit statuses, following the BSD `sysexits` conventions.
+pub mod exit {
+    /// The command succeeded.
+    pub const SUCCESS: u8 = 0;
+    /// The command failed for an operational reason.
+    pub const FAILURE: u8 = 1;
+    /// The command's input data was invalid.
+    pub const DATA_ERROR: u8 = 65;
+    /// The configuration is invalid.
+    pub const CONFIG_ERROR: u8 = 78;
+}
+
+/// Top-level command-line arguments.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file; defaults to `$RECIPE_SERVER_CONFIG`, then
+    /// `recipe-server.toml` if present.
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Optional SQLite or PostgreSQL database URI.
+    #[arg(short, long, name = "db-uri", global = true)]
+    db_uri: Option<String>,
+
+    /// Print a machine-readable JSON result on standard output.
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Log line encoding.
+    #[arg(long, value_enum, global = true)]
+    log_format: Option<config::LogFormat>,
+
+    /// Print the effective configuration, with secrets redacted, and exit.
+    #[arg(long, global = true)]
+    print_config: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+impl Cli {
+    /// The configuration layers named on the command line.
+    pub fn config_source(&self) -> ConfigSource {
+        ConfigSource {
+            path: self.config.clone(),
+            db_uri: self.db_uri.clone(),
+            log_format: self.log_format,
+            serve: self.serve.clone(),
+        }
+    }
+}
+
+/// Arguments for running the web service.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ServeArgs {
+    /// IP address to bind the server to.
+    #[arg(short, long)]
+    ip: Option<String>,
+
+    /// Port number to bind the server to.
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Unix domain socket to listen on instead of the IP address and port.
+    #[arg(long, value_name = "PATH")]
+    unix_socket: Option<PathBuf>,
+
+    /// Disable the in-process recipe cache.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Maximum number of recipes held in the cache.
+    #[arg(long)]
+    cache_capacity: Option<usize>,
+
+    /// Seconds a cached recipe stays valid.
+    #[arg(long)]
+    cache_ttl: Option<u64>,
+}
+
+impl ServeArgs {
+    /// Overrides configuration values with any flags given.
+    fn apply(self, config: &mut Config) {
+        if let Some(ip) = self.ip {
+            config.server.ip = ip;
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(path) = self.unix_socket {
+            config.server.unix_socket = Some(path);
+        }
+        if self.no_cache {
+            config.cache.enabled = false;
+        }
+        if let Some(capacity) = self.cache_capacity {
+            config.cache.capacity = capacity;
+        }
+        if let Some(ttl) = self.cache_ttl {
+            config.cache.ttl_secs = ttl;
+        }
+    }
+}
+
+/// Subcommands.
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run the web service (the default).
+    Serve(ServeArgs),
+
+    /// Load recipes from a JSON array or NDJSON (`.ndjson`, `.jsonl`) file.
+    Import {
+        /// File to read.
+        file: PathBuf,
+
+        /// How to treat recipes whose id already exists.
+        #[arg(long, value_enum, default_value_t)]
+        mode: import::ImportMode,
+
+        /// Report what would happen without committing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Write recipes to a file or standard output.
+    Export {
+        /// Output encoding.
+        #[arg(short, long, value_enum, default_value_t)]
+        format: export::ExportFormat,
+
+        /// Only export recipes carrying at least one of these tags.
+        #[arg(long, value_delimiter = ',')]
+        tags: Vec<String>,
+
+        /// Only export recipes modified at or after this RFC 3339 time.
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+
+        /// File to write; standard output if omitted.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Apply, revert or inspect database migrations.
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateCommand,
+    },
+
+    /// Manage users.
+    User {
+        #[command(subcommand)]
+        action: UserCommand,
+    },
+
+    /// Manage API keys.
+    Apikey {
+        #[command(subcommand)]
+        action: ApiKeyCommand,
+    },
+
+    /// Back up or restore the database file.
+    Db {
+        #[command(subcommand)]
+        action: DbCommand,
+    },
+
+    /// Check that the server could start with the given settings.
+    CheckConfig(ServeArgs),
+}
+
+/// `migrate` subcommands.
+#[derive(clap::Subcommand)]
+enum MigrateCommand {
+    /// Apply all pending migrations.
+    Up,
+    /// Revert migrations newer than a target version.
+    Down {
+        /// Version to revert to; defaults to the one before the latest applied.
+        #[arg(long)]
+        target: Option<i64>,
+    },
+    /// List migrations and whether each has been applied.
+    Status,
+}
+
+/// `user` subcommands.
+#[derive(clap::Subcommand)]
+enum UserCommand {
+    /// Add a user.
+    Add {
+        /// Email address identifying the user.
+        email: String,
+        /// Full name of the user.
+        #[arg(long)]
+        name: String,
+        /// Role to grant.
+        #[arg(long, value_enum, default_value_t)]
+        role: users::Role,
+    },
+    /// List users.
+    List,
+    /// Change a user's role.
+    SetRole {
+        /// Email address identifying the user.
+        email: String,
+        /// Role to grant.
+        #[arg(value_enum)]
+        role: users::Role,
+    },
+}
+
+/// `apikey` subcommands.
+#[derive(clap::Subcommand)]
+enum ApiKeyCommand {
+    /// Create an API key for a user and print it once.
+    Create {
+        /// Email address of the owning user.
+        email: String,
+    },
+    /// Revoke an API key.
+    Revoke {
+        /// Id of the key, as printed by `create`.
+        id: String,
+    },
+}
+
+/// `db` subcommands.
+#[derive(clap::Subcommand)]
+enum DbCommand {
+    /// Write a consistent copy of the database while it is in use.
+    Backup {
+        /// File to create, gzip-compressed if it ends in `.gz`. If omitted,
+        /// a timestamped backup is written to the backup directory and old
+        /// ones there are rotated out.
+        output: Option<PathBuf>,
+    },
+    /// Check a backup, which may be gzip-compressed, and replace the
+    /// database with it. Stop the server first.
+    Restore {
+        /// Backup file to restore from.
+        input: PathBuf,
+    },
+}
+
+impl Command {
+    /// Name reported in JSON output.
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Serve(_) => "serve",
+            Command::Import { .. } => "import",
+            Command::Export { .. } => "export",
+            Command::Migrate { .. } => "migrate",
+            Command::User { .. } => "user",
+            Command::Apikey { .. } => "apikey",
+            Command::Db { .. } => "db",
+            Command::CheckConfig(_) => "check-config",
+        }
+    }
+}
+
+/// Result of a successful command.
+struct Output {
+    status: u8,
+    result: serde_json::Value,
+    text: String,
+}
+
+impl Output {
+    /// A successful result with its human-readable rendering.
+    fn new<T: Serialize>(result: &T, text: impl Into<String>) -> Self {
+        Self {
+            status: exit::SUCCESS,
+            result: serde_json::to_value(result).unwrap(),
+            text: text.into(),
+        }
+    }
+
+    /// Overrides the exit status, for commands that finish but report problems.
+    fn with_status(mut self, status: u8) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Failure of a command.
+struct CliError {
+    status: u8,
+    message: String,
+}
+
+impl CliError {
+    /// A failure caused by invalid input data.
+    fn data(message: impl Into<String>) -> Self {
+        Self {
+            status: exit::DATA_ERROR,
+            message: message.into(),
+        }
+    }
+
+    /// A failure caused by invalid configuration.
+    fn config(message: impl Into<String>) -> Self {
+        Self {
+            status: exit::CONFIG_ERROR,
+            message: message.into(),
+        }
+    }
+}
+
+impl<E: std::fmt::Display> From<E> for CliError {
+    fn from(e: E) -> Self {
+        Self {
+            status: exit::FAILURE,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The layers the configuration was built from at startup, kept so that
+/// it can be rebuilt on reload.
+#[derive(Clone)]
+pub struct ConfigSource {
+    path: Option<PathBuf>,
+    db_uri: Option<String>,
+    log_format: Option<config::LogFormat>,
+    serve: ServeArgs,
+}
+
+impl ConfigSource {
+    /// Rebuilds the configuration, re-reading the file and environment.
+    pub fn load(&self) -> Result<Config, String> {
+        let mut command = Command::Serve(self.serve.clone());
+        load_config(
+            self.path.as_deref(),
+            self.db_uri.clone(),
+            self.log_format,
+            &mut command,
+        )
+        .map_err(|e| e.message)
+    }
+}
+
+/// Builds the configuration from its file, the environment and the flags.
+fn load_config(
+    path: Option<&std::path::Path>,
+    db_uri: Option<String>,
+    log_format: Option<config::LogFormat>,
+    command: &mut Command,
+) -> Result<Config, CliError> {
+    let mut config = Config::load(path).map_err(|e| CliError::config(e.to_string()))?;
+    if let Some(db_uri) = db_uri {
+        config.database.uri = db_uri;
+    }
+    if let Some(format) = log_format {
+        config.logging.format = format;
+    }
+    if let Command::Serve(args) | Command::CheckConfig(args) = command {
+        args.clone().apply(&mut config);
+    }
+    Ok(config)
+}
+
+/// Runs the selected command and reports its outcome.
+pub async fn run(cli: Cli) -> ExitCode {
+    let source = cli.config_source();
+    let Cli {
+        config,
+        db_uri,
+        json,
+        log_format,
+        print_config,
+        command,
+        serve,
+    } = cli;
+    let mut command = command.unwrap_or(Command::Serve(serve));
+    let name = if print_config {
+        "print-config"
+    } else {
+        command.name()
+    };
+
+    let outcome = match load_config(config.as_deref(), db_uri, log_format, &mut command) {
+        Ok(config) if print_config => run_print_config(&config),
+        Ok(config) => match init_tracing(&config, matches!(command, Command::Serve(_))) {
+            Ok(provider) => {
+                let outcome = execute(config, json, command, source).await;
+                if let Some(provider) = provider {
+                    let flushed = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+                    if let Ok(Err(e)) = flushed {
+                        eprintln!("recipe_server: trace export: {}", e);
+                    }
+                }
+                outcome
+            }
+            Err(e) => Err(CliError::config(format!("telemetry: {}", e))),
+        },
+        Err(e) => Err(e),
+    };
+    let status = match &outcome {
+        Ok(output) => output.status,
+        Err(e) => e.status,
+    };
+    match (json, outcome) {
+        (true, Ok(output)) => {
+            let ok = if output.status == exit::SUCCESS {
+                "ok"
+            } else {
+                "error"
+            };
+            let report = serde_json::json!({
+                "status": ok,
+                "command": name,
+                "exit_code": output.status,
+                "result": output.result,
+            });
+            println!("{}", report);
+        }
+        (true, Err(e)) => {
+            let report = serde_json::json!({
+                "status": "error",
+                "command": name,
+                "exit_code": e.status,
+                "error": e.message,
+            });
+            println!("{}", report);
+        }
+        (false, Ok(output)) => {
+            if !output.text.is_empty() {
+                println!("{}", output.text);
+            }
+        }
+        (false, Err(e)) => eprintln!("recipe_server: error: {}", e.message),
+    }
+    ExitCode::from(status)
+}
+
+/// Dispatches a command.
+async fn execute(
+    config: Config,
+    json: bool,
+    command: Command,
+    source: ConfigSource,
+) -> Result<Output, CliError> {
+    let db = &config.database;
+    match command {
+        Command::Serve(args) => {
+            config
+                .validate()
+                .map_err(|e| CliError::config(e.to_string()))?;
+            let source = ConfigSource {
+                serve: args,
+                ..source
+            };
+            serve(Arc::new(config), source).await?;
+            Ok(Output::new(&(), ""))
+        }
+        Command::Import {
+            file,
+            mode,
+            dry_run,
+        } => run_import(db, file, mode, dry_run).await,
+        Command::Export {
+            format,
+            tags,
+            since,
+            output,
+        } => {
+            if json && output.is_none() {
+                return Err(CliError::config("--json requires --output for export"));
+            }
+            run_export(db, format, tags, since, output).await
+        }
+        Command::Migrate { action } => run_migrate(db, action).await,
+        Command::User { action } => run_user(db, action).await,
+        Command::Apikey { action } => run_apikey(db, action).await,
+        Command::Db { action } => run_db(db, &config.backup, action).await,
+        Command::CheckConfig(_) => run_check_config(&config),
+    }
+}
+
+/// Runs `import`.
+async fn run_import(
+    db: &config::DatabaseConfig,
+    file: PathBuf,
+    mode: import::ImportMode,
+    dry_run: bool,
+) -> Result<Output, CliError> {
+    let ndjson = matches!(
+        file.extension().and_then(|ext| ext.to_str()),
+        Some("ndjson" | "jsonl")
+    );
+    let mut items: import::ImportItems = if ndjson {
+        let reader = BufReader::new(tokio::fs::File::open(&file).await?);
+        import::ndjson_items(reader)
+    } else {
+        let recipes = read_recipes(&file).map_err(|e| CliError::data(e.to_string()))?;
+        futures_util::stream::iter(recipes.into_iter().map(Ok)).boxed()
+    };
+
+    let store = store::connect(db).await?;
+    store.migrate().await?;
+    let mut import = import::Import::new(store.as_ref(), mode, dry_run);
+    let mut results = Vec::new();
+    let mut index = 0;
+    while let Some(item) = items.next().await {
+        let result = import.recipe(index, item).await?;
+        result.log();
+        results.push(result);
+        index += 1;
+    }
+    let summary = import.finish(None).await?;
+
+    let text = format!("{:?}", summary);
+    let status = if summary.has_errors() {
+        exit::DATA_ERROR
+    } else {
+        exit::SUCCESS
+    };
+    let result = serde_json::json!({ "results": results, "summary": summary });
+    Ok(Output::new(&result, text).with_status(status))
+}
+
+/// Runs `export`.
+async fn run_export(
+    db: &config::DatabaseConfig,
+    format: export::ExportFormat,
+    tags: Vec<String>,
+    since: Option<DateTime<Utc>>,
+    output: Option<PathBuf>,
+) -> Result<Output, CliError> {
+    let store = store::connect(db).await?;
+    let filter = RecipeFilter::new(&tags, since);
+    let exported = match &output {
+        Some(path) => {
+            let mut file = tokio::fs::File::create(path).await?;
+            export::export(store.as_ref(), &filter, format, &mut file).await
+        }
+        None => export::export(store.as_ref(), &filter, format, &mut tokio::io::stdout()).await,
+    };
+    let count = exported?;
+    tracing::info!("Exported {} recipes", count);
+    let text = match &output {
+        Some(path) => format!("Exported {} recipes to {}", count, path.display()),
+        None => String::new(),
+    };
+    Ok(Output::new(&serde_json::json!({ "exported": count }), text))
+}
+
+/// One row of `migrate status`.
+#[derive(Serialize)]
+struct MigrationStatus {
+    version: i64,
+    description: String,
+    applied: bool,
+}
+
+/// Lists every known migration with its applied state.
+async fn migration_status(store: &dyn store::Store) -> Result<Vec<MigrationStatus>, CliError> {
+    let applied = store.applied_migrations().await?;
+    let status = store
+        .migrator()
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains(&m.version),
+        })
+        .collect();
+    Ok(status)
+}
+
+/// Runs `migrate`.
+async fn run_migrate(
+    db: &config::DatabaseConfig,
+    action: MigrateCommand,
+) -> Result<Output, CliError> {
+    let store = store::connect(db).await?;
+    match action {
+        MigrateCommand::Up => store.migrate().await?,
+        MigrateCommand::Down { target } => {
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    let status = migration_status(store.as_ref()).await?;
+                    let mut applied = status.iter().filter(|m| m.applied).map(|m| m.version);
+                    let latest = applied.next_back();
+                    match latest {
+                        Some(_) => applied.next_back().unwrap_or(0),
+                        None => return Err(CliError::data("No applied migrations to revert")),
+                    }
+                }
+            };
+            store.undo(target).await?;
+        }
+        MigrateCommand::Status => (),
+    }
+    let status = migration_status(store.as_ref()).await?;
+    let text = status
+        .iter()
+        .map(|m| {
+            let state = if m.applied { "applied" } else { "pending" };
+            format!("{:>4} {:<8} {}", m.version, state, m.description)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(Output::new(&status, text))
+}
+
+/// Runs `user`.
+async fn run_user(db: &config::DatabaseConfig, action: UserCommand) -> Result<Output, CliError> {
+    let store = store::connect(db).await?;
+    match action {
+        UserCommand::Add { email, name, role } => {
+            store.add_user(&email, &name, role).await?;
+            let result = serde_json::json!({ "email": email, "role": role });
+            Ok(Output::new(
+                &result,
+                format!("Added {} ({:?})", email, role),
+            ))
+        }
+        UserCommand::List => {
+            let users = store.list_users().await?;
+            let text = users
+                .iter()
+                .map(|u| format!("{:<32} {:<8?} {}", u.email, u.role, u.full_name))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(Output::new(&users, text))
+        }
+        UserCommand::SetRole { email, role } => {
+            if !store.set_role(&email, role).await? {
+                return Err(CliError::data(format!("No such user: {}", email)));
+            }
+            let result = serde_json::json!({ "email": email, "role": role });
+            Ok(Output::new(&result, format!("{} is now {:?}", email, role)))
+        }
+    }
+}
+
+/// Runs `apikey`.
+async fn run_apikey(
+    db: &config::DatabaseConfig,
+    action: ApiKeyCommand,
+) -> Result<Output, CliError> {
+    let store = store::connect(db).await?;
+    match action {
+        ApiKeyCommand::Create { email } => {
+            match users::create_api_key(store.as_ref(), &email).await? {
+                Some(key) => {
+                    let text = format!("Key {} for {}:\n{}", key.id, email, key.key);
+                    Ok(Output::new(&key, text))
+                }
+                None => Err(CliError::data(format!("No such user: {}", email))),
+            }
+        }
+        ApiKeyCommand::Revoke { id } => {
+            if !store.revoke_api_key(&id).await? {
+                return Err(CliError::data(format!("No such active key: {}", id)));
+            }
+            let result = serde_json::json!({ "revoked": id });
+            Ok(Output::new(&result, format!("Revoked key {}", id)))
+        }
+    }
+}
+
+/// Runs `db`.
+async fn run_db(
+    db: &config::DatabaseConfig,
+    config: &config::BackupConfig,
+    action: DbCommand,
+) -> Result<Output, CliError> {
+    match action {
+        DbCommand::Backup { output } => {
+            let store = store::connect(db).await?;
+            let backup = match output {
+                Some(output) => backup::write(store.as_ref(), &output).await?,
+                None => backup::rotate(store.as_ref(), config).await?,
+            };
+            let mut text = format!(
+                "Backed up to {} ({} bytes)",
+                backup.path.display(),
+                backup.bytes
+            );
+            for path in &backup.removed {
+                text.push_str(&format!("\nRemoved {}", path.display()));
+            }
+            Ok(Output::new(&backup, text))
+        }
+        DbCommand::Restore { input } => match backup::restore(&db.uri, &input).await {
+            Ok(restored) => {
+                let text = format!(
+                    "Restored {} to {} (migration {})",
+                    input.display(),
+                    restored.path.display(),
+                    restored.migration_version
+                );
+                Ok(Output::new(&restored, text))
+            }
+            Err(e @ backup::RestoreError::Invalid(_)) => Err(CliError::data(e.to_string())),
+            Err(e @ backup::RestoreError::Unsupported) => Err(CliError::config(e.to_string())),
+            Err(e) => Err(e.into()),
+        },
+    }
+}
+
+/// Runs `check-config`.
+fn run_check_config(config: &Config) -> Result<Output, CliError> {
+    match config.validate() {
+        Ok(()) => Ok(Output::new(&config.redacted(), "Configuration OK")),
+        Err(errors) => {
+            let text = errors.to_string();
+            Ok(Output::new(&errors, text).with_status(exit::CONFIG_ERROR))
+        }
+    }
+}
+
+/// Runs `--print-config`.
+fn run_print_config(config: &Config) -> Result<Output, CliError> {
+    let config = config.redacted();
+    let text = toml::to_string(&config)?;
+    Ok(Output::new(&config, text.trim_end()))
+}
@@ -0,0 +1,108 @@
+//! Conditional request support for recipe resources.
+//!
+//! Computes strong entity tags from recipe content and `Last-Modified`
+//! times from the `updated_at` column, and evaluates `If-None-Match`,
+//! `If-Modified-Since` and `If-Match` request headers against them.
+
+use crate::*;
+
+use axum_extra::headers::{
+    ETag, HeaderMapExt, IfMatch, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// Cache validators describing one representation of a recipe.
+pub struct Validators {
+    etag: ETag,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Computes validators for a recipe.
+    ///
+    /// `representation` distinguishes encodings of the same recipe (for
+    /// example `"json"` and `"html"`), since a strong ETag must change
+    /// whenever the response bytes do.
+    pub fn new(recipe: &JsonRecipe, representation: &str) -> Self {
+        let (r, _) = recipe.to_recipe();
+        let mut hasher = Sha256::new();
+        for field in [
+            representation,
+            &r.id,
+            &r.dish_name,
+            &r.ingredients,
+            &r.time_to_prepare,
+            &r.source,
+        ] {
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+        }
+        for tag in recipe.sorted_tags() {
+            hasher.update(tag.as_bytes());
+            hasher.update([0]);
+        }
+        let last_modified = recipe.updated_at().map(SystemTime::from);
+        Self {
+            etag: etag(hasher),
+            last_modified,
+        }
+    }
+
+    /// Computes validators for a rendered page from its bytes.
+    ///
+    /// This suits pages that show more than one recipe's content, such as
+    /// tag counts across the catalogue. They carry no `Last-Modified`, as
+    /// the recipe's time would not cover the rest of the page.
+    pub fn for_page(body: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(body);
+        Self {
+            etag: etag(hasher),
+            last_modified: None,
+        }
+    }
+
+    /// Returns `true` if the client's cached copy is still current.
+    ///
+    /// `If-None-Match` takes precedence; `If-Modified-Since` is only
+    /// consulted when it is absent, as required by RFC 9110.
+    pub fn not_modified(&self, headers: &http::HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            return !if_none_match.precondition_passes(&self.etag);
+        }
+        match (headers.typed_get::<IfModifiedSince>(), self.last_modified) {
+            (Some(since), Some(modified)) => !since.is_modified(modified),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if a write may proceed under the request's `If-Match`.
+    pub fn write_allowed(&self, headers: &http::HeaderMap) -> bool {
+        headers
+            .typed_get::<IfMatch>()
+            .is_none_or(|if_match| if_match.precondition_passes(&self.etag))
+    }
+
+    /// Attaches `ETag` and `Last-Modified` headers to a response.
+    pub fn apply(&self, mut response: response::Response) -> response::Response {
+        let headers = response.headers_mut();
+        headers.typed_insert(self.etag.clone());
+        if let Some(modified) = self.last_modified {
+            headers.typed_insert(LastModified::from(modified));
+        }
+        response
+    }
+
+    /// Builds an empty `304 Not Modified` response carrying the validators.
+    pub fn not_modified_response(&self) -> response::Response {
+        self.apply(StatusCode::NOT_MODIFIED.into_response())
+    }
+}
+
+/// Strong ETag from the first 128 bits of a digest.
+fn etag(hasher: Sha256) -> ETag {
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex).parse().unwrap()
+}
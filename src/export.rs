@@ -0,0 +1,152 @@
+//! Streaming export of the recipe catalogue.
+//!
+//! Recipes are read from the database one row at a time and encoded
+//! straight to the output, so exports of any size run in constant memory.
+//! The JSON format is the same one `read_recipes` accepts, so an export
+//! can be fed back in through the `import` subcommand or endpoint.
+
+use crate::*;
+
+use futures_util::StreamExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Error type for exports, which can fail in the database, the encoder
+/// or the output.
+pub type ExportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Output encoding for an export.
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A single JSON array of recipes.
+    #[default]
+    Json,
+    /// One JSON recipe per line.
+    Ndjson,
+    /// Comma-separated values with a header row; tags are comma-joined.
+    Csv,
+    /// A YAML sequence of recipes.
+    Yaml,
+}
+
+impl ExportFormat {
+    /// MIME type of the encoded output.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Yaml => "application/yaml",
+        }
+    }
+
+    /// Conventional file extension for the encoded output.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Yaml => "yaml",
+        }
+    }
+
+    /// Bytes written before the first recipe.
+    fn header(self) -> Result<Vec<u8>, ExportError> {
+        match self {
+            ExportFormat::Json => Ok(b"[".to_vec()),
+            ExportFormat::Csv => csv_record([
+                "id",
+                "dish_name",
+                "ingredients",
+                "time_to_prepare",
+                "source",
+                "tags",
+                "updated_at",
+            ]),
+            ExportFormat::Ndjson | ExportFormat::Yaml => Ok(Vec::new()),
+        }
+    }
+
+    /// Encodes the recipe at position `index` of the export.
+    fn record(self, index: usize, recipe: &JsonRecipe) -> Result<Vec<u8>, ExportError> {
+        match self {
+            ExportFormat::Json => {
+                let mut bytes = if index == 0 {
+                    b"\n".to_vec()
+                } else {
+                    b",\n".to_vec()
+                };
+                serde_json::to_writer(&mut bytes, recipe)?;
+                Ok(bytes)
+            }
+            ExportFormat::Ndjson => {
+                let mut bytes = serde_json::to_vec(recipe)?;
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+            ExportFormat::Csv => {
+                let (r, _) = recipe.to_recipe();
+                let tags = recipe.sorted_tags().join(",");
+                let updated_at = recipe
+                    .updated_at()
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_default();
+                csv_record([
+                    r.id.as_str(),
+                    &r.dish_name,
+                    &r.ingredients,
+                    &r.time_to_prepare,
+                    &r.source,
+                    &tags,
+                    &updated_at,
+                ])
+            }
+            ExportFormat::Yaml => {
+                Ok(serde_yaml::to_string(std::slice::from_ref(recipe))?.into_bytes())
+            }
+        }
+    }
+
+    /// Bytes written after the last recipe.
+    fn footer(self, count: usize) -> Vec<u8> {
+        match self {
+            ExportFormat::Json => b"\n]\n".to_vec(),
+            ExportFormat::Yaml if count == 0 => b"[]\n".to_vec(),
+            ExportFormat::Ndjson | ExportFormat::Csv | ExportFormat::Yaml => Vec::new(),
+        }
+    }
+}
+
+/// Encodes one CSV row, quoting fields as needed.
+fn csv_record<'a, I>(fields: I) -> Result<Vec<u8>, ExportError>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+/// Writes the recipes selected by `filter` to `out` in `format`.
+///
+/// Returns the number of recipes written.
+pub async fn export<W>(
+    store: &dyn store::RecipeStore,
+    filter: &RecipeFilter,
+    format: ExportFormat,
+    out: &mut W,
+) -> Result<usize, ExportError>
+where
+    W: AsyncWrite + Unpin,
+{
+    out.write_all(&format.header()?).await?;
+    let mut recipes = store.list(filter);
+    let mut count = 0;
+    while let Some(recipe) = recipes.next().await {
+        out.write_all(&format.record(count, &recipe?)?).await?;
+        count += 1;
+    }
+    out.write_all(&format.footer(count)).await?;
+    out.flush().await?;
+    Ok(count)
+}
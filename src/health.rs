@@ -0,0 +1,204 @@
+//! Liveness, readiness and build-information probes.
+//!
+//! These routes are mounted at the server root rather than under
+//! `/api/v1`, and outside the request tracing layer so that frequent
+//! polling by an orchestrator does not flood the logs.
+
+use crate::*;
+
+/// Commit the server was built from, or `unknown` outside a git checkout.
+const GIT_COMMIT: &str = env!("RECIPE_SERVER_GIT_COMMIT");
+
+/// Unix time of the build, honoring `SOURCE_DATE_EPOCH`.
+const BUILD_TIME: &str = env!("RECIPE_SERVER_BUILD_TIME");
+
+/// Outcome of the last readiness check, so that only changes are logged.
+static READY: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(true);
+
+/// Constructs the router for the probe endpoints.
+pub fn router() -> OpenApiRouter<SharedAppState> {
+    OpenApiRouter::new()
+        .routes(routes!(healthz))
+        .routes(routes!(readyz))
+        .routes(routes!(version))
+}
+
+/// Liveness report.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Health {
+    #[schema(example = "ok")]
+    status: &'static str,
+}
+
+/// Outcome of one readiness check.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Check {
+    ok: bool,
+    /// What was found, or why the check failed.
+    detail: String,
+}
+
+impl Check {
+    fn new(result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Self { ok: true, detail },
+            Err(detail) => Self { ok: false, detail },
+        }
+    }
+}
+
+/// Readiness report.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    /// `ready` if every check passed, otherwise `unavailable`.
+    #[schema(example = "ready")]
+    status: &'static str,
+    /// Whether a connection can be taken from the pool and queried.
+    database: Check,
+    /// Whether every embedded migration has been applied.
+    migrations: Check,
+    /// Whether the JWT secret and registration password were loaded.
+    secrets: Check,
+    /// Whether the server is still accepting work, rather than shutting
+    /// down.
+    serving: Check,
+}
+
+/// Build information.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Version {
+    /// Crate version.
+    #[schema(example = "0.1.0")]
+    version: &'static str,
+    /// Abbreviated git commit hash.
+    git_commit: &'static str,
+    /// Time the binary was built.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    build_time: Option<DateTime<Utc>>,
+    /// Latest migration applied to the database.
+    migration_version: Option<i64>,
+}
+
+/// Checks that every embedded migration has been applied.
+async fn check_migrations(store: &dyn store::Store) -> Result<String, String> {
+    let applied = store
+        .applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?;
+    let pending: Vec<String> = store
+        .migrator()
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration() && !applied.contains(&m.version))
+        .map(|m| m.version.to_string())
+        .collect();
+    if pending.is_empty() {
+        Ok(format!("{} applied", applied.len()))
+    } else {
+        Err(format!("pending: {}", pending.join(", ")))
+    }
+}
+
+/// Utoipa-documented liveness probe. Succeeds whenever the process can
+/// answer at all.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "Process is alive", body = Health),
+    )
+)]
+pub async fn healthz() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+/// Utoipa-documented readiness probe. Fails with 503 until the server can
+/// usefully handle requests.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Ready to serve", body = Readiness),
+        (status = 503, description = "Not ready", body = Readiness),
+    )
+)]
+pub async fn readyz(State(appstate): State<SharedAppState>) -> response::Response {
+    let appstate = appstate.read().await;
+    let database = appstate
+        .store
+        .ping()
+        .await
+        .map(|_| "reachable".to_string())
+        .map_err(|e| e.to_string());
+    let secrets = if appstate.reg_key.is_empty() {
+        Err("registration password is empty".to_string())
+    } else {
+        Ok("loaded".to_string())
+    };
+    let serving = if appstate.shutdown.is_draining() {
+        Err("shutting down".to_string())
+    } else {
+        Ok("serving".to_string())
+    };
+    let readiness = Readiness {
+        status: "unavailable",
+        database: Check::new(database),
+        migrations: Check::new(check_migrations(appstate.store.as_ref()).await),
+        secrets: Check::new(secrets),
+        serving: Check::new(serving),
+    };
+
+    let ready = [
+        &readiness.database,
+        &readiness.migrations,
+        &readiness.secrets,
+        &readiness.serving,
+    ]
+    .iter()
+    .all(|check| check.ok);
+    let changed = READY.swap(ready, std::sync::atomic::Ordering::Relaxed) != ready;
+    if ready {
+        if changed {
+            tracing::info!("Readiness check passed again");
+        }
+        let readiness = Readiness {
+            status: "ready",
+            ..readiness
+        };
+        (StatusCode::OK, Json(readiness)).into_response()
+    } else {
+        if changed {
+            tracing::warn!("Readiness check failed: {:?}", readiness);
+        } else {
+            tracing::debug!("Readiness check failed: {:?}", readiness);
+        }
+        (StatusCode::SERVICE_UNAVAILABLE, Json(readiness)).into_response()
+    }
+}
+
+/// Utoipa-documented build information.
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses(
+        (status = 200, description = "Build and schema versions", body = Version),
+    )
+)]
+pub async fn version(State(appstate): State<SharedAppState>) -> Json<Version> {
+    let appstate = appstate.read().await;
+    let migration_version = appstate
+        .store
+        .applied_migrations()
+        .await
+        .ok()
+        .and_then(|applied| applied.into_iter().max());
+    let build_time = BUILD_TIME
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0));
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: GIT_COMMIT,
+        build_time,
+        migration_version,
+    })
+}
@@ -0,0 +1,526 @@
+//! Bulk recipe import.
+//!
+//! An `Import` applies recipes to a database transaction one at a time,
+//! each inside its own savepoint so that a bad recipe is reported and
+//! skipped without aborting the rest. It backs both the `import`
+//! subcommand, which commits once at the end, and the
+//! `POST /recipes/import` endpoint, which commits every batch of
+//! [`BATCH_RECIPES`] so that no transaction waits on a slow client.
+
+use crate::*;
+
+use futures_util::{StreamExt, stream::BoxStream};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Stream of parsed import items, each a recipe or a parse failure.
+pub type ImportItems = BoxStream<'static, Result<JsonRecipe, String>>;
+
+/// Recipes the import endpoint applies in each transaction.
+pub const BATCH_RECIPES: usize = 100;
+
+/// How imported recipes interact with existing ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Add new recipes; skip recipes whose id already exists.
+    #[default]
+    Insert,
+    /// Add new recipes and overwrite existing ones.
+    Upsert,
+    /// Delete the whole catalogue first, then add every recipe.
+    Replace,
+}
+
+/// Outcome of importing a single recipe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Inserted,
+    Updated,
+    Skipped,
+    Error,
+}
+
+/// Per-recipe entry in an import report.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportResult {
+    /// Zero-based position of the recipe in the input.
+    index: usize,
+    /// Recipe id, if the input could be parsed far enough to find one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// What happened to the recipe.
+    status: ImportStatus,
+    /// Why the recipe was skipped or rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl ImportResult {
+    /// Logs the result at a level matching its status.
+    pub fn log(&self) {
+        let id = self.id.as_deref().unwrap_or("?");
+        let reason = self.reason.as_deref().unwrap_or("");
+        match self.status {
+            ImportStatus::Inserted | ImportStatus::Updated => {
+                tracing::debug!("Import {}: {}: {:?}", self.index, id, self.status);
+            }
+            ImportStatus::Skipped => {
+                tracing::info!("Import {}: {}: skipped: {}", self.index, id, reason);
+            }
+            ImportStatus::Error => {
+                tracing::warn!("Import {}: {}: error: {}", self.index, id, reason);
+            }
+        }
+    }
+}
+
+/// Totals reported once an import has finished.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportSummary {
+    inserted: usize,
+    updated: usize,
+    skipped: usize,
+    errors: usize,
+    /// `false` for dry runs, whose changes are rolled back.
+    committed: bool,
+}
+
+impl ImportSummary {
+    /// Returns `true` if any recipe could not be imported.
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+}
+
+/// An import in progress.
+pub struct Import<'a> {
+    store: &'a dyn store::RecipeStore,
+    /// The open transaction, if any; one is begun by the first recipe
+    /// after each commit.
+    jtx: Option<Box<dyn store::ImportTx>>,
+    mode: ImportMode,
+    dry_run: bool,
+    /// Whether the next transaction must clear the catalogue first.
+    clear: bool,
+    /// Ids written since the last commit.
+    written: Vec<String>,
+    summary: ImportSummary,
+}
+
+impl<'a> Import<'a> {
+    /// Prepares an import, which clears the catalogue first in `Replace`
+    /// mode. No transaction is begun until the first recipe.
+    pub fn new(store: &'a dyn store::RecipeStore, mode: ImportMode, dry_run: bool) -> Self {
+        Self {
+            store,
+            jtx: None,
+            mode,
+            dry_run,
+            clear: mode == ImportMode::Replace,
+            written: Vec::new(),
+            summary: ImportSummary::default(),
+        }
+    }
+
+    /// The open transaction, begun if there is none.
+    async fn transaction(&mut self) -> Result<&mut Box<dyn store::ImportTx>, sqlx::Error> {
+        let jtx = match self.jtx.take() {
+            Some(jtx) => jtx,
+            None => {
+                let jtx = self.store.begin_import(self.clear).await?;
+                // A dry run's clearing is rolled back with each batch, so
+                // every batch clears again.
+                self.clear &= self.dry_run;
+                jtx
+            }
+        };
+        Ok(self.jtx.insert(jtx))
+    }
+
+    /// Applies one input item, which is either a recipe or the reason it
+    /// could not be parsed.
+    pub async fn recipe(
+        &mut self,
+        index: usize,
+        item: Result<JsonRecipe, String>,
+    ) -> Result<ImportResult, sqlx::Error> {
+        let recipe = match item {
+            Ok(recipe) => recipe,
+            Err(reason) => {
+                return Ok(self.record(index, None, ImportStatus::Error, Some(reason)));
+            }
+        };
+        let id = Some(recipe.id().to_string());
+
+        let mode = self.mode;
+        let result = match self.transaction().await?.apply(mode, &recipe).await {
+            Ok(ImportStatus::Skipped) => {
+                let reason = "Recipe already exists".to_string();
+                self.record(index, id, ImportStatus::Skipped, Some(reason))
+            }
+            Ok(status) => {
+                self.written.push(recipe.id().to_string());
+                self.record(index, id, status, None)
+            }
+            Err(e) => self.record(index, id, ImportStatus::Error, Some(e.to_string())),
+        };
+        Ok(result)
+    }
+
+    /// Counts a result and builds its report entry.
+    fn record(
+        &mut self,
+        index: usize,
+        id: Option<String>,
+        status: ImportStatus,
+        reason: Option<String>,
+    ) -> ImportResult {
+        let count = match status {
+            ImportStatus::Inserted => &mut self.summary.inserted,
+            ImportStatus::Updated => &mut self.summary.updated,
+            ImportStatus::Skipped => &mut self.summary.skipped,
+            ImportStatus::Error => &mut self.summary.errors,
+        };
+        *count += 1;
+        ImportResult {
+            index,
+            id,
+            status,
+            reason,
+        }
+    }
+
+    /// Commits the recipes applied so far (or rolls them back for a dry
+    /// run) and drops them from the cache. The next recipe begins a new
+    /// transaction.
+    pub async fn commit(&mut self, cache: Option<&cache::RecipeCache>) -> Result<(), sqlx::Error> {
+        if self.clear && !self.dry_run {
+            // Nothing has been imported, but the catalogue is still replaced.
+            self.transaction().await?;
+        }
+        let Some(jtx) = self.jtx.take() else {
+            return Ok(());
+        };
+        if self.dry_run {
+            return jtx.rollback().await;
+        }
+        jtx.commit().await?;
+        if let Some(cache) = cache {
+            if self.mode == ImportMode::Replace {
+                cache.clear();
+            } else {
+                for recipe_id in &self.written {
+                    cache.invalidate(recipe_id);
+                }
+            }
+        }
+        self.written.clear();
+        Ok(())
+    }
+
+    /// Commits the rest of the import (or rolls it back for a dry run)
+    /// and reports the totals.
+    pub async fn finish(
+        mut self,
+        cache: Option<&cache::RecipeCache>,
+    ) -> Result<ImportSummary, sqlx::Error> {
+        self.commit(cache).await?;
+        self.summary.committed = !self.dry_run;
+        Ok(self.summary)
+    }
+}
+
+/// Parses newline-delimited JSON recipes as they arrive.
+///
+/// Blank lines are ignored, and lines longer than [`MAX_RECIPE_BYTES`]
+/// are reported as failed items without being held in memory. A read
+/// error ends the stream after reporting it as a failed item.
+pub fn ndjson_items<R>(reader: R) -> ImportItems
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    futures_util::stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        loop {
+            match read_line(&mut reader).await {
+                Ok(Some(Line::Text(line))) if line.trim_ascii().is_empty() => continue,
+                Ok(Some(Line::Text(line))) => {
+                    let item = serde_json::from_slice(&line).map_err(|e| e.to_string());
+                    return Some((item, Some(reader)));
+                }
+                Ok(Some(Line::TooLong)) => return Some((Err(too_large_reason()), Some(reader))),
+                Ok(None) => return None,
+                Err(e) => return Some((Err(e.to_string()), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Largest recipe an import accepts, in bytes of JSON.
+pub const MAX_RECIPE_BYTES: usize = 1 << 20;
+
+/// Largest import the server accepts, in bytes of JSON.
+pub const MAX_IMPORT_BYTES: usize = 64 << 20;
+
+/// Reason given for a recipe larger than [`MAX_RECIPE_BYTES`].
+fn too_large_reason() -> String {
+    format!("Recipe is larger than {} bytes", MAX_RECIPE_BYTES)
+}
+
+/// A line of input.
+enum Line {
+    /// The line, less its newline.
+    Text(Vec<u8>),
+    /// A line longer than [`MAX_RECIPE_BYTES`], which was skipped.
+    TooLong,
+}
+
+/// Reads the next line, holding at most [`MAX_RECIPE_BYTES`] of it.
+/// `None` at the end of the input.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Option<Line>> {
+    let mut line = Vec::new();
+    let mut too_long = false;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            if line.is_empty() && !too_long {
+                return Ok(None);
+            }
+            break;
+        }
+        let (chunk, end) = match buf.iter().position(|&b| b == b'\n') {
+            Some(newline) => (&buf[..newline], Some(newline + 1)),
+            None => (buf, None),
+        };
+        if !too_long && line.len() + chunk.len() <= MAX_RECIPE_BYTES {
+            line.extend_from_slice(chunk);
+        } else {
+            too_long = true;
+            line = Vec::new();
+        }
+        let consumed = end.unwrap_or(buf.len());
+        reader.consume(consumed);
+        if end.is_some() {
+            break;
+        }
+    }
+    Ok(Some(if too_long {
+        Line::TooLong
+    } else {
+        Line::Text(line)
+    }))
+}
+
+/// Parses a JSON array of recipes as it arrives, one element at a time,
+/// reporting each malformed element separately rather than rejecting the
+/// whole array.
+///
+/// Fails at once if the input does not start with an array. Elements
+/// larger than [`MAX_RECIPE_BYTES`] are reported as failed items without
+/// being held in memory. Input that ends inside the array, or a read
+/// error, ends the stream after reporting it as a failed item.
+pub async fn json_array_items<R>(reader: R) -> Result<ImportItems, String>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    let mut scanner = ArrayScanner {
+        reader,
+        pending: None,
+        done: false,
+    };
+    match scanner.next_token().await.map_err(|e| e.to_string())? {
+        Some(b'[') => {}
+        _ => return Err("Expected a JSON array of recipes".to_string()),
+    }
+    match scanner.next_token().await.map_err(|e| e.to_string())? {
+        Some(b']') => scanner.done = true,
+        first => scanner.pending = first,
+    }
+    let items = futures_util::stream::unfold(scanner, |mut scanner| async move {
+        let item = scanner.element().await?;
+        Some((item, scanner))
+    });
+    Ok(items.boxed())
+}
+
+/// Splits a JSON array into its elements, tracking nesting and strings
+/// so that only the commas and bracket of the array itself end one.
+struct ArrayScanner<R> {
+    reader: R,
+    /// A byte of the next element already read.
+    pending: Option<u8>,
+    /// Set once the array has ended.
+    done: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> ArrayScanner<R> {
+    /// Reads one byte. `None` at the end of the input.
+    async fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.take() {
+            return Ok(Some(byte));
+        }
+        let buf = self.reader.fill_buf().await?;
+        let Some(&byte) = buf.first() else {
+            return Ok(None);
+        };
+        self.reader.consume(1);
+        Ok(Some(byte))
+    }
+
+    /// Reads the next byte that is not whitespace.
+    async fn next_token(&mut self) -> std::io::Result<Option<u8>> {
+        loop {
+            match self.next_byte().await? {
+                Some(byte) if byte.is_ascii_whitespace() => continue,
+                token => return Ok(token),
+            }
+        }
+    }
+
+    /// Reads and parses the next element. `None` once the array has
+    /// ended.
+    async fn element(&mut self) -> Option<Result<JsonRecipe, String>> {
+        if self.done {
+            return None;
+        }
+        let mut element = Vec::new();
+        let mut too_large = false;
+        let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+        loop {
+            let byte = match self.next_byte().await {
+                Ok(Some(byte)) => byte,
+                Ok(None) => {
+                    self.done = true;
+                    return Some(Err("Input ended inside the array".to_string()));
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.to_string()));
+                }
+            };
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == b'"' {
+                    in_string = false;
+                }
+            } else {
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' if depth > 0 => depth -= 1,
+                    b']' => {
+                        self.done = true;
+                        break;
+                    }
+                    b',' if depth == 0 => break,
+                    _ => {}
+                }
+            }
+            if too_large {
+                continue;
+            }
+            if element.len() < MAX_RECIPE_BYTES {
+                element.push(byte);
+            } else {
+                too_large = true;
+                element = Vec::new();
+            }
+        }
+        if too_large {
+            return Some(Err(too_large_reason()));
+        }
+        Some(serde_json::from_slice(&element).map_err(|e| e.to_string()))
+    }
+}
+
+/// Serializes one report record as a line of NDJSON.
+pub fn ndjson_line<T: Serialize>(record: &T) -> String {
+    let mut line = serde_json::to_string(record).unwrap();
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recipe element with id `id` and dish name `dish_name`, which is
+    /// inserted as JSON string content.
+    fn element(id: &str, dish_name: &str) -> String {
+        format!(
+            r#"{{"id": "{}", "dish_name": "{}", "ingredients": "eggs", "time_to_prepare": "5m", "tags": ["a", "b"], "source": "test"}}"#,
+            id, dish_name
+        )
+    }
+
+    /// Scans `input`, reading it `chunk` bytes at a time.
+    async fn scan(input: String, chunk: usize) -> Vec<Result<JsonRecipe, String>> {
+        let reader = tokio::io::BufReader::with_capacity(chunk, std::io::Cursor::new(input));
+        json_array_items(reader).await.unwrap().collect().await
+    }
+
+    /// Ids of the parsed items, with `None` for failed ones.
+    fn ids(items: &[Result<JsonRecipe, String>]) -> Vec<Option<&str>> {
+        items
+            .iter()
+            .map(|item| item.as_ref().ok().map(|recipe| recipe.id()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn strings_hide_brackets_commas_and_quotes() {
+        let tricky = r#"Pie ], \"best\", {ever} \\"#;
+        let input = format!("[{}, {}]", element("r1", tricky), element("r2", "[,]"));
+        let items = scan(input, 4096).await;
+        assert_eq!(ids(&items), [Some("r1"), Some("r2")]);
+        let (recipe, _) = items[0].as_ref().unwrap().to_recipe();
+        assert_eq!(recipe.dish_name, r#"Pie ], "best", {ever} \"#);
+    }
+
+    #[tokio::test]
+    async fn nested_arrays_are_single_elements() {
+        let input = format!("[[1, [2, 3]], {}]", element("r1", "Soup"));
+        let items = scan(input, 4096).await;
+        assert_eq!(ids(&items), [None, Some("r1")]);
+    }
+
+    #[tokio::test]
+    async fn elements_may_span_chunks() {
+        let input = format!(
+            "[ {} ,\n {} ]",
+            element("r1", r#"a \"quoted\" ], name"#),
+            element("r2", "Soup")
+        );
+        for chunk in [1, 2, 3, 7] {
+            let items = scan(input.clone(), chunk).await;
+            assert_eq!(ids(&items), [Some("r1"), Some("r2")], "chunk {}", chunk);
+        }
+    }
+
+    #[tokio::test]
+    async fn trailing_comma_is_a_failed_item() {
+        let input = format!("[{},]", element("r1", "Soup"));
+        let items = scan(input, 4096).await;
+        assert_eq!(ids(&items), [Some("r1"), None]);
+    }
+
+    #[tokio::test]
+    async fn unterminated_array_ends_with_a_failure() {
+        let input = format!("[{}, {}", element("r1", "Soup"), element("r2", "Stew"));
+        let items = scan(input, 4096).await;
+        assert_eq!(ids(&items), [Some("r1"), None]);
+        assert_eq!(
+            items[1].as_ref().unwrap_err(),
+            "Input ended inside the array"
+        );
+
+        let input = format!("[{}, {{\"id\": \"r2", element("r1", "Soup"));
+        let items = scan(input, 4096).await;
+        assert_eq!(ids(&items), [Some("r1"), None]);
+    }
+}
@@ -0,0 +1,136 @@
+//! Listening sockets.
+//!
+//! The server listens on the first of: a socket inherited from systemd
+//! socket activation (`LISTEN_FDS`), the configured Unix domain socket, or
+//! `ip:port`.
+
+use crate::*;
+
+use std::io;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// First file descriptor passed by systemd socket activation.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// A bound listening socket.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Where the server is listening, and what to remove on shutdown.
+pub struct Bound {
+    pub listener: Listener,
+    /// Human-readable address for logging.
+    pub endpoint: String,
+    /// Socket file created by the server, to be removed on shutdown.
+    pub socket_path: Option<PathBuf>,
+}
+
+/// Binds the listening socket described by `config`.
+pub async fn bind(config: &config::ServerConfig) -> io::Result<Bound> {
+    #[cfg(unix)]
+    {
+        if let Some(bound) = inherited()? {
+            return Ok(bound);
+        }
+        if let Some(path) = &config.unix_socket {
+            return bind_unix(path, &config.unix_socket_mode);
+        }
+    }
+    let endpoint = format!("{}:{}", config.ip, config.port);
+    let listener = TcpListener::bind(&endpoint).await?;
+    Ok(Bound {
+        listener: Listener::Tcp(listener),
+        endpoint,
+        socket_path: None,
+    })
+}
+
+/// Binds a Unix domain socket at `path` with permissions `mode` (octal).
+///
+/// A stale socket left by an unclean exit is replaced; any other file at
+/// `path` is an error.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, mode: &str) -> io::Result<Bound> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let mode =
+        u32::from_str_radix(mode, 8).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(Bound {
+        listener: Listener::Unix(listener),
+        endpoint: format!("unix:{}", path.display()),
+        socket_path: Some(path.to_path_buf()),
+    })
+}
+
+/// Takes over the socket passed by systemd, if this process was socket
+/// activated.
+///
+/// Only the first socket is used. Its file belongs to systemd, so it is
+/// not removed on shutdown.
+#[cfg(unix)]
+fn inherited() -> io::Result<Option<Bound>> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !for_us || count < 1 {
+        return Ok(None);
+    }
+    if count > 1 {
+        tracing::warn!("systemd passed {} sockets; using only the first", count);
+    }
+
+    // SAFETY: systemd guarantees the descriptor is an open socket that
+    // nothing else in this process owns.
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    // An address of another family means a Unix socket.
+    let bound = match tcp.local_addr() {
+        Ok(addr) => {
+            tcp.set_nonblocking(true)?;
+            Bound {
+                listener: Listener::Tcp(TcpListener::from_std(tcp)?),
+                endpoint: format!("{} (systemd)", addr),
+                socket_path: None,
+            }
+        }
+        Err(_) => {
+            // SAFETY: the descriptor was just released by `tcp`.
+            let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+            unix.set_nonblocking(true)?;
+            let endpoint = match unix.local_addr()?.as_pathname() {
+                Some(path) => format!("unix:{} (systemd)", path.display()),
+                None => "unix socket (systemd)".to_string(),
+            };
+            Bound {
+                listener: Listener::Unix(UnixListener::from_std(unix)?),
+                endpoint,
+                socket_path: None,
+            }
+        }
+    };
+    Ok(Some(bound))
+}
@@ -2,13 +2,31 @@ use tower_http::services::{ServeDir, ServeFile};
 
 mod api;
 mod authjwt;
+mod backup;
+mod cache;
+mod cli;
+mod conditional;
+mod config;
 mod error;
+mod export;
+mod health;
+mod import;
+mod listen;
+mod metrics;
+mod pantry;
 mod recipe;
+mod reload;
+mod request_id;
+mod shutdown;
+mod store;
+mod tags;
+mod telemetry;
 mod templates;
+mod tls;
+mod users;
 mod web;
 
 extern crate fastrand;
-extern crate log;
 extern crate mime;
 
 use axum::{
@@ -23,12 +41,12 @@ use axum_extra::{
 };
 use chrono::{TimeDelta, prelude::*};
 use clap::Parser;
+use conditional::Validators;
+use config::Config;
 use error::*;
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use recipe::*;
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool, migrate::MigrateDatabase, sqlite};
-use std::borrow::Cow;
 use std::sync::Arc;
 use tokio::{net, signal, sync::RwLock, time::Duration};
 use tower_http::{services, trace};
@@ -39,197 +57,134 @@ use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
-/// Command-line arguments for configuring the server.
-#[derive(Parser)]
-struct Args {
-    /// Optional path to JSON file for initializing database.
-    #[arg(long, name = "Init-from")]
-    init_from: Option<std::path::PathBuf>,
-
-    /// Optional SQLite database URI.
-    #[arg(short, long, name = "db-uri")]
-    db_uri: Option<String>,
-
-    /// IP address to bind the server to.
-    #[arg(short, long, default_value = "127.0.0.1")]
-    ip: String,
-
-    /// Port number to bind the server to.
-    #[arg(short, long, default_value = "3000")]
-    port: u16,
-}
-
 /// Shared application state structure.
 struct AppState {
-    db: SqlitePool,
+    store: Arc<dyn store::Store>,
     jwt_keys: authjwt::JwtKeys,
     reg_key: String,
     current_recipe: Recipe,
+    cache: Arc<cache::RecipeCache>,
+    config: Arc<Config>,
+    shutdown: shutdown::Shutdown,
 }
 
 type SharedAppState = Arc<RwLock<AppState>>;
 
 impl AppState {
     /// Create a new instance of `AppState`.
-    pub fn new(db: SqlitePool, jwt_keys: authjwt::JwtKeys, reg_key: String) -> Self {
+    pub fn new(
+        store: Arc<dyn store::Store>,
+        jwt_keys: authjwt::JwtKeys,
+        reg_key: String,
+        cache: Arc<cache::RecipeCache>,
+        config: Arc<Config>,
+        shutdown: shutdown::Shutdown,
+    ) -> Self {
         let current_recipe = Recipe {
             id: "placeholder-id".to_string(),
             dish_name: "Sample Dish".to_string(),
             ingredients: "example ingredient1, ingredient2".to_string(),
             time_to_prepare: "30 minutes".to_string(),
             source: "https://example.com".to_string(),
+            updated_at: Utc::now(),
         };
         Self {
-            db,
+            store,
             jwt_keys,
             reg_key,
             current_recipe,
+            cache,
+            config,
+            shutdown,
         }
     }
 }
 
-/// Determine the SQLite database URI to use.
-fn get_db_uri(db_uri: Option<&str>) -> Cow<str> {
-    if let Some(db_uri) = db_uri {
-        db_uri.into()
-    } else if let Ok(db_uri) = std::env::var("DATABASE_URL") {
-        db_uri.into()
-    } else {
-        "sqlite://db/recipes.db".into()
-    }
-}
+/// Launch the web service. On SIGHUP the configuration is reloaded from
+/// `source`.
+async fn serve(
+    config: Arc<Config>,
+    source: cli::ConfigSource,
+) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::info!("Starting...");
 
-/// Extract directory path from SQLite URI.
-fn extract_db_dir(db_uri: &str) -> Result<&str, RecipeServerError> {
-    if db_uri.starts_with("sqlite://") && db_uri.ends_with(".db") {
-        let start = db_uri.find(':').unwrap() + 3;
-        let mut path = &db_uri[start..];
-        if let Some(end) = path.rfind('/') {
-            path = &path[..end];
-        } else {
-            path = "";
-        }
-        Ok(path)
-    } else {
-        Err(RecipeServerError::InvalidDbUri(db_uri.to_string()))
-    }
-}
+    let store = store::connect(&config.database).await?;
+    tracing::info!("Using {} database", store.system());
+    store.migrate().await?;
 
-/// Handle shutdown signal (SIGINT, SIGTERM) and perform graceful cleanup.
-async fn shutdown_signal() {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("Failed to install Ctrl+C handler");
-    };
+    let jwt_keys = authjwt::make_jwt_keys(&config.auth)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("jwt keys: {}", e);
+            std::process::exit(1);
+        });
 
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("Failed to create SIGTERM handler")
-            .recv()
-            .await;
-    };
+    let reg_key = config.auth.load_reg_password().await.unwrap_or_else(|e| {
+        tracing::error!("Reg password: {}", e);
+        std::process::exit(1);
+    });
 
+    let cache = Arc::new(cache::RecipeCache::new(
+        config.cache.enabled,
+        config.cache.capacity,
+        Duration::from_secs(config.cache.ttl_secs),
+    ));
+    let shutdown = shutdown::Shutdown::new(&config.server);
+    let app_state = AppState::new(
+        store.clone(),
+        jwt_keys,
+        reg_key,
+        cache,
+        config.clone(),
+        shutdown.clone(),
+    );
+    let state = Arc::new(RwLock::new(app_state));
+    #[cfg(unix)]
+    shutdown.spawn(reload::watch(state.clone(), source));
     #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
-
-    tokio::select! {
-        _ = ctrl_c => {
-            tracing::info!("Received Ctrl+C (SIGINT) signal.");
-        },
-        _ = terminate => {
-            tracing::info!("Received SIGTERM signal.");
-        },
+    let _ = source;
+
+    let app = router(state, &config);
+
+    let listen::Bound {
+        listener,
+        endpoint,
+        socket_path,
+    } = listen::bind(&config.server).await?;
+    let signal = shutdown.clone().signal(socket_path);
+    match listener {
+        listen::Listener::Tcp(listener) if config.tls.enabled => {
+            serve_tls(listener, &endpoint, app, &config, &shutdown, signal).await?;
+        }
+        listen::Listener::Tcp(listener) => {
+            tracing::info!("Started: Listening on {}", endpoint);
+            let server = axum::serve(listener, app).with_graceful_shutdown(signal);
+            shutdown.drain(server).await?;
+        }
+        #[cfg(unix)]
+        listen::Listener::Unix(_) if config.tls.enabled => {
+            return Err("TLS is not supported on a Unix socket".into());
+        }
+        #[cfg(unix)]
+        listen::Listener::Unix(listener) => {
+            tracing::info!("Started: Listening on {}", endpoint);
+            let server = axum::serve(listener, app).with_graceful_shutdown(signal);
+            shutdown.drain(server).await?;
+        }
     }
-
-    tracing::info!("Initiating graceful shutdown...");
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    store.close().await;
     tracing::info!("Cleanup complete.");
+    Ok(())
 }
 
-/// Launch the web service.
-async fn serve() -> Result<(), Box<dyn std::error::Error>> {
-    let tsf = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
-    let tse = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| "recipe_server=debug".into());
-    tracing_subscriber::registry().with(tsf).with(tse).init();
-
-    log::info!("Starting...");
-
-    let args = Args::parse();
-
-    let db_uri = get_db_uri(args.db_uri.as_deref());
-    if !sqlite::Sqlite::database_exists(&db_uri).await? {
-        let db_dir = extract_db_dir(&db_uri)?;
-        std::fs::create_dir_all(db_dir)?;
-        sqlite::Sqlite::create_database(&db_uri).await?
-    }
-
-    let db = SqlitePool::connect(&db_uri).await?;
-    sqlx::migrate!().run(&db).await?;
-    if let Some(path) = args.init_from {
-        let recipes = read_recipes(path)?;
-        'next_recipe: for jj in recipes {
-            let mut jtx = db.begin().await?;
-            let (j, ts) = jj.to_recipe();
-            let recipe_insert = sqlx::query!(
-                "INSERT INTO recipes (id, dish_name, ingredients, time_to_prepare, source)
-                 VALUES ($1, $2, $3, $4, $5);",
-                j.id,
-                j.dish_name,
-                j.ingredients,
-                j.time_to_prepare,
-                j.source,
-            )
-            .execute(&mut *jtx)
-            .await;
-            if let Err(e) = recipe_insert {
-                eprintln!("Error: Recipe insert: {}: {}", j.id, e);
-                jtx.rollback().await?;
-                continue;
-            };
-            for t in ts {
-                let tag_insert = sqlx::query!(
-                    "INSERT INTO recipe_tags (recipe_id, tag) VALUES ($1, $2);",
-                    j.id,
-                    t,
-                )
-                .execute(&mut *jtx)
-                .await;
-                if let Err(e) = tag_insert {
-                    eprintln!("Error--Tag insert: {} {}: {}", j.id, t, e);
-                    jtx.rollback().await?;
-                    continue 'next_recipe;
-                };
-            }
-            jtx.commit().await?;
-        }
-        return Ok(());
-    }
-
-    let jwt_keys = authjwt::make_jwt_keys().await.unwrap_or_else(|_| {
-        tracing::error!("jwt keys");
-        std::process::exit(1);
-    });
-
-    let reg_key = authjwt::read_secret("REG_PASSWORD", "secrets/reg_password.txt")
-        .await
-        .unwrap_or_else(|_| {
-            tracing::error!("Reg password");
-            std::process::exit(1);
-        });
-
-    let app_state = AppState::new(db, jwt_keys, reg_key);
-    let state = Arc::new(RwLock::new(app_state));
-
+/// Build the application: the web pages, the API and its documentation,
+/// and the probes, with their middleware.
+fn router(state: SharedAppState, config: &Config) -> axum::Router {
     let trace_layer = trace::TraceLayer::new_for_http()
-        .make_span_with(trace::DefaultMakeSpan::new().level(tracing::Level::INFO))
+        .make_span_with(request_id::make_span)
         .on_response(trace::DefaultOnResponse::new().level(tracing::Level::INFO));
 
-    let cors = tower_http::cors::CorsLayer::new()
-        .allow_methods([http::Method::GET, http::Method::POST])
-        .allow_origin(tower_http::cors::Any);
+    let cors = config.cors.layer();
 
     async fn handler_404() -> axum::response::Response {
         (http::StatusCode::NOT_FOUND, "404 Not Found").into_response()
@@ -237,29 +192,32 @@ async fn serve() -> Result<(), Box<dyn std::error::Error>> {
 
     let mime_favicon = "image/vnd.microsoft.icon".parse().unwrap();
 
-    let (api_router, api) = OpenApiRouter::with_openapi(api::ApiDoc::openapi())
+    let assets = &config.assets;
+    let (api_router, mut api) = OpenApiRouter::with_openapi(api::ApiDoc::openapi())
         .nest("/api/v1", api::router())
         .split_for_parts();
+    let (probe_router, probe_api) = health::router().merge(metrics::router()).split_for_parts();
+    api.merge(probe_api);
 
     let swagger_ui = SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", api.clone());
     let redoc_ui = Redoc::with_url("/redoc", api);
     let rapidoc_ui = RapiDoc::new("/api-docs/openapi.json").path("/rapidoc");
 
-    let app = axum::Router::new()
+    axum::Router::new()
         .route("/", axum::routing::get(web::get_recipe))
-        .nest_service("/pkg", ServeDir::new("leptos_frontend/dist/pkg"))
+        .nest_service("/pkg", ServeDir::new(assets.leptos_dist.join("pkg")))
         .route(
             "/ui",
             axum::routing::get(|| async { web::serve_leptos_ui().await }),
         )
         .route_service(
             "/index.html",
-            ServeFile::new("leptos_frontend/dist/index.html"),
+            ServeFile::new(assets.leptos_dist.join("index.html")),
         )
         .fallback(handler_404)
         .route_service(
             "/favicon.ico",
-            services::ServeFile::new_with_mime("assets/static/favicon.ico", &mime_favicon),
+            services::ServeFile::new_with_mime(&assets.favicon, &mime_favicon),
         )
         .merge(swagger_ui)
         .merge(redoc_ui)
@@ -267,23 +225,115 @@ async fn serve() -> Result<(), Box<dyn std::error::Error>> {
         .merge(api_router)
         .fallback(handler_404)
         .layer(cors)
+        .layer(axum::middleware::from_fn(metrics::track_requests))
         .layer(trace_layer)
-        .with_state(state);
+        .merge(probe_router)
+        .layer(request_id::propagate_layer())
+        .layer(request_id::set_layer())
+        .with_state(state)
+}
 
-    let endpoint = format!("{}:{}", args.ip, args.port);
-    let listener = net::TcpListener::bind(&endpoint).await?;
-    log::info!("Started: Listening on {}", endpoint);
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+/// Serve `app` over HTTPS on `listener`, with certificate reloading and
+/// the optional plain-HTTP redirect.
+async fn serve_tls(
+    listener: net::TcpListener,
+    endpoint: &str,
+    app: axum::Router,
+    config: &Config,
+    shutdown: &shutdown::Shutdown,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let certificates = tls::Certificates::load(&config.tls)?;
+    let reload_interval = Duration::from_secs(config.tls.reload_interval_secs);
+    shutdown.spawn(certificates.clone().watch(reload_interval));
+    if let Some(port) = config.tls.redirect_port {
+        let redirect_endpoint = format!("{}:{}", config.server.ip, port);
+        let redirect_listener = net::TcpListener::bind(&redirect_endpoint).await?;
+        let redirect_app = tls::redirect_router(config.server.port);
+        tracing::info!("Redirecting HTTP on {} to HTTPS", redirect_endpoint);
+        let stop = shutdown.clone().stop_accepting();
+        shutdown.spawn(async move {
+            let server = axum::serve(redirect_listener, redirect_app).with_graceful_shutdown(stop);
+            if let Err(e) = server.await {
+                tracing::error!("HTTP redirect: {}", e);
+            }
+        });
+    }
+
+    let listener = tls::TlsListener::new(listener, certificates, shutdown)?;
+    tracing::info!("Started: Listening on {} (HTTPS)", endpoint);
+    let server = axum::serve(listener, app).with_graceful_shutdown(signal);
+    shutdown.drain(server).await?;
     Ok(())
 }
 
-/// Main entry point that launches the server.
-#[tokio::main]
-async fn main() {
-    if let Err(err) = serve().await {
-        eprintln!("recipe_server: error: {}", err);
-        std::process::exit(1);
+/// Handle for replacing the log filter, unless `RUST_LOG` fixed it.
+static LOG_FILTER: std::sync::OnceLock<
+    tracing_subscriber::reload::Handle<tracing_subscriber::EnvFilter, tracing_subscriber::Registry>,
+> = std::sync::OnceLock::new();
+
+/// Replace the log filter set by [`init_tracing`]. Does nothing if
+/// `RUST_LOG` is set.
+fn set_log_filter(filter: &str) {
+    if let Some(handle) = LOG_FILTER.get()
+        && let Err(e) = handle.reload(filter)
+    {
+        tracing::error!("Log filter {:?}: {}", filter, e);
+    }
+}
+
+/// Install the global tracing subscriber. `RUST_LOG` overrides the
+/// configured filter.
+///
+/// If `export` is set and telemetry is enabled, spans are also sent to the
+/// configured collector, and the returned provider must be shut down
+/// before exit.
+fn init_tracing(
+    config: &Config,
+    export: bool,
+) -> Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>, Box<dyn std::error::Error>> {
+    let logging = &config.logging;
+    let (tse, reloadable) = match tracing_subscriber::EnvFilter::try_from_default_env() {
+        Ok(tse) => (tse, false),
+        Err(_) => (logging.filter.as_str().into(), true),
+    };
+    let (tse, filter_handle) = tracing_subscriber::reload::Layer::new(tse);
+    if reloadable {
+        let _ = LOG_FILTER.set(filter_handle);
+    }
+    let (pretty, json) = match logging.format {
+        config::LogFormat::Pretty => {
+            let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+            (Some(layer), None)
+        }
+        config::LogFormat::Json => {
+            let layer = tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_writer(std::io::stderr);
+            (None, Some(layer))
+        }
+    };
+    let provider = if export && config.telemetry.enabled {
+        Some(telemetry::init(&config.telemetry)?)
+    } else {
+        None
+    };
+    tracing_subscriber::registry()
+        .with(tse)
+        .with(pretty)
+        .with(json)
+        .with(provider.as_ref().map(telemetry::layer))
+        .init();
+    if provider.is_some() {
+        tracing::info!("Exporting traces to {}", config.telemetry.endpoint());
     }
+    Ok(provider)
+}
+
+/// Main entry point that dispatches the command line.
+#[tokio::main]
+async fn main() -> std::process::ExitCode {
+    cli::run(cli::Cli::parse()).await
 }
@@ -0,0 +1,213 @@
+//! Prometheus metrics.
+//!
+//! Metrics live in a process-wide registry so that any module can record
+//! into it without threading a handle through. HTTP traffic is measured by
+//! [`track_requests`]; database, pool and catalogue figures are gathered
+//! when `/metrics` is scraped.
+
+use crate::*;
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// Every metric the server exports.
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    query_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+    auth_failures: IntCounterVec,
+    cache_lookups: IntCounterVec,
+    recipes: IntGauge,
+    tags: IntGauge,
+}
+
+/// Buckets for database queries, which are mostly well under a millisecond.
+const QUERY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let registry = Registry::new_custom(Some("recipe_server".to_string()), None).unwrap();
+
+    let http_requests = IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests by route template"),
+        &["method", "route", "status"],
+    )
+    .unwrap();
+    let http_duration = HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "HTTP request latency by route template",
+        ),
+        &["method", "route"],
+    )
+    .unwrap();
+    let query_duration = HistogramVec::new(
+        HistogramOpts::new(
+            "db_query_duration_seconds",
+            "Database latency by recipe store function",
+        )
+        .buckets(QUERY_BUCKETS.to_vec()),
+        &["function"],
+    )
+    .unwrap();
+    let pool_connections = IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Database pool connections by state"),
+        &["state"],
+    )
+    .unwrap();
+    let pool_max_connections = IntGauge::new(
+        "db_pool_max_connections",
+        "Maximum size of the database pool",
+    )
+    .unwrap();
+    let auth_failures = IntCounterVec::new(
+        Opts::new("auth_failures_total", "Rejected authentications by reason"),
+        &["reason"],
+    )
+    .unwrap();
+    let cache_lookups = IntCounterVec::new(
+        Opts::new("cache_lookups_total", "Recipe cache lookups by outcome"),
+        &["result"],
+    )
+    .unwrap();
+    let recipes = IntGauge::new("recipes", "Recipes in the catalogue").unwrap();
+    let tags = IntGauge::new("tags", "Distinct tags in the catalogue").unwrap();
+
+    let collectors: [Box<dyn prometheus::core::Collector>; 9] = [
+        Box::new(http_requests.clone()),
+        Box::new(http_duration.clone()),
+        Box::new(query_duration.clone()),
+        Box::new(pool_connections.clone()),
+        Box::new(pool_max_connections.clone()),
+        Box::new(auth_failures.clone()),
+        Box::new(cache_lookups.clone()),
+        Box::new(recipes.clone()),
+        Box::new(tags.clone()),
+    ];
+    for collector in collectors {
+        registry.register(collector).unwrap();
+    }
+
+    Metrics {
+        registry,
+        http_requests,
+        http_duration,
+        query_duration,
+        pool_connections,
+        pool_max_connections,
+        auth_failures,
+        cache_lookups,
+        recipes,
+        tags,
+    }
+});
+
+/// Starts timing a database function; the time is recorded when the
+/// returned timer is dropped.
+pub fn query_timer(function: &str) -> HistogramTimer {
+    METRICS
+        .query_duration
+        .with_label_values(&[function])
+        .start_timer()
+}
+
+/// Counts a rejected authentication.
+pub fn auth_failure(reason: &str) {
+    METRICS.auth_failures.with_label_values(&[reason]).inc();
+}
+
+/// Counts a recipe cache lookup.
+pub fn cache_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    METRICS.cache_lookups.with_label_values(&[result]).inc();
+}
+
+/// Middleware recording the count and latency of each request, labeled
+/// with the route template rather than the concrete path.
+pub async fn track_requests(request: Request, next: Next) -> response::Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let timer = METRICS
+        .http_duration
+        .with_label_values(&[&method, &route])
+        .start_timer();
+    let response = next.run(request).await;
+    timer.observe_duration();
+
+    let status = response.status().as_u16().to_string();
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, &status])
+        .inc();
+    response
+}
+
+/// Constructs the router for the metrics endpoint.
+pub fn router() -> OpenApiRouter<SharedAppState> {
+    OpenApiRouter::new().routes(routes!(get_metrics))
+}
+
+/// Utoipa-documented handler exporting metrics in the Prometheus text
+/// format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"),
+    )
+)]
+pub async fn get_metrics(State(appstate): State<SharedAppState>) -> response::Response {
+    let appstate = appstate.read().await;
+    let store = &appstate.store;
+
+    let pool = store.pool_stats();
+    let idle = i64::try_from(pool.idle).unwrap_or(i64::MAX);
+    let size = i64::from(pool.size);
+    let max = i64::from(pool.max);
+    METRICS
+        .pool_connections
+        .with_label_values(&["idle"])
+        .set(idle);
+    METRICS
+        .pool_connections
+        .with_label_values(&["active"])
+        .set(size - idle);
+    METRICS.pool_max_connections.set(max);
+
+    match store.totals().await {
+        Ok((recipes, tags)) => {
+            METRICS.recipes.set(recipes);
+            METRICS.tags.set(tags);
+        }
+        Err(e) => tracing::warn!("Catalogue totals failed: {}", e),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        tracing::error!("Metrics encoding failed: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        [(
+            http::header::CONTENT_TYPE,
+            encoder.format_type().to_string(),
+        )],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,239 @@
+//! Matching recipes against the ingredients on hand.
+//!
+//! A recipe's ingredients are read from its comma-separated `ingredients`
+//! text. Ingredients are matched by a key: the tag-style canonical name,
+//! with a plural last word made singular and common synonyms folded
+//! together, so "Parmesan cheese" and "parmesan" are the same ingredient,
+//! as are "Tomatoes" and "tomato".
+
+use crate::recipe::{JsonRecipe, RecipeFilter};
+use crate::store::RecipeStore;
+use crate::tags;
+
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use utoipa::ToSchema;
+
+/// Names folded into another, by key: each pair is a name and the
+/// ingredient it stands for.
+const SYNONYMS: &[(&str, &str)] = &[
+    ("all-purpose-flour", "flour"),
+    ("plain-flour", "flour"),
+    ("basil-leaves", "basil"),
+    ("black-pepper", "pepper"),
+    ("ground-black-pepper", "pepper"),
+    ("caster-sugar", "sugar"),
+    ("granulated-sugar", "sugar"),
+    ("white-sugar", "sugar"),
+    ("cheddar-cheese", "cheddar"),
+    ("mozzarella-cheese", "mozzarella"),
+    ("parmesan-cheese", "parmesan"),
+    ("parmigiano-reggiano", "parmesan"),
+    ("ricotta-cheese", "ricotta"),
+    ("coriander-leaves", "cilantro"),
+    ("garbanzo-bean", "chickpea"),
+    ("extra-virgin-olive-oil", "olive-oil"),
+    ("kosher-salt", "salt"),
+    ("sea-salt", "salt"),
+    ("scallion", "green-onion"),
+    ("spring-onion", "green-onion"),
+];
+
+/// A recipe sharing ingredients with the pantry.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PantryMatch {
+    pub recipe: JsonRecipe,
+    /// The recipe's ingredients on hand, as it gives them.
+    #[schema(example = json!(["spaghetti", "eggs", "parmesan cheese"]))]
+    pub owned: Vec<String>,
+    /// The recipe's ingredients still needed, as it gives them.
+    #[schema(example = json!(["pancetta", "black pepper"]))]
+    pub missing: Vec<String>,
+}
+
+/// The key `ingredient` is matched by. Empty if it has no letters or
+/// digits.
+pub fn key(ingredient: &str) -> String {
+    let name = tags::canonical(ingredient);
+    if let Some(&(_, into)) = SYNONYMS.iter().find(|(from, _)| *from == name) {
+        return into.to_string();
+    }
+    let name = singular(&name);
+    match SYNONYMS.iter().find(|(from, _)| *from == name) {
+        Some(&(_, into)) => into.to_string(),
+        None => name,
+    }
+}
+
+/// `name` with a plural last word made singular, roughly: "tomatoes"
+/// becomes "tomato" and "eggs" "egg", but "glass" is left alone.
+fn singular(name: &str) -> String {
+    if let Some(stem) = name.strip_suffix("oes") {
+        format!("{stem}o")
+    } else if name.ends_with('s') && !name.ends_with("ss") && name.len() > 3 {
+        name[..name.len() - 1].to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// The ingredients listed in `text`, each once, as given.
+pub fn ingredients(text: &str) -> Vec<(String, &str)> {
+    let mut seen = BTreeSet::new();
+    text.split([',', ';', '\n'])
+        .map(str::trim)
+        .map(|ingredient| (key(ingredient), ingredient))
+        .filter(|(key, _)| !key.is_empty() && seen.insert(key.clone()))
+        .collect()
+}
+
+/// Matches `recipe` against the `pantry` keys. `None` if it shares no
+/// ingredient with the pantry, or less than `threshold` of them.
+fn match_recipe(
+    recipe: JsonRecipe,
+    pantry: &BTreeSet<String>,
+    threshold: f64,
+) -> Option<PantryMatch> {
+    let (parts, _) = recipe.to_recipe();
+    let (mut owned, mut missing) = (Vec::new(), Vec::new());
+    for (key, name) in ingredients(&parts.ingredients) {
+        if pantry.contains(&key) {
+            owned.push(name.to_string());
+        } else {
+            missing.push(name.to_string());
+        }
+    }
+    let total = owned.len() + missing.len();
+    if owned.is_empty() || (owned.len() as f64) < threshold * total as f64 {
+        return None;
+    }
+    Some(PantryMatch {
+        recipe,
+        owned,
+        missing,
+    })
+}
+
+/// Recipes sharing at least one ingredient with `pantry`, and at least
+/// `threshold` (from 0 to 1) of their ingredients. Those using the most
+/// pantry ingredients come first, then those missing the fewest; at most
+/// `limit` are returned.
+pub async fn match_pantry(
+    store: &dyn RecipeStore,
+    pantry: &[String],
+    threshold: f64,
+    limit: usize,
+) -> Result<Vec<PantryMatch>, sqlx::Error> {
+    let pantry: BTreeSet<String> = pantry
+        .iter()
+        .map(|ingredient| key(ingredient))
+        .filter(|key| !key.is_empty())
+        .collect();
+    let filter = RecipeFilter::new(&[], None);
+    let mut recipes = store.list(&filter);
+    let mut matches = Vec::new();
+    while let Some(recipe) = recipes.next().await {
+        if let Some(found) = match_recipe(recipe?, &pantry, threshold) {
+            matches.push(found);
+        }
+    }
+    matches.sort_by(|a, b| {
+        b.owned
+            .len()
+            .cmp(&a.owned.len())
+            .then(a.missing.len().cmp(&b.missing.len()))
+            .then_with(|| a.recipe.id().cmp(b.recipe.id()))
+    });
+    matches.truncate(limit);
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    /// A recipe with id `id` listing `ingredients`.
+    fn recipe(id: &str, ingredients: &str) -> JsonRecipe {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "dish_name": format!("Dish {}", id),
+            "ingredients": ingredients,
+            "time_to_prepare": "10m",
+            "tags": [],
+            "source": "test",
+        }))
+        .unwrap()
+    }
+
+    /// Owned strings from `names`.
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn keys_fold_plurals_and_synonyms() {
+        assert_eq!(key("Tomatoes"), "tomato");
+        assert_eq!(key("eggs"), "egg");
+        assert_eq!(key("Glass"), "glass");
+        assert_eq!(key("gas"), "gas");
+        assert_eq!(key("Parmesan cheese"), "parmesan");
+        assert_eq!(key("Spring onions"), "green-onion");
+        assert_eq!(key("Extra virgin olive oil"), "olive-oil");
+        assert_eq!(key(" - "), "");
+    }
+
+    #[test]
+    fn ingredients_are_listed_once() {
+        let listed = ingredients("Eggs, flour;\nEGG , , all-purpose flour, milk");
+        assert_eq!(
+            listed,
+            [
+                ("egg".to_string(), "Eggs"),
+                ("flour".to_string(), "flour"),
+                ("milk".to_string(), "milk"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recipes_match_by_share_of_ingredients() {
+        let pantry: BTreeSet<String> = ["egg", "flour"].map(String::from).into();
+        let pancakes = || recipe("p", "eggs, plain flour, milk, sugar");
+
+        let found = match_recipe(pancakes(), &pantry, 0.0).unwrap();
+        assert_eq!(found.owned, ["eggs", "plain flour"]);
+        assert_eq!(found.missing, ["milk", "sugar"]);
+        assert!(match_recipe(pancakes(), &pantry, 0.5).is_some());
+        assert!(match_recipe(pancakes(), &pantry, 0.6).is_none());
+        assert!(match_recipe(recipe("s", "lettuce, tomatoes"), &pantry, 0.0).is_none());
+        assert!(match_recipe(recipe("e", ""), &pantry, 0.0).is_none());
+    }
+
+    #[tokio::test]
+    async fn matches_rank_by_owned_then_missing() {
+        let store = MemoryStore::new();
+        for (id, ingredients) in [
+            ("omelette", "eggs, milk, cheddar cheese"),
+            ("pancakes", "eggs, flour, milk, sugar, butter"),
+            ("crepes", "eggs, flour, milk, butter"),
+            ("toast", "bread, butter"),
+            ("scramble", "eggs, butter"),
+        ] {
+            store.add(recipe(id, ingredients)).await.unwrap();
+        }
+        let pantry = strings(&["Egg", "Flour", "Milk", "Cheddar", "salt"]);
+
+        let found = match_pantry(&store, &pantry, 0.0, 10).await.unwrap();
+        let ids: Vec<&str> = found.iter().map(|found| found.recipe.id()).collect();
+        assert_eq!(ids, ["omelette", "crepes", "pancakes", "scramble"]);
+        assert_eq!(found[1].missing, ["butter"]);
+
+        let found = match_pantry(&store, &pantry, 0.75, 10).await.unwrap();
+        let ids: Vec<&str> = found.iter().map(|found| found.recipe.id()).collect();
+        assert_eq!(ids, ["omelette", "crepes"]);
+        let found = match_pantry(&store, &pantry, 0.0, 1).await.unwrap();
+        assert_eq!(found.len(), 1);
+    }
+}
@@ -12,7 +12,7 @@ use utoipa::ToSchema;
 ///
 /// This structure includes metadata such as `tags` which are stored
 /// in a set to avoid duplicates.
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct JsonRecipe {
     id: String,
     dish_name: String,
@@ -20,18 +20,23 @@ pub struct JsonRecipe {
     time_to_prepare: String,
     tags: HashSet<String>,
     source: String,
+    /// Time of the last write, set by the server and ignored on input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    updated_at: Option<DateTime<Utc>>,
 }
 
 /// Internal application representation of a recipe.
 ///
 /// This struct is used within the application logic and database operations.
-#[derive(Clone)]
+#[derive(Clone, sqlx::FromRow)]
 pub struct Recipe {
     pub id: String,
     pub dish_name: String,
     pub ingredients: String,
     pub time_to_prepare: String,
     pub source: String,
+    pub updated_at: DateTime<Utc>,
 }
 
 /// Reads and parses a list of recipes from a JSON file.
@@ -52,9 +57,27 @@ impl JsonRecipe {
             time_to_prepare: recipe.time_to_prepare,
             tags,
             source: recipe.source,
+            updated_at: Some(recipe.updated_at),
         }
     }
 
+    /// Returns the recipe's unique identifier.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the time of the last write, if known.
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+    }
+
+    /// Returns the recipe's tags in sorted order.
+    pub fn sorted_tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self.tags.iter().map(String::deref).collect();
+        tags.sort_unstable();
+        tags
+    }
+
     /// Converts a `JsonRecipe` to a `Recipe` and an iterator of tag strings.
     pub fn to_recipe(&self) -> (Recipe, impl Iterator<Item = &str>) {
         let recipe = Recipe {
@@ -63,6 +86,7 @@ impl JsonRecipe {
             ingredients: self.ingredients.clone(),
             time_to_prepare: self.time_to_prepare.clone(),
             source: self.source.clone(),
+            updated_at: self.updated_at.unwrap_or_else(Utc::now),
         };
         let tags = self.tags.iter().map(String::deref);
         (recipe, tags)
@@ -76,99 +100,27 @@ impl axum::response::IntoResponse for &JsonRecipe {
     }
 }
 
-/// Fetch a recipe and its tags by ID from the database.
-pub async fn get(db: &SqlitePool, recipe_id: &str) -> Result<(Recipe, Vec<String>), sqlx::Error> {
-    let recipe = sqlx::query_as!(
-        Recipe,
-        r#"
-        SELECT id, dish_name, ingredients, time_to_prepare, source
-        FROM recipes
-        WHERE id = $1;
-        "#,
-        recipe_id
-    )
-    .fetch_one(db)
-    .await?;
-
-    let tags: Vec<String> = sqlx::query_scalar!(
-        "SELECT tag FROM recipe_tags WHERE recipe_id = $1;",
-        recipe_id
-    )
-    .fetch_all(db)
-    .await?;
-
-    Ok((recipe, tags))
-}
-
-/// Fetch the ID of a random recipe with matching tags from the database.
-pub async fn get_tagged<'a, I>(db: &SqlitePool, tags: I) -> Result<Option<String>, sqlx::Error>
-where
-    I: Iterator<Item = &'a str>,
-{
-    let mut jtx = db.begin().await?;
-    sqlx::query("DROP TABLE IF EXISTS qtags;")
-        .execute(&mut *jtx)
-        .await?;
-    sqlx::query("CREATE TEMPORARY TABLE qtags (tag VARCHR(200));")
-        .execute(&mut *jtx)
-        .await?;
-    for tag in tags {
-        sqlx::query("INSERT INTO qtags VALUES ($1);")
-            .bind(tag)
-            .execute(&mut *jtx)
-            .await?;
-    }
-    let recipe_ids = sqlx::query(
-        "SELECT DISTINCT recipe_id FROM recipe_tags JOIN qtags ON recipe_tags.tag = qtags.tag ORDER BY RANDOM() LIMIT 1;"
-    )
-    .fetch_all(&mut *jtx)
-    .await?;
-    let nrecipe_ids = recipe_ids.len();
-    let result = if nrecipe_ids == 1 {
-        Some(recipe_ids[0].get(0))
-    } else {
-        None
-    };
-    jtx.commit().await?;
-
-    Ok(result)
-}
-
-/// Fetch the ID of a random recipe from the database.
-pub async fn get_random(db: &SqlitePool) -> Result<String, sqlx::Error> {
-    sqlx::query_scalar!("SELECT id FROM recipes ORDER BY RANDOM() LIMIT 1;")
-        .fetch_one(db)
-        .await
+/// Selection criteria for listing recipes.
+pub struct RecipeFilter {
+    /// Select only recipes carrying at least one of these canonical tag
+    /// names, or a tag beneath one, if any.
+    pub tags: Vec<String>,
+    /// Select only recipes modified at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// `tags` as a JSON array, for backends that take lists as JSON, or
+    /// `None` if `tags` is empty.
+    pub tags_json: Option<String>,
 }
 
-/// Insert a new recipe and its tags into the database.
-pub async fn add(db: &SqlitePool, recipe: JsonRecipe) -> Result<(), sqlx::Error> {
-    let mut jtx = db.begin().await?;
-
-    sqlx::query!(
-        r#"
-        INSERT INTO recipes (id, dish_name, ingredients, time_to_prepare, source)
-        VALUES ($1, $2, $3, $4, $5);
-        "#,
-        recipe.id,
-        recipe.dish_name,
-        recipe.ingredients,
-        recipe.time_to_prepare,
-        recipe.source,
-    )
-    .execute(&mut *jtx)
-    .await?;
-
-    for tag in recipe.tags {
-        sqlx::query!(
-            r#"INSERT INTO recipe_tags (recipe_id, tag) VALUES ($1, $2);"#,
-            recipe.id,
-            tag,
-        )
-        .execute(&mut *jtx)
-        .await?;
+impl RecipeFilter {
+    /// Selects recipes carrying at least one of `tags` or a tag beneath one
+    /// (or any recipe if `tags` is empty) that were modified at or after `since`, if given.
+    pub fn new(tags: &[String], since: Option<DateTime<Utc>>) -> Self {
+        let tags = tags::canonical_all(tags.iter().map(String::as_str));
+        Self {
+            tags_json: (!tags.is_empty()).then(|| serde_json::to_string(&tags).unwrap()),
+            tags,
+            since,
+        }
     }
-
-    jtx.commit().await?;
-    Ok(())
 }
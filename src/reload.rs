@@ -0,0 +1,89 @@
+//! Configuration reload on SIGHUP.
+//!
+//! The configuration is rebuilt from the same file, environment and flags
+//! as at startup. If it is valid, the auth settings, secrets and log
+//! filter take effect at once; other sections are only read at startup, so
+//! changes to them are reported and otherwise ignored.
+
+use crate::*;
+
+/// Reload the configuration each time SIGHUP arrives.
+#[cfg(unix)]
+pub async fn watch(state: SharedAppState, source: cli::ConfigSource) {
+    let mut hangups = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::error!("Failed to create SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        tracing::info!("Received SIGHUP signal; reloading configuration.");
+        reload(&state, &source).await;
+    }
+}
+
+/// Replace the running configuration, keeping the old one on any error.
+async fn reload(state: &SharedAppState, source: &cli::ConfigSource) {
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Reload failed: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = config.validate() {
+        tracing::error!("Reload rejected: {}", e);
+        return;
+    }
+    let jwt_keys = match authjwt::make_jwt_keys(&config.auth).await {
+        Ok(jwt_keys) => jwt_keys,
+        Err(e) => {
+            tracing::error!("Reload failed: jwt keys: {}", e);
+            return;
+        }
+    };
+    let reg_key = match config.auth.load_reg_password().await {
+        Ok(reg_key) => reg_key,
+        Err(e) => {
+            tracing::error!("Reload failed: reg password: {}", e);
+            return;
+        }
+    };
+
+    let mut appstate = state.write().await;
+    for section in restart_required(&appstate.config, &config) {
+        tracing::warn!("Changes to [{}] take effect after a restart", section);
+    }
+    set_log_filter(&config.logging.filter);
+    appstate.jwt_keys = jwt_keys;
+    appstate.reg_key = reg_key;
+    appstate.config = Arc::new(config);
+    tracing::info!("Configuration reloaded.");
+}
+
+/// Names of the startup-only sections that differ between `old` and `new`.
+fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    fn value<T: Serialize>(section: &T) -> serde_json::Value {
+        serde_json::to_value(section).unwrap_or_default()
+    }
+    let sections = [
+        ("server", value(&old.server), value(&new.server)),
+        ("database", value(&old.database), value(&new.database)),
+        ("cors", value(&old.cors), value(&new.cors)),
+        ("cache", value(&old.cache), value(&new.cache)),
+        ("assets", value(&old.assets), value(&new.assets)),
+        ("telemetry", value(&old.telemetry), value(&new.telemetry)),
+        ("tls", value(&old.tls), value(&new.tls)),
+        (
+            "logging.format",
+            value(&old.logging.format),
+            value(&new.logging.format),
+        ),
+    ];
+    sections
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(name, _, _)| name)
+        .collect()
+}
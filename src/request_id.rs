@@ -0,0 +1,43 @@
+//! Request correlation ids.
+//!
+//! Every request carries an `X-Request-Id`, taken from upstream if present
+//! and generated otherwise. The id is recorded on the request's tracing
+//! span, so every log line emitted while handling it carries the id, and
+//! is echoed in the response headers. Response bodies are left as they
+//! are, so that each keeps its documented schema.
+
+use crate::*;
+
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+/// Header carrying the request id.
+pub const HEADER: &str = "x-request-id";
+
+/// Layer assigning an id to requests that arrive without one.
+pub fn set_layer() -> SetRequestIdLayer<MakeRequestUuid> {
+    SetRequestIdLayer::x_request_id(MakeRequestUuid)
+}
+
+/// Layer copying the request id onto the response.
+pub fn propagate_layer() -> PropagateRequestIdLayer {
+    PropagateRequestIdLayer::x_request_id()
+}
+
+/// Returns the request id from request headers.
+pub fn from_headers(headers: &http::HeaderMap) -> Option<&str> {
+    headers.get(HEADER).and_then(|id| id.to_str().ok())
+}
+
+/// Builds the tracing span for a request, carrying its id.
+///
+/// The span joins the caller's trace if the request has a `traceparent`.
+pub fn make_span(request: &http::Request<axum::body::Body>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = from_headers(request.headers()).unwrap_or("-"),
+    );
+    telemetry::set_parent(&span, request.headers());
+    span
+}
@@ -0,0 +1,130 @@
+//! Graceful shutdown.
+//!
+//! On SIGINT or SIGTERM the server first reports itself not ready, so load
+//! balancers stop routing to it, then keeps serving for
+//! `server.shutdown_delay_secs` while they notice. It then stops accepting
+//! connections and waits up to `server.shutdown_timeout_secs` for
+//! in-flight requests to finish, after which background tasks are
+//! cancelled and the database is closed.
+
+use crate::*;
+
+use std::future::IntoFuture;
+use std::io;
+use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
+
+/// Shutdown state shared by the server, its handlers and its tasks.
+#[derive(Clone)]
+pub struct Shutdown {
+    /// Cancelled when a shutdown signal arrives.
+    draining: CancellationToken,
+    /// Cancelled once requests have drained; background tasks stop.
+    stopped: CancellationToken,
+    delay: Duration,
+    timeout: Duration,
+}
+
+impl Shutdown {
+    /// Create shutdown state with the delay and deadline from `config`.
+    pub fn new(config: &config::ServerConfig) -> Self {
+        Self {
+            draining: CancellationToken::new(),
+            stopped: CancellationToken::new(),
+            delay: Duration::from_secs(config.shutdown_delay_secs),
+            timeout: Duration::from_secs(config.shutdown_timeout_secs),
+        }
+    }
+
+    /// Whether a shutdown signal has arrived.
+    pub fn is_draining(&self) -> bool {
+        self.draining.is_cancelled()
+    }
+
+    /// Spawn a background task that is cancelled at shutdown.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(self.stopped.clone().run_until_cancelled_owned(task));
+    }
+
+    /// Resolves once servers should stop accepting connections: the delay
+    /// after a shutdown signal, as for [`signal`](Self::signal).
+    pub async fn stop_accepting(self) {
+        self.draining.cancelled().await;
+        tokio::time::sleep(self.delay).await;
+    }
+
+    /// Wait for SIGINT or SIGTERM, then begin draining.
+    ///
+    /// `socket_path` is a Unix socket file to remove, so that no new
+    /// connections arrive. Resolves once the server should stop accepting.
+    pub async fn signal(self, socket_path: Option<PathBuf>) {
+        let ctrl_c = async {
+            signal::ctrl_c()
+                .await
+                .expect("Failed to install Ctrl+C handler");
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            signal::unix::signal(signal::unix::SignalKind::terminate())
+                .expect("Failed to create SIGTERM handler")
+                .recv()
+                .await;
+        };
+
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {
+                tracing::info!("Received Ctrl+C (SIGINT) signal.");
+            },
+            _ = terminate => {
+                tracing::info!("Received SIGTERM signal.");
+            },
+        }
+
+        tracing::info!("Initiating graceful shutdown...");
+        self.draining.cancel();
+        if let Some(path) = socket_path {
+            match std::fs::remove_file(&path) {
+                Ok(()) => tracing::info!("Removed socket {}", path.display()),
+                Err(e) => tracing::warn!("Removing socket {}: {}", path.display(), e),
+            }
+        }
+        tokio::time::sleep(self.delay).await;
+        tracing::info!("Draining connections");
+    }
+
+    /// Run `server` until it finishes draining or the deadline passes,
+    /// then cancel background tasks.
+    ///
+    /// Requests still running at the deadline are abandoned.
+    pub async fn drain<F>(&self, server: F) -> io::Result<()>
+    where
+        F: IntoFuture<Output = io::Result<()>>,
+    {
+        let server = server.into_future();
+        tokio::pin!(server);
+        let result = tokio::select! {
+            result = &mut server => result,
+            () = self.draining.cancelled() => {
+                match tokio::time::timeout(self.delay + self.timeout, &mut server).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        tracing::warn!(
+                            "Requests still running after {}s; abandoning them",
+                            self.timeout.as_secs(),
+                        );
+                        Ok(())
+                    }
+                }
+            }
+        };
+        self.stopped.cancel();
+        result
+    }
+}
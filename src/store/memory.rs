@@ -0,0 +1,1060 @@
+//! In-memory backend.
+//!
+//! Everything is held in process memory and lost on exit, which makes this
+//! backend useful for trying the server out and for exercising handlers
+//! without a database file. It has no migrations and no backups.
+
+use super::*;
+
+use futures_util::StreamExt;
+use sqlx::error::{DatabaseError, ErrorKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+/// No migrations: there is no schema to evolve.
+static MIGRATOR: Migrator = Migrator::DEFAULT;
+
+/// A write that would break a uniqueness or reference rule.
+#[derive(Debug)]
+struct Violation {
+    message: String,
+    kind: ErrorKind,
+}
+
+impl Violation {
+    fn error(kind: ErrorKind, message: String) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(Self { message, kind }))
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl DatabaseError for Violation {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self.kind {
+            ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+            ErrorKind::ForeignKeyViolation => ErrorKind::ForeignKeyViolation,
+            ErrorKind::NotNullViolation => ErrorKind::NotNullViolation,
+            ErrorKind::CheckViolation => ErrorKind::CheckViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// An API key record.
+struct ApiKey {
+    user_email: String,
+    key_hash: String,
+    revoked: bool,
+}
+
+/// The categories and tags seeded by the `tag_categories`, `tag_aliases`
+/// and `tag_hierarchy` migrations: name, whether multi-valued, description and
+/// canonical tag names.
+const SEED_CATEGORIES: &[(&str, bool, &str, &[&str])] = &[
+    (
+        "difficulty",
+        false,
+        "How hard the recipe is to make",
+        &["easy-to-make", "moderate-difficulty", "hard-to-make"],
+    ),
+    (
+        "cost",
+        false,
+        "How much the ingredients cost",
+        &["cheap", "moderate", "expensive"],
+    ),
+    ("cuisine", true, "Culinary tradition", &["italian"]),
+    (
+        "course",
+        true,
+        "Meal or course the dish is served as",
+        &[
+            "breakfast",
+            "dessert",
+            "main-course",
+            "pasta",
+            "salad",
+            "soup",
+        ],
+    ),
+    (
+        "diet",
+        true,
+        "Dietary character",
+        &["meat", "seafood", "vegan", "vegetarian"],
+    ),
+];
+
+/// The aliases seeded by the `tag_aliases` migration, with their tags.
+const SEED_ALIASES: &[(&str, &str)] = &[("meatless", "vegetarian"), ("veggie", "vegetarian")];
+
+/// The parents seeded by the `tag_hierarchy` migration, with their tags.
+const SEED_PARENTS: &[(&str, &str)] = &[("pasta", "italian"), ("pasta", "main-course")];
+
+/// A tag seen.
+#[derive(Clone)]
+struct TagEntry {
+    display_name: String,
+    category: Option<String>,
+}
+
+/// A tag category, less its tags.
+#[derive(Clone)]
+struct CategoryEntry {
+    multi_valued: bool,
+    description: String,
+}
+
+/// Recipes, the tags they use, their aliases, parents and categories.
+#[derive(Clone, Default)]
+struct Catalogue {
+    recipes: BTreeMap<String, JsonRecipe>,
+    /// Every tag seen, by canonical name. Like the database's `tags`
+    /// table, it keeps tags no recipe carries any more.
+    tags: BTreeMap<String, TagEntry>,
+    /// Canonical name of the tag each alias resolves to.
+    aliases: BTreeMap<String, String>,
+    /// Canonical names of each tag and one of its parents.
+    parents: BTreeSet<(String, String)>,
+    categories: BTreeMap<String, CategoryEntry>,
+}
+
+impl Catalogue {
+    /// Returns `recipe` stamped with the current time, with each tag
+    /// given once, by its display name, registering any new tags.
+    fn stamped(&mut self, recipe: &JsonRecipe) -> JsonRecipe {
+        let (mut recipe, tags) = recipe.to_recipe();
+        let tags = tags::entries(tags)
+            .into_iter()
+            .map(|(name, display_name)| {
+                let name = self.resolve(&name).to_string();
+                self.tag(name, display_name).display_name.clone()
+            })
+            .collect();
+        recipe.updated_at = Utc::now();
+        JsonRecipe::new(recipe, tags)
+    }
+
+    /// The tag with canonical name `name`, registered with `display_name`
+    /// if new.
+    fn tag(&mut self, name: String, display_name: &str) -> &mut TagEntry {
+        self.tags.entry(name).or_insert_with(|| TagEntry {
+            display_name: display_name.to_string(),
+            category: None,
+        })
+    }
+
+    /// The canonical name of the tag `name` resolves to.
+    fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// Canonical names of the tags `tags` resolve to.
+    fn resolve_all(&self, tags: &[String]) -> Vec<String> {
+        tags::canonical_all(tags.iter().map(String::as_str))
+            .iter()
+            .map(|name| self.resolve(name).to_string())
+            .collect()
+    }
+
+    /// The categorized tags among `tags`, in category order and canonical
+    /// order within each.
+    fn categorized(&self, tags: &[String]) -> Vec<tags::Categorized> {
+        let mut names = self.resolve_all(tags);
+        names.sort_unstable();
+        names.dedup();
+        let mut categorized: Vec<tags::Categorized> = names
+            .iter()
+            .filter_map(|name| self.tags.get(name))
+            .filter_map(|tag| {
+                let category = tag.category.as_ref()?;
+                let entry = self.categories.get(category)?;
+                Some(tags::Categorized {
+                    tag: tag.display_name.clone(),
+                    category: category.clone(),
+                    multi_valued: entry.multi_valued,
+                })
+            })
+            .collect();
+        // Stable, so canonical order holds within each category.
+        categorized.sort_by(|a, b| a.category.cmp(&b.category));
+        categorized
+    }
+
+    /// Canonical names of the tags `tags` resolve to and of every tag
+    /// beneath them.
+    fn expand_all(&self, tags: &[String]) -> Vec<String> {
+        let mut expanded = self.resolve_all(tags);
+        let mut next = 0;
+        while let Some(name) = expanded.get(next).cloned() {
+            for (tag, parent) in &self.parents {
+                if *parent == name && !expanded.contains(tag) {
+                    expanded.push(tag.clone());
+                }
+            }
+            next += 1;
+        }
+        expanded
+    }
+
+    /// Returns `true` if `tag` is `ancestor` or sits beneath it.
+    fn is_beneath(&self, tag: &str, ancestor: &str) -> bool {
+        self.expand_all(&[ancestor.to_string()])
+            .iter()
+            .any(|name| name == tag)
+    }
+
+    /// Make `alias` an alias of the tag `tag` resolves to, creating it if
+    /// new and merging any tag stored under `alias` into it. Returns
+    /// `false` if `tag` resolves to `alias` itself.
+    fn put_alias(&mut self, alias: &str, tag: &str) -> bool {
+        let target = self.resolve(tag).to_string();
+        if target == alias {
+            return false;
+        }
+        self.tag(target.clone(), &target);
+        self.merge(alias, &target);
+        self.aliases.insert(alias.to_string(), target);
+        true
+    }
+
+    /// Merge tag `from` into the tag `into` resolves to, or rename it
+    /// `into`. Returns `false` if there is no tag `from`.
+    fn merge(&mut self, from: &str, into: &str) -> bool {
+        let into = self.resolve(into).to_string();
+        if into == from {
+            return self.tags.contains_key(from);
+        }
+        let Some(entry) = self.tags.remove(from) else {
+            return false;
+        };
+        for target in self.aliases.values_mut() {
+            if target == from {
+                *target = into.clone();
+            }
+        }
+        let rename = |name: &String| {
+            if name == from {
+                into.clone()
+            } else {
+                name.clone()
+            }
+        };
+        self.parents = self
+            .parents
+            .iter()
+            .map(|(tag, parent)| (rename(tag), rename(parent)))
+            .filter(|(tag, parent)| tag != parent)
+            .collect();
+        let Some(target) = self.tags.get_mut(&into) else {
+            self.tags.insert(into, entry);
+            return true;
+        };
+        if target.category.is_none() {
+            target.category = entry.category;
+        }
+        let display_name = target.display_name.clone();
+        let from = [from.to_string()];
+        for recipe in self.recipes.values_mut() {
+            if !has_any_tag(recipe, &from) {
+                continue;
+            }
+            let (mut merged, tags) = recipe.to_recipe();
+            let tags = tags
+                .map(|tag| {
+                    if tags::canonical(tag) == from[0] {
+                        display_name.clone()
+                    } else {
+                        tag.to_string()
+                    }
+                })
+                .collect();
+            merged.updated_at = Utc::now();
+            *recipe = JsonRecipe::new(merged, tags);
+        }
+        true
+    }
+
+    /// Returns `true` if the recipe `recipe_id` passes `precondition`, or
+    /// there is none.
+    fn precondition_holds(&self, recipe_id: &str, precondition: Precondition<'_>) -> bool {
+        precondition
+            .is_none_or(|precondition| self.recipes.get(recipe_id).is_some_and(precondition))
+    }
+
+    /// Insert a new recipe.
+    fn insert(&mut self, recipe: &JsonRecipe) -> Result<(), sqlx::Error> {
+        if self.recipes.contains_key(recipe.id()) {
+            let message = format!("recipe {} already exists", recipe.id());
+            return Err(Violation::error(ErrorKind::UniqueViolation, message));
+        }
+        let recipe = self.stamped(recipe);
+        self.recipes.insert(recipe.id().to_string(), recipe);
+        Ok(())
+    }
+
+    /// Replace an existing recipe. Returns `false` if there is no such
+    /// recipe.
+    fn replace(&mut self, recipe: &JsonRecipe) -> bool {
+        if !self.recipes.contains_key(recipe.id()) {
+            return false;
+        }
+        let recipe = self.stamped(recipe);
+        self.recipes.insert(recipe.id().to_string(), recipe);
+        true
+    }
+}
+
+/// Everything stored.
+#[derive(Default)]
+struct Data {
+    catalogue: Catalogue,
+    users: BTreeMap<String, User>,
+    api_keys: BTreeMap<String, ApiKey>,
+}
+
+/// Recipes and users in process memory.
+pub struct MemoryStore {
+    data: Arc<Mutex<Data>>,
+}
+
+impl MemoryStore {
+    /// Create a store holding no recipes or users, with the standard tag
+    /// categories.
+    pub fn new() -> Self {
+        let mut catalogue = Catalogue::default();
+        for &(name, multi_valued, description, tags) in SEED_CATEGORIES {
+            let category = CategoryEntry {
+                multi_valued,
+                description: description.to_string(),
+            };
+            catalogue.categories.insert(name.to_string(), category);
+            for &tag in tags {
+                catalogue.tag(tag.to_string(), tag).category = Some(name.to_string());
+            }
+        }
+        for &(alias, tag) in SEED_ALIASES {
+            catalogue.aliases.insert(alias.to_string(), tag.to_string());
+        }
+        for &(tag, parent) in SEED_PARENTS {
+            catalogue
+                .parents
+                .insert((tag.to_string(), parent.to_string()));
+        }
+        let data = Data {
+            catalogue,
+            ..Data::default()
+        };
+        Self {
+            data: Arc::new(Mutex::new(data)),
+        }
+    }
+}
+
+/// Returns `true` if `recipe` carries any of the canonical tag names
+/// `tags`.
+fn has_any_tag(recipe: &JsonRecipe, tags: &[String]) -> bool {
+    recipe
+        .sorted_tags()
+        .into_iter()
+        .any(|tag| tags.contains(&tags::canonical(tag)))
+}
+
+/// The number of `recipes` carrying each tag they carry, by canonical
+/// name.
+fn count_tags<'a>(recipes: impl Iterator<Item = &'a JsonRecipe>) -> BTreeMap<String, i64> {
+    let mut counts = BTreeMap::new();
+    for recipe in recipes {
+        for name in tags::canonical_all(recipe.sorted_tags()) {
+            *counts.entry(name).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Pick a random element of `ids`.
+fn pick(ids: Vec<&String>) -> Option<String> {
+    if ids.is_empty() {
+        return None;
+    }
+    Some(ids[fastrand::usize(..ids.len())].clone())
+}
+
+#[async_trait]
+impl RecipeStore for MemoryStore {
+    async fn get(&self, recipe_id: &str) -> Result<(Recipe, Vec<String>), sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let recipe = data
+            .catalogue
+            .recipes
+            .get(recipe_id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let (recipe, tags) = recipe.to_recipe();
+        let tags = tags.map(str::to_string).collect();
+        Ok((recipe, tags))
+    }
+
+    async fn get_tagged(&self, tags: &[String]) -> Result<Option<String>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let tags = data.catalogue.expand_all(tags);
+        let ids = data
+            .catalogue
+            .recipes
+            .iter()
+            .filter(|(_, recipe)| has_any_tag(recipe, &tags))
+            .map(|(id, _)| id)
+            .collect();
+        Ok(pick(ids))
+    }
+
+    async fn get_random(&self) -> Result<String, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        pick(data.catalogue.recipes.keys().collect()).ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn add(&self, recipe: JsonRecipe) -> Result<(), sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        data.catalogue.insert(&recipe)
+    }
+
+    async fn update(
+        &self,
+        recipe: JsonRecipe,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        if !data.catalogue.precondition_holds(recipe.id(), precondition) {
+            return Ok(Written::PreconditionFailed);
+        }
+        if !data.catalogue.replace(&recipe) {
+            return Ok(Written::NotFound);
+        }
+        Ok(Written::Done)
+    }
+
+    async fn delete(
+        &self,
+        recipe_id: &str,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        if !data.catalogue.precondition_holds(recipe_id, precondition) {
+            return Ok(Written::PreconditionFailed);
+        }
+        if data.catalogue.recipes.remove(recipe_id).is_none() {
+            return Ok(Written::NotFound);
+        }
+        Ok(Written::Done)
+    }
+
+    fn list<'a>(
+        &'a self,
+        filter: &'a RecipeFilter,
+    ) -> BoxStream<'a, Result<JsonRecipe, sqlx::Error>> {
+        let data = self.data.lock().unwrap();
+        let tags = data.catalogue.expand_all(&filter.tags);
+        let recipes: Vec<_> = data
+            .catalogue
+            .recipes
+            .values()
+            .filter(|recipe| tags.is_empty() || has_any_tag(recipe, &tags))
+            .filter(|recipe| match (filter.since, recipe.updated_at()) {
+                (Some(since), Some(updated_at)) => updated_at >= since,
+                _ => true,
+            })
+            .cloned()
+            .map(Ok)
+            .collect();
+        futures_util::stream::iter(recipes).boxed()
+    }
+
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<JsonRecipe>, sqlx::Error> {
+        let text = text.to_lowercase();
+        let limit = usize::try_from(limit).unwrap_or(0);
+        let data = self.data.lock().unwrap();
+        let found = data
+            .catalogue
+            .recipes
+            .values()
+            .filter(|json| {
+                let (recipe, mut tags) = json.to_recipe();
+                recipe.dish_name.to_lowercase().contains(&text)
+                    || recipe.ingredients.to_lowercase().contains(&text)
+                    || tags.any(|tag| tag.to_lowercase().contains(&text))
+            })
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+
+    async fn totals(&self) -> Result<(i64, i64), sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let recipes = &data.catalogue.recipes;
+        let tags: BTreeSet<String> = recipes
+            .values()
+            .flat_map(JsonRecipe::sorted_tags)
+            .map(tags::canonical)
+            .collect();
+        Ok((recipes.len() as i64, tags.len() as i64))
+    }
+
+    /// Imports work on a copy of the catalogue, which replaces it on
+    /// commit; recipe writes made meanwhile by other callers are lost.
+    async fn begin_import(&self, replace: bool) -> Result<Box<dyn ImportTx>, sqlx::Error> {
+        let mut catalogue = self.data.lock().unwrap().catalogue.clone();
+        if replace {
+            catalogue.recipes.clear();
+        }
+        Ok(Box::new(MemoryImport {
+            data: self.data.clone(),
+            catalogue,
+        }))
+    }
+}
+
+/// A bulk import into memory.
+struct MemoryImport {
+    data: Arc<Mutex<Data>>,
+    catalogue: Catalogue,
+}
+
+#[async_trait]
+impl ImportTx for MemoryImport {
+    async fn apply(
+        &mut self,
+        mode: import::ImportMode,
+        recipe: &JsonRecipe,
+    ) -> Result<import::ImportStatus, sqlx::Error> {
+        let exists = self.catalogue.recipes.contains_key(recipe.id());
+        if exists && mode == import::ImportMode::Insert {
+            return Ok(import::ImportStatus::Skipped);
+        }
+        let tags: Vec<String> = recipe
+            .sorted_tags()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let categorized = self.catalogue.categorized(&tags);
+        tags::check_categories(&categorized).map_err(sqlx::Error::InvalidArgument)?;
+        if exists {
+            self.catalogue.replace(recipe);
+            Ok(import::ImportStatus::Updated)
+        } else {
+            self.catalogue.insert(recipe)?;
+            Ok(import::ImportStatus::Inserted)
+        }
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), sqlx::Error> {
+        self.data.lock().unwrap().catalogue = self.catalogue;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TagStore for MemoryStore {
+    async fn tag_names(&self) -> Result<Vec<String>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.catalogue.tags.keys().cloned().collect())
+    }
+
+    async fn rename_tag(&self, from: &str, into: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data.catalogue.merge(from, into))
+    }
+
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<usize, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        let catalogue = &mut data.catalogue;
+        Ok(from
+            .iter()
+            .filter(|alias| catalogue.put_alias(alias, into))
+            .count())
+    }
+
+    async fn tag_counts(&self) -> Result<Vec<tags::TagCount>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let catalogue = &data.catalogue;
+        let counts = count_tags(catalogue.recipes.values());
+        let tag_counts = catalogue
+            .tags
+            .iter()
+            .map(|(name, tag)| tags::TagCount {
+                name: name.clone(),
+                display_name: tag.display_name.clone(),
+                recipes: counts.get(name).copied().unwrap_or(0),
+            })
+            .collect();
+        Ok(tag_counts)
+    }
+
+    async fn facets(&self, selected: &[String]) -> Result<tags::Facets, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let catalogue = &data.catalogue;
+        let selected = catalogue.resolve_all(selected);
+        let matching: Vec<&JsonRecipe> = catalogue
+            .recipes
+            .values()
+            .filter(|recipe| {
+                let tags = tags::canonical_all(recipe.sorted_tags());
+                selected.iter().all(|name| tags.contains(name))
+            })
+            .collect();
+        let tags = count_tags(matching.iter().copied())
+            .into_iter()
+            .filter(|(name, _)| !selected.contains(name))
+            .map(|(name, recipes)| tags::TagCount {
+                display_name: catalogue.tags[&name].display_name.clone(),
+                name,
+                recipes,
+            })
+            .collect();
+        Ok(tags::Facets {
+            matching: matching.len() as i64,
+            tags,
+        })
+    }
+
+    async fn tag_edges(&self) -> Result<Vec<tags::TagEdge>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let edges = data
+            .catalogue
+            .parents
+            .iter()
+            .map(|(tag, parent)| tags::TagEdge {
+                tag: tag.clone(),
+                parent: parent.clone(),
+            })
+            .collect();
+        Ok(edges)
+    }
+
+    async fn add_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        let catalogue = &mut data.catalogue;
+        let tag = catalogue.resolve(tag).to_string();
+        let parent = catalogue.resolve(parent).to_string();
+        if catalogue.is_beneath(&parent, &tag) {
+            return Ok(false);
+        }
+        catalogue.tag(tag.clone(), &tag);
+        catalogue.tag(parent.clone(), &parent);
+        catalogue.parents.insert((tag, parent));
+        Ok(true)
+    }
+
+    async fn remove_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        let catalogue = &mut data.catalogue;
+        let edge = (
+            catalogue.resolve(tag).to_string(),
+            catalogue.resolve(parent).to_string(),
+        );
+        Ok(catalogue.parents.remove(&edge))
+    }
+
+    async fn aliases(&self) -> Result<Vec<tags::Alias>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let aliases = data
+            .catalogue
+            .aliases
+            .iter()
+            .map(|(alias, tag)| tags::Alias {
+                alias: alias.clone(),
+                tag: tag.clone(),
+            })
+            .collect();
+        Ok(aliases)
+    }
+
+    async fn put_alias(&self, alias: &str, tag: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data.catalogue.put_alias(alias, tag))
+    }
+
+    async fn delete_alias(&self, alias: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        Ok(data.catalogue.aliases.remove(alias).is_some())
+    }
+
+    async fn categories(&self) -> Result<Vec<tags::Category>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let catalogue = &data.catalogue;
+        let categories = catalogue
+            .categories
+            .iter()
+            .map(|(name, entry)| tags::Category {
+                name: name.clone(),
+                multi_valued: entry.multi_valued,
+                description: entry.description.clone(),
+                tags: catalogue
+                    .tags
+                    .values()
+                    .filter(|tag| tag.category.as_ref() == Some(name))
+                    .map(|tag| tag.display_name.clone())
+                    .collect(),
+            })
+            .collect();
+        Ok(categories)
+    }
+
+    async fn put_category(
+        &self,
+        name: &str,
+        multi_valued: bool,
+        description: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        let entry = CategoryEntry {
+            multi_valued,
+            description: description.to_string(),
+        };
+        data.catalogue.categories.insert(name.to_string(), entry);
+        Ok(())
+    }
+
+    async fn delete_category(&self, name: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        let catalogue = &mut data.catalogue;
+        if catalogue.categories.remove(name).is_none() {
+            return Ok(false);
+        }
+        for tag in catalogue.tags.values_mut() {
+            if tag.category.as_deref() == Some(name) {
+                tag.category = None;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn set_tag_category(
+        &self,
+        tag: &str,
+        category: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        let catalogue = &mut data.catalogue;
+        match category {
+            Some(category) => {
+                if !catalogue.categories.contains_key(category) {
+                    return Ok(false);
+                }
+                let name = catalogue.resolve(&tags::canonical(tag)).to_string();
+                let entry = catalogue.tag(name, tags::display_name(tag));
+                entry.category = Some(category.to_string());
+            }
+            None => {
+                let name = catalogue.resolve(&tags::canonical(tag)).to_string();
+                if let Some(entry) = catalogue.tags.get_mut(&name) {
+                    entry.category = None;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    async fn categorized(&self, tags: &[String]) -> Result<Vec<tags::Categorized>, sqlx::Error> {
+        Ok(self.data.lock().unwrap().catalogue.categorized(tags))
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn add_user(&self, email: &str, full_name: &str, role: Role) -> Result<(), sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        if data.users.contains_key(email) {
+            let message = format!("user {} already exists", email);
+            return Err(Violation::error(ErrorKind::UniqueViolation, message));
+        }
+        let user = User {
+            email: email.to_string(),
+            full_name: full_name.to_string(),
+            role,
+            created_at: Utc::now(),
+        };
+        data.users.insert(email.to_string(), user);
+        Ok(())
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.values().cloned().collect())
+    }
+
+    async fn set_role(&self, email: &str, role: Role) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        match data.users.get_mut(email) {
+            Some(user) => {
+                user.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn add_api_key(
+        &self,
+        id: &str,
+        email: &str,
+        key_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        if !data.users.contains_key(email) {
+            return Ok(false);
+        }
+        if data.api_keys.contains_key(id) {
+            let message = format!("API key {} already exists", id);
+            return Err(Violation::error(ErrorKind::UniqueViolation, message));
+        }
+        let key = ApiKey {
+            user_email: email.to_string(),
+            key_hash: key_hash.to_string(),
+            revoked: false,
+        };
+        data.api_keys.insert(id.to_string(), key);
+        Ok(true)
+    }
+
+    async fn revoke_api_key(&self, id: &str) -> Result<bool, sqlx::Error> {
+        let mut data = self.data.lock().unwrap();
+        match data.api_keys.get_mut(id) {
+            Some(key) if !key.revoked => {
+                key.revoked = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn api_key_user(&self, key_hash: &str) -> Result<Option<User>, sqlx::Error> {
+        let data = self.data.lock().unwrap();
+        let user = data
+            .api_keys
+            .values()
+            .find(|key| key.key_hash == key_hash && !key.revoked)
+            .and_then(|key| data.users.get(&key.user_email))
+            .cloned();
+        Ok(user)
+    }
+}
+
+#[async_trait]
+impl Maintenance for MemoryStore {
+    fn system(&self) -> &'static str {
+        "memory"
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        Ok(())
+    }
+
+    async fn undo(&self, _target: i64) -> Result<(), MigrateError> {
+        Ok(())
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<i64>, sqlx::Error> {
+        Ok(Vec::new())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: 0,
+            idle: 0,
+            max: 0,
+        }
+    }
+
+    async fn snapshot(&self, _path: &str) -> Result<(), sqlx::Error> {
+        Err(sqlx::Error::Configuration(
+            "backups are not supported for the in-memory store".into(),
+        ))
+    }
+
+    async fn close(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::recipe;
+
+    /// Sorted tags of recipe `id`, as stored.
+    async fn tags_of(store: &MemoryStore, id: &str) -> Vec<String> {
+        let (_, mut tags) = store.get(id).await.unwrap();
+        tags.sort();
+        tags
+    }
+
+    #[tokio::test]
+    async fn aliases_resolve_to_tags() {
+        let store = MemoryStore::new();
+        assert!(!store.put_alias("quick", "quick").await.unwrap());
+        // Seeded: veggie is an alias of vegetarian.
+        assert!(!store.put_alias("vegetarian", "veggie").await.unwrap());
+        // An alias of an alias resolves to the tag itself.
+        assert!(store.put_alias("veg", "veggie").await.unwrap());
+        let aliases = store.aliases().await.unwrap();
+        assert!(aliases.contains(&tags::Alias {
+            alias: "veg".to_string(),
+            tag: "vegetarian".to_string(),
+        }));
+
+        store.add(recipe("r1", &["Veg", "Soup"])).await.unwrap();
+        assert_eq!(tags_of(&store, "r1").await, ["soup", "vegetarian"]);
+    }
+
+    #[tokio::test]
+    async fn aliasing_a_tag_merges_it() {
+        let store = MemoryStore::new();
+        store.add(recipe("r1", &["Quick"])).await.unwrap();
+        store.add(recipe("r2", &["Fast", "Soup"])).await.unwrap();
+        store.add(recipe("r3", &["Fast", "Quick"])).await.unwrap();
+        assert!(store.put_alias("speedy", "fast").await.unwrap());
+
+        assert!(store.put_alias("fast", "quick").await.unwrap());
+        assert_eq!(tags_of(&store, "r2").await, ["Quick", "soup"]);
+        assert_eq!(tags_of(&store, "r3").await, ["Quick"]);
+        assert!(
+            !store
+                .tag_names()
+                .await
+                .unwrap()
+                .contains(&"fast".to_string())
+        );
+        // Aliases of the merged tag follow it.
+        let aliases = store.aliases().await.unwrap();
+        assert!(aliases.contains(&tags::Alias {
+            alias: "speedy".to_string(),
+            tag: "quick".to_string(),
+        }));
+        let counts = store.tag_counts().await.unwrap();
+        let quick = counts.iter().find(|tag| tag.name == "quick").unwrap();
+        assert_eq!(quick.recipes, 3);
+    }
+
+    #[tokio::test]
+    async fn merging_keeps_the_target() {
+        let store = MemoryStore::new();
+        store.add(recipe("r1", &["Soup", "Broth"])).await.unwrap();
+        let from = ["broth", "stock", "soup"].map(String::from);
+        // The target itself is left alone, and a name no tag has still
+        // becomes an alias.
+        assert_eq!(store.merge_tags(&from, "soup").await.unwrap(), 2);
+        assert_eq!(tags_of(&store, "r1").await, ["soup"]);
+        let filter = RecipeFilter::new(&["stock".to_string()], None);
+        assert_eq!(store.list(&filter).count().await, 1);
+        // The target takes the category of a tag merged into it if it
+        // has none.
+        store.add(recipe("r2", &["Chowder"])).await.unwrap();
+        assert!(store.put_alias("soup", "chowder").await.unwrap());
+        let categorized = store.categorized(&["chowder".to_string()]).await;
+        assert_eq!(categorized.unwrap()[0].category, "course");
+    }
+
+    #[tokio::test]
+    async fn facets_count_the_narrowed_selection() {
+        let store = MemoryStore::new();
+        store.add(recipe("r1", &["Soup", "Cheap"])).await.unwrap();
+        store.add(recipe("r2", &["Soup", "Veggie"])).await.unwrap();
+        store.add(recipe("r3", &["Salad", "Cheap"])).await.unwrap();
+        let counts = |facets: &tags::Facets| -> Vec<(String, i64)> {
+            facets
+                .tags
+                .iter()
+                .map(|tag| (tag.name.clone(), tag.recipes))
+                .collect()
+        };
+
+        let all = store.facets(&[]).await.unwrap();
+        assert_eq!(all.matching, 3);
+        assert_eq!(all.tags.len(), 4);
+        let soup = store.facets(&["SOUP".to_string()]).await.unwrap();
+        assert_eq!(soup.matching, 2);
+        let expected = [("cheap", 1), ("vegetarian", 1)].map(|(name, n)| (name.to_string(), n));
+        assert_eq!(counts(&soup), expected);
+        // Aliases select the tag they resolve to.
+        let selected = ["meatless".to_string(), "soup".to_string()];
+        let vegetarian = store.facets(&selected).await.unwrap();
+        assert_eq!(vegetarian.matching, 1);
+        assert!(vegetarian.tags.is_empty());
+        let none = store
+            .facets(&["salad".to_string(), "soup".to_string()])
+            .await;
+        assert_eq!(
+            none.unwrap(),
+            tags::Facets {
+                matching: 0,
+                tags: vec![]
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn hierarchies_reject_cycles() {
+        let store = MemoryStore::new();
+        assert!(!store.add_tag_parent("soup", "soup").await.unwrap());
+        // Seeded: pasta sits beneath italian.
+        assert!(!store.add_tag_parent("italian", "pasta").await.unwrap());
+        assert!(store.add_tag_parent("carbonara", "pasta").await.unwrap());
+        assert!(!store.add_tag_parent("italian", "carbonara").await.unwrap());
+        // Aliases resolve first, so an alias of the tag is the tag itself.
+        assert!(!store.add_tag_parent("vegetarian", "veggie").await.unwrap());
+        assert!(
+            store
+                .put_alias("spaghetti-carbonara", "carbonara")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .add_tag_parent("italian", "spaghetti-carbonara")
+                .await
+                .unwrap()
+        );
+
+        // Merging a tag into its parent drops the edge between them.
+        assert!(store.put_alias("carbonara", "pasta").await.unwrap());
+        let edges = store.tag_edges().await.unwrap();
+        assert!(edges.iter().all(|edge| edge.tag != edge.parent));
+        let tree = tags::build_tree(&store.tag_counts().await.unwrap(), &edges);
+        let italian = tree.iter().find(|node| node.name == "italian").unwrap();
+        assert_eq!(italian.children.len(), 1);
+        assert!(italian.children[0].children.is_empty());
+    }
+}
@@ -0,0 +1,339 @@
+//! Storage backends.
+//!
+//! Recipes and users live in SQLite or PostgreSQL, chosen by the scheme of
+//! `database.uri`. Each backend implements the [`Store`] traits with its
+//! own queries and carries its own migrations, under `migrations/sqlite`
+//! and `migrations/postgres`; migration versions are kept in step so that
+//! both report the same schema version. A `memory://` URI selects a
+//! throwaway in-memory store instead.
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+#[cfg(test)]
+pub(crate) mod tests;
+
+use crate::*;
+
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::pool::PoolOptions;
+use users::{Role, User};
+
+/// A test the stored recipe must pass for a write to it to proceed, made
+/// within the write's transaction so that no other write comes between.
+/// A missing recipe fails it. `None` lets every write proceed.
+pub type Precondition<'a> = Option<&'a (dyn Fn(&JsonRecipe) -> bool + Send + Sync)>;
+
+/// How a write to an existing recipe went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Written {
+    /// The recipe was written.
+    Done,
+    /// No recipe with the given ID exists.
+    NotFound,
+    /// The stored recipe failed the write's precondition.
+    PreconditionFailed,
+}
+
+/// Recipe storage.
+#[async_trait]
+pub trait RecipeStore: Send + Sync {
+    /// Fetch a recipe and its tags by ID.
+    async fn get(&self, recipe_id: &str) -> Result<(Recipe, Vec<String>), sqlx::Error>;
+
+    /// Fetch the ID of a random recipe carrying any of `tags` or of the
+    /// tags beneath them.
+    async fn get_tagged(&self, tags: &[String]) -> Result<Option<String>, sqlx::Error>;
+
+    /// Fetch the ID of a random recipe.
+    async fn get_random(&self) -> Result<String, sqlx::Error>;
+
+    /// Insert a new recipe and its tags.
+    async fn add(&self, recipe: JsonRecipe) -> Result<(), sqlx::Error>;
+
+    /// Replace an existing recipe and its tags, bumping its modification
+    /// time, if the stored recipe passes `precondition`.
+    async fn update(
+        &self,
+        recipe: JsonRecipe,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error>;
+
+    /// Remove a recipe and its tags, if the stored recipe passes
+    /// `precondition`.
+    async fn delete(
+        &self,
+        recipe_id: &str,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error>;
+
+    /// Stream the recipes selected by `filter`, with their tags, in id
+    /// order.
+    fn list<'a>(
+        &'a self,
+        filter: &'a RecipeFilter,
+    ) -> BoxStream<'a, Result<JsonRecipe, sqlx::Error>>;
+
+    /// Find up to `limit` recipes whose name, ingredients or tags contain
+    /// `text`, ignoring case, in id order.
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<JsonRecipe>, sqlx::Error>;
+
+    /// Count the recipes and distinct tags in the catalogue.
+    async fn totals(&self) -> Result<(i64, i64), sqlx::Error>;
+
+    /// Start a transaction for a bulk import, first removing every recipe
+    /// if `replace` is set.
+    async fn begin_import(&self, replace: bool) -> Result<Box<dyn ImportTx>, sqlx::Error>;
+}
+
+/// A bulk import's transaction, written atomically.
+#[async_trait]
+pub trait ImportTx: Send {
+    /// Write one recipe according to `mode`. A recipe that fails, such as
+    /// one carrying two tags of a single-valued category, leaves the
+    /// transaction as it was.
+    async fn apply(
+        &mut self,
+        mode: import::ImportMode,
+        recipe: &JsonRecipe,
+    ) -> Result<import::ImportStatus, sqlx::Error>;
+
+    /// Make every applied recipe permanent.
+    async fn commit(self: Box<Self>) -> Result<(), sqlx::Error>;
+
+    /// Discard every applied recipe.
+    async fn rollback(self: Box<Self>) -> Result<(), sqlx::Error>;
+}
+
+/// Tag, alias and category storage.
+///
+/// Tag and alias names taken here are canonical, except where noted.
+#[async_trait]
+pub trait TagStore: Send + Sync {
+    /// Names under which tags are stored, in name order. These may predate
+    /// the current canonical form.
+    async fn tag_names(&self) -> Result<Vec<String>, sqlx::Error>;
+
+    /// Move the recipes, category and aliases of the tag stored under
+    /// `from` to the tag `into` resolves to, then remove `from`. If there
+    /// is no such tag, `from` is renamed `into` instead. Returns `false` if
+    /// there is no tag `from`.
+    async fn rename_tag(&self, from: &str, into: &str) -> Result<bool, sqlx::Error>;
+
+    /// Make each of `from` an alias of the tag `into` resolves to, as
+    /// [`put_alias`](Self::put_alias) does, all in one transaction. Names
+    /// already resolving to that tag are left alone. Returns the number
+    /// of names merged.
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<usize, sqlx::Error>;
+
+    /// List every tag with the number of recipes carrying it, in
+    /// canonical order.
+    async fn tag_counts(&self) -> Result<Vec<tags::TagCount>, sqlx::Error>;
+
+    /// Count the recipes carrying every tag of `selected`, given in any
+    /// spelling, and for each other tag they carry, how many carry it too.
+    /// An unknown tag in `selected` matches no recipe; an empty `selected`
+    /// matches all.
+    async fn facets(&self, selected: &[String]) -> Result<tags::Facets, sqlx::Error>;
+
+    /// List every placement of a tag under a parent, in tag order.
+    async fn tag_edges(&self) -> Result<Vec<tags::TagEdge>, sqlx::Error>;
+
+    /// Place the tag `tag` resolves to under the one `parent` resolves to,
+    /// creating either if new. Returns `false`, changing nothing, if that
+    /// would put a tag beneath itself.
+    async fn add_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error>;
+
+    /// Take a tag out from under a parent. Returns `false` if it was not
+    /// under it.
+    async fn remove_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error>;
+
+    /// List every alias, in alias order.
+    async fn aliases(&self) -> Result<Vec<tags::Alias>, sqlx::Error>;
+
+    /// Make `alias` resolve to the tag `tag` resolves to, which is created
+    /// if new. A tag named `alias` is first merged into that tag. Returns
+    /// `false` if `tag` resolves to `alias` itself.
+    async fn put_alias(&self, alias: &str, tag: &str) -> Result<bool, sqlx::Error>;
+
+    /// Remove an alias. Returns `false` if there is no such alias.
+    async fn delete_alias(&self, alias: &str) -> Result<bool, sqlx::Error>;
+
+    /// List every category with its tags, in name order.
+    async fn categories(&self) -> Result<Vec<tags::Category>, sqlx::Error>;
+
+    /// Create a category, or change whether an existing one is
+    /// multi-valued and its description.
+    async fn put_category(
+        &self,
+        name: &str,
+        multi_valued: bool,
+        description: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Remove a category, leaving its tags uncategorized. Returns `false`
+    /// if there is no such category.
+    async fn delete_category(&self, name: &str) -> Result<bool, sqlx::Error>;
+
+    /// Place a tag, which is created if new, in a category, or take it out
+    /// of its category if `category` is `None`. Returns `false` if there is
+    /// no such category.
+    async fn set_tag_category(
+        &self,
+        tag: &str,
+        category: Option<&str>,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// The categorized tags among `tags`, in category order and canonical
+    /// order within each category.
+    async fn categorized(&self, tags: &[String]) -> Result<Vec<tags::Categorized>, sqlx::Error>;
+}
+
+/// User and API key storage.
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// Add a new user.
+    async fn add_user(&self, email: &str, full_name: &str, role: Role) -> Result<(), sqlx::Error>;
+
+    /// List all users in email order.
+    async fn list_users(&self) -> Result<Vec<User>, sqlx::Error>;
+
+    /// Change a user's role. Returns `false` if there is no such user.
+    async fn set_role(&self, email: &str, role: Role) -> Result<bool, sqlx::Error>;
+
+    /// Record an API key, by the hash of its secret, for a user. Returns
+    /// `false` if there is no such user.
+    async fn add_api_key(&self, id: &str, email: &str, key_hash: &str)
+    -> Result<bool, sqlx::Error>;
+
+    /// Revoke an API key. Returns `false` if there is no such active key.
+    async fn revoke_api_key(&self, id: &str) -> Result<bool, sqlx::Error>;
+
+    /// Look up the user owning the active API key with hash `key_hash`.
+    async fn api_key_user(&self, key_hash: &str) -> Result<Option<User>, sqlx::Error>;
+}
+
+/// Connection and schema management.
+#[async_trait]
+pub trait Maintenance: Send + Sync {
+    /// Name of the database system.
+    fn system(&self) -> &'static str;
+
+    /// Migrations for this backend.
+    fn migrator(&self) -> &'static Migrator;
+
+    /// Apply all pending migrations.
+    async fn migrate(&self) -> Result<(), MigrateError>;
+
+    /// Revert migrations newer than `target`.
+    async fn undo(&self, target: i64) -> Result<(), MigrateError>;
+
+    /// Versions of the successfully applied migrations.
+    async fn applied_migrations(&self) -> Result<Vec<i64>, sqlx::Error>;
+
+    /// Check that a connection can be taken from the pool and queried.
+    async fn ping(&self) -> Result<(), sqlx::Error>;
+
+    /// Current connection pool occupancy.
+    fn pool_stats(&self) -> PoolStats;
+
+    /// Write a consistent copy of the database to a new SQLite file at
+    /// `path`, while it is in use.
+    async fn snapshot(&self, path: &str) -> Result<(), sqlx::Error>;
+
+    /// Flush pending writes and close every connection.
+    async fn close(&self);
+}
+
+/// Everything the server needs from a backend.
+pub trait Store: RecipeStore + TagStore + UserStore + Maintenance {}
+
+impl<T: RecipeStore + TagStore + UserStore + Maintenance> Store for T {}
+
+/// Pool settings shared by the database backends.
+fn pool_options<DB: sqlx::Database>(config: &config::DatabaseConfig) -> PoolOptions<DB> {
+    let idle_timeout =
+        (config.idle_timeout_secs > 0).then(|| Duration::from_secs(config.idle_timeout_secs));
+    PoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .idle_timeout(idle_timeout)
+}
+
+/// Connection pool occupancy.
+pub struct PoolStats {
+    /// Open connections.
+    pub size: u32,
+    /// Open connections not in use.
+    pub idle: usize,
+    /// Largest number of connections allowed.
+    pub max: u32,
+}
+
+/// Backend named by a database URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+    Memory,
+}
+
+impl Backend {
+    /// Selects the backend from the scheme of `uri`, checking that an
+    /// SQLite URI names a `.db` file.
+    pub fn from_uri(uri: &str) -> Result<Self, RecipeServerError> {
+        if uri.starts_with("postgres://") || uri.starts_with("postgresql://") {
+            Ok(Backend::Postgres)
+        } else if uri.starts_with("memory://") {
+            Ok(Backend::Memory)
+        } else {
+            sqlite::extract_db_path(uri).map(|_| Backend::Sqlite)
+        }
+    }
+}
+
+/// Connect to the database at `config.uri`, creating an SQLite database
+/// if it does not exist.
+pub async fn connect(
+    config: &config::DatabaseConfig,
+) -> Result<Arc<dyn Store>, Box<dyn std::error::Error>> {
+    let store: Arc<dyn Store> = match Backend::from_uri(&config.uri)? {
+        Backend::Sqlite => Arc::new(sqlite::SqliteStore::connect(config).await?),
+        Backend::Postgres => Arc::new(postgres::PgStore::connect(config).await?),
+        Backend::Memory => Arc::new(memory::MemoryStore::new()),
+    };
+    Ok(store)
+}
+
+/// Rename tags stored under names that are no longer canonical, merging
+/// those that now share a name. Returns the number of tags renamed.
+pub async fn renormalize_tags(store: &dyn TagStore) -> Result<usize, sqlx::Error> {
+    let mut renamed = 0;
+    for name in store.tag_names().await? {
+        let canonical = tags::canonical(&name);
+        if canonical.is_empty() || canonical == name {
+            continue;
+        }
+        if store.rename_tag(&name, &canonical).await? {
+            tracing::info!("Renamed tag {:?} to {:?}", name, canonical);
+            renamed += 1;
+        }
+    }
+    Ok(renamed)
+}
+
+/// Returns a `LIKE` pattern matching any text containing `text`, with `\`
+/// as the escape character.
+fn like_pattern(text: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
@@ -0,0 +1,1090 @@
+//! PostgreSQL backend.
+//!
+//! Queries are checked at run time rather than compile time, since the
+//! `.sqlx` cache is built against SQLite only.
+
+use super::*;
+
+use futures_util::StreamExt;
+use sqlx::migrate::Migrate;
+use sqlx::postgres::{PgConnection, PgPool, Postgres};
+use sqlx::{Connection, Transaction};
+
+/// Database migrations embedded from `migrations/postgres`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+/// Longest wait for busy database connections at shutdown.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Columns selected into a [`Recipe`].
+const RECIPE_COLUMNS: &str = "id, dish_name, ingredients, time_to_prepare, source, updated_at";
+
+/// Recipes and users in a PostgreSQL database.
+pub struct PgStore {
+    db: PgPool,
+}
+
+impl PgStore {
+    /// Connect to the PostgreSQL database, which must already exist.
+    pub async fn connect(
+        config: &config::DatabaseConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let db = pool_options(config).connect(&config.uri).await?;
+        Ok(Self { db })
+    }
+}
+
+/// A recipe row with its tags, as listed.
+#[derive(sqlx::FromRow)]
+struct ListRow {
+    #[sqlx(flatten)]
+    recipe: Recipe,
+    tags: Vec<String>,
+}
+
+/// Check whether a recipe with the given ID exists, within a transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "exists_with"))]
+async fn exists_with(conn: &mut PgConnection, recipe_id: &str) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::query_timer("exists_with");
+    let found: Option<String> = sqlx::query_scalar("SELECT id FROM recipes WHERE id = $1;")
+        .bind(recipe_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(found.is_some())
+}
+
+/// Insert a new recipe and its tags, within a transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "insert_with"))]
+async fn insert_with(conn: &mut PgConnection, recipe: &JsonRecipe) -> Result<(), sqlx::Error> {
+    let _timer = metrics::query_timer("insert_with");
+    let (recipe, tags) = recipe.to_recipe();
+    sqlx::query(
+        r#"
+        INSERT INTO recipes (id, dish_name, ingredients, time_to_prepare, source, updated_at)
+        VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP);
+        "#,
+    )
+    .bind(&recipe.id)
+    .bind(&recipe.dish_name)
+    .bind(&recipe.ingredients)
+    .bind(&recipe.time_to_prepare)
+    .bind(&recipe.source)
+    .execute(&mut *conn)
+    .await?;
+
+    insert_tags_with(conn, &recipe.id, tags).await
+}
+
+/// Replace an existing recipe and its tags, within a transaction.
+///
+/// Returns `false` if no recipe with the given ID exists.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "update_with"))]
+async fn update_with(conn: &mut PgConnection, recipe: &JsonRecipe) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::query_timer("update_with");
+    let (recipe, tags) = recipe.to_recipe();
+    let updated = sqlx::query(
+        r#"
+        UPDATE recipes
+        SET dish_name = $2, ingredients = $3, time_to_prepare = $4, source = $5,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1;
+        "#,
+    )
+    .bind(&recipe.id)
+    .bind(&recipe.dish_name)
+    .bind(&recipe.ingredients)
+    .bind(&recipe.time_to_prepare)
+    .bind(&recipe.source)
+    .execute(&mut *conn)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query("DELETE FROM recipe_tags WHERE recipe_id = $1;")
+        .bind(&recipe.id)
+        .execute(&mut *conn)
+        .await?;
+    insert_tags_with(conn, &recipe.id, tags).await?;
+    Ok(true)
+}
+
+/// Fetch a recipe and its tags by ID.
+async fn get_with(
+    conn: &mut PgConnection,
+    recipe_id: &str,
+) -> Result<(Recipe, Vec<String>), sqlx::Error> {
+    let query = format!("SELECT {} FROM recipes WHERE id = $1;", RECIPE_COLUMNS);
+    let recipe: Recipe = sqlx::query_as(&query)
+        .bind(recipe_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let tags: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT display_name FROM tags
+        JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+        WHERE recipe_id = $1
+        ORDER BY name;
+        "#,
+    )
+    .bind(recipe_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok((recipe, tags))
+}
+
+/// Returns `true` if the stored recipe `recipe_id` passes `precondition`,
+/// or there is none, within a transaction. The recipe's row stays locked
+/// until the transaction ends, so no other write comes between.
+async fn precondition_holds_with(
+    conn: &mut PgConnection,
+    recipe_id: &str,
+    precondition: Precondition<'_>,
+) -> Result<bool, sqlx::Error> {
+    let Some(precondition) = precondition else {
+        return Ok(true);
+    };
+    let locked: Option<String> =
+        sqlx::query_scalar("SELECT id FROM recipes WHERE id = $1 FOR UPDATE;")
+            .bind(recipe_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    if locked.is_none() {
+        return Ok(false);
+    }
+    let (recipe, tags) = get_with(conn, recipe_id).await?;
+    Ok(precondition(&JsonRecipe::new(recipe, tags)))
+}
+
+/// Insert the tags of a recipe, resolving aliases and adding any tags not
+/// seen before, within a transaction.
+async fn insert_tags_with<'a>(
+    conn: &mut PgConnection,
+    recipe_id: &str,
+    tags: impl Iterator<Item = &'a str>,
+) -> Result<(), sqlx::Error> {
+    let (names, display_names): (Vec<String>, Vec<&str>) = tags::entries(tags).into_iter().unzip();
+    sqlx::query(
+        r#"
+        INSERT INTO tags (name, display_name)
+        SELECT * FROM UNNEST($1::text[], $2::text[]) AS new (name, display_name)
+        WHERE NOT EXISTS (SELECT 1 FROM tag_aliases WHERE alias = new.name)
+        ON CONFLICT (name) DO NOTHING;
+        "#,
+    )
+    .bind(&names)
+    .bind(&display_names)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO recipe_tags (recipe_id, tag_id)
+        SELECT DISTINCT $1, tag_id FROM tag_names WHERE name = ANY($2)
+        ON CONFLICT DO NOTHING;
+        "#,
+    )
+    .bind(recipe_id)
+    .bind(&names)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// The categorized tags among `tags`, in category order and canonical
+/// order within each, within a transaction.
+async fn categorized_with(
+    conn: &mut PgConnection,
+    tags: &[String],
+) -> Result<Vec<tags::Categorized>, sqlx::Error> {
+    let rows: Vec<CategorizedRow> = sqlx::query_as(
+        r#"
+        SELECT display_name, category, multi_valued
+        FROM tags
+        JOIN categorized_tags ON categorized_tags.tag_id = tags.id
+        JOIN tag_categories ON tag_categories.name = categorized_tags.category
+        WHERE tags.id IN (SELECT tag_id FROM tag_names WHERE name = ANY($1))
+        ORDER BY category, tags.name;
+        "#,
+    )
+    .bind(tags::canonical_all(tags.iter().map(String::as_str)))
+    .fetch_all(&mut *conn)
+    .await?;
+    let categorized = rows
+        .into_iter()
+        .map(|row| tags::Categorized {
+            tag: row.display_name,
+            category: row.category,
+            multi_valued: row.multi_valued,
+        })
+        .collect();
+    Ok(categorized)
+}
+
+/// Make `alias` an alias of the tag `tag` resolves to, creating it if
+/// new and merging any tag stored under `alias` into it, within a
+/// transaction. Returns `false` if `tag` resolves to `alias` itself.
+async fn put_alias_with(
+    conn: &mut PgConnection,
+    alias: &str,
+    tag: &str,
+) -> Result<bool, sqlx::Error> {
+    let target = resolve_or_insert_with(conn, tag).await?;
+    let existing: Option<i64> = sqlx::query_scalar("SELECT id FROM tags WHERE name = $1;")
+        .bind(alias)
+        .fetch_optional(&mut *conn)
+        .await?;
+    match existing {
+        Some(existing) if existing == target => return Ok(false),
+        Some(_) => {
+            merge_tags_with(conn, alias, tag).await?;
+        }
+        None => {}
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO tag_aliases (alias, tag_id) VALUES ($1, $2)
+        ON CONFLICT (alias) DO UPDATE SET tag_id = excluded.tag_id;
+        "#,
+    )
+    .bind(alias)
+    .bind(target)
+    .execute(&mut *conn)
+    .await?;
+    Ok(true)
+}
+
+/// Merge tag `from` into the tag `into` resolves to, or rename it `into`,
+/// within a transaction. Returns `false` if there is no tag `from`.
+async fn merge_tags_with(
+    conn: &mut PgConnection,
+    from: &str,
+    into: &str,
+) -> Result<bool, sqlx::Error> {
+    let Some(from_id): Option<i64> = sqlx::query_scalar("SELECT id FROM tags WHERE name = $1;")
+        .bind(from)
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(false);
+    };
+    let into_id: Option<i64> = sqlx::query_scalar("SELECT tag_id FROM tag_names WHERE name = $1;")
+        .bind(into)
+        .fetch_optional(&mut *conn)
+        .await?;
+    match into_id {
+        None => {
+            sqlx::query("UPDATE tags SET name = $2 WHERE id = $1;")
+                .bind(from_id)
+                .bind(into)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Some(into_id) if into_id == from_id => {}
+        Some(into_id) => {
+            sqlx::query(
+                r#"
+                UPDATE recipes SET updated_at = CURRENT_TIMESTAMP
+                WHERE id IN (SELECT recipe_id FROM recipe_tags WHERE tag_id = $1);
+                "#,
+            )
+            .bind(from_id)
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO recipe_tags (recipe_id, tag_id)
+                SELECT recipe_id, $2 FROM recipe_tags WHERE tag_id = $1
+                ON CONFLICT DO NOTHING;
+                "#,
+            )
+            .bind(from_id)
+            .bind(into_id)
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO categorized_tags (tag_id, category)
+                SELECT $2, category FROM categorized_tags WHERE tag_id = $1
+                ON CONFLICT DO NOTHING;
+                "#,
+            )
+            .bind(from_id)
+            .bind(into_id)
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query("UPDATE tag_aliases SET tag_id = $2 WHERE tag_id = $1;")
+                .bind(from_id)
+                .bind(into_id)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO tag_parents (tag_id, parent_id)
+                SELECT $2, parent_id FROM tag_parents WHERE tag_id = $1 AND parent_id <> $2
+                UNION
+                SELECT tag_id, $2 FROM tag_parents WHERE parent_id = $1 AND tag_id <> $2
+                ON CONFLICT DO NOTHING;
+                "#,
+            )
+            .bind(from_id)
+            .bind(into_id)
+            .execute(&mut *conn)
+            .await?;
+            // The rest of `from` goes by `ON DELETE CASCADE`.
+            sqlx::query("DELETE FROM tags WHERE id = $1;")
+                .bind(from_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
+/// The id of the tag `name` resolves to, adding a tag of that name if
+/// there is none, within a transaction.
+async fn resolve_or_insert_with(conn: &mut PgConnection, name: &str) -> Result<i64, sqlx::Error> {
+    let id: Option<i64> = sqlx::query_scalar("SELECT tag_id FROM tag_names WHERE name = $1;")
+        .bind(name)
+        .fetch_optional(&mut *conn)
+        .await?;
+    match id {
+        Some(id) => Ok(id),
+        None => {
+            sqlx::query_scalar(
+                "INSERT INTO tags (name, display_name) VALUES ($1, $1) RETURNING id;",
+            )
+            .bind(name)
+            .fetch_one(&mut *conn)
+            .await
+        }
+    }
+}
+
+/// A tag count from its name, display name and count.
+fn tag_count((name, display_name, recipes): (String, String, i64)) -> tags::TagCount {
+    tags::TagCount {
+        name,
+        display_name,
+        recipes,
+    }
+}
+
+/// Remove every recipe and tag, within a transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "delete_all_with"))]
+async fn delete_all_with(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let _timer = metrics::query_timer("delete_all_with");
+    sqlx::query("DELETE FROM recipes;")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl RecipeStore for PgStore {
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "get"))]
+    async fn get(&self, recipe_id: &str) -> Result<(Recipe, Vec<String>), sqlx::Error> {
+        let _timer = metrics::query_timer("get");
+        let mut conn = self.db.acquire().await?;
+        get_with(&mut conn, recipe_id).await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "get_tagged"))]
+    async fn get_tagged(&self, tags: &[String]) -> Result<Option<String>, sqlx::Error> {
+        let _timer = metrics::query_timer("get_tagged");
+        sqlx::query_scalar(
+            r#"
+            WITH RECURSIVE matched (tag_id) AS (
+                SELECT tag_id FROM tag_names WHERE name = ANY($1)
+                UNION
+                SELECT tag_parents.tag_id FROM tag_parents
+                JOIN matched ON tag_parents.parent_id = matched.tag_id)
+            SELECT recipe_id FROM recipe_tags
+            WHERE tag_id IN (SELECT tag_id FROM matched)
+            ORDER BY RANDOM() LIMIT 1;
+            "#,
+        )
+        .bind(tags::canonical_all(tags.iter().map(String::as_str)))
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "get_random"))]
+    async fn get_random(&self) -> Result<String, sqlx::Error> {
+        let _timer = metrics::query_timer("get_random");
+        sqlx::query_scalar("SELECT id FROM recipes ORDER BY RANDOM() LIMIT 1;")
+            .fetch_one(&self.db)
+            .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "add"))]
+    async fn add(&self, recipe: JsonRecipe) -> Result<(), sqlx::Error> {
+        let _timer = metrics::query_timer("add");
+        let mut jtx = self.db.begin().await?;
+        insert_with(&mut jtx, &recipe).await?;
+        jtx.commit().await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "update"))]
+    async fn update(
+        &self,
+        recipe: JsonRecipe,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error> {
+        let _timer = metrics::query_timer("update");
+        let mut jtx = self.db.begin().await?;
+        if !precondition_holds_with(&mut jtx, recipe.id(), precondition).await? {
+            return Ok(Written::PreconditionFailed);
+        }
+        if !update_with(&mut jtx, &recipe).await? {
+            jtx.rollback().await?;
+            return Ok(Written::NotFound);
+        }
+        jtx.commit().await?;
+        Ok(Written::Done)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "delete"))]
+    async fn delete(
+        &self,
+        recipe_id: &str,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error> {
+        let _timer = metrics::query_timer("delete");
+        let mut jtx = self.db.begin().await?;
+        if !precondition_holds_with(&mut jtx, recipe_id, precondition).await? {
+            return Ok(Written::PreconditionFailed);
+        }
+        // Tags go with the recipe, by `ON DELETE CASCADE`.
+        let deleted = sqlx::query("DELETE FROM recipes WHERE id = $1;")
+            .bind(recipe_id)
+            .execute(&mut *jtx)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Ok(Written::NotFound);
+        }
+        jtx.commit().await?;
+        Ok(Written::Done)
+    }
+
+    fn list<'a>(
+        &'a self,
+        filter: &'a RecipeFilter,
+    ) -> BoxStream<'a, Result<JsonRecipe, sqlx::Error>> {
+        // Held by the stream, so the whole listing is timed and spanned.
+        let timer = metrics::query_timer("list");
+        let span =
+            tracing::debug_span!("list", db.system = "postgresql", db.operation.name = "list");
+        let tags = (!filter.tags.is_empty()).then_some(&filter.tags);
+        sqlx::query_as::<_, ListRow>(
+            r#"
+            WITH RECURSIVE matched (tag_id) AS (
+                SELECT tag_id FROM tag_names WHERE name = ANY($1)
+                UNION
+                SELECT tag_parents.tag_id FROM tag_parents
+                JOIN matched ON tag_parents.parent_id = matched.tag_id)
+            SELECT id, dish_name, ingredients, time_to_prepare, source, updated_at,
+                   ARRAY(SELECT display_name FROM tags
+                         JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+                         WHERE recipe_id = recipes.id) AS tags
+            FROM recipes
+            WHERE ($1::text[] IS NULL OR EXISTS (
+                      SELECT 1 FROM recipe_tags
+                      WHERE recipe_id = recipes.id AND tag_id IN (SELECT tag_id FROM matched)))
+              AND ($2::timestamptz IS NULL OR updated_at >= $2)
+            ORDER BY id;
+            "#,
+        )
+        .bind(tags)
+        .bind(filter.since)
+        .fetch(&self.db)
+        .map(move |row| {
+            let _ = (&timer, &span);
+            let row = row?;
+            Ok(JsonRecipe::new(row.recipe, row.tags))
+        })
+        .boxed()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "search"))]
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<JsonRecipe>, sqlx::Error> {
+        let _timer = metrics::query_timer("search");
+        let rows = sqlx::query_as::<_, ListRow>(
+            r#"
+            SELECT id, dish_name, ingredients, time_to_prepare, source, updated_at,
+                   ARRAY(SELECT display_name FROM tags
+                         JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+                         WHERE recipe_id = recipes.id) AS tags
+            FROM recipes
+            WHERE dish_name ILIKE $1
+               OR ingredients ILIKE $1
+               OR EXISTS (SELECT 1 FROM recipe_tags JOIN tags ON tags.id = recipe_tags.tag_id
+                          WHERE recipe_id = recipes.id AND display_name ILIKE $1)
+            ORDER BY id
+            LIMIT $2;
+            "#,
+        )
+        .bind(like_pattern(text))
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+        let recipes = rows
+            .into_iter()
+            .map(|row| JsonRecipe::new(row.recipe, row.tags))
+            .collect();
+        Ok(recipes)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "totals"))]
+    async fn totals(&self) -> Result<(i64, i64), sqlx::Error> {
+        let _timer = metrics::query_timer("totals");
+        sqlx::query_as(
+            r#"
+            SELECT (SELECT COUNT(*) FROM recipes),
+                   (SELECT COUNT(DISTINCT tag_id) FROM recipe_tags);
+            "#,
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn begin_import(&self, replace: bool) -> Result<Box<dyn ImportTx>, sqlx::Error> {
+        let mut jtx = self.db.begin().await?;
+        if replace {
+            delete_all_with(&mut jtx).await?;
+        }
+        Ok(Box::new(PgImport { jtx }))
+    }
+}
+
+/// A bulk import into PostgreSQL.
+struct PgImport {
+    jtx: Transaction<'static, Postgres>,
+}
+
+#[async_trait]
+impl ImportTx for PgImport {
+    async fn apply(
+        &mut self,
+        mode: import::ImportMode,
+        recipe: &JsonRecipe,
+    ) -> Result<import::ImportStatus, sqlx::Error> {
+        let mut savepoint = self.jtx.begin().await?;
+        let applied = async {
+            let exists = exists_with(&mut savepoint, recipe.id()).await?;
+            if exists && mode == import::ImportMode::Insert {
+                return Ok(import::ImportStatus::Skipped);
+            }
+            let tags: Vec<String> = recipe
+                .sorted_tags()
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            let categorized = categorized_with(&mut savepoint, &tags).await?;
+            tags::check_categories(&categorized).map_err(sqlx::Error::InvalidArgument)?;
+            if exists {
+                update_with(&mut savepoint, recipe).await?;
+                Ok(import::ImportStatus::Updated)
+            } else {
+                insert_with(&mut savepoint, recipe).await?;
+                Ok(import::ImportStatus::Inserted)
+            }
+        }
+        .await;
+        match applied {
+            Ok(import::ImportStatus::Skipped) | Err(_) => savepoint.rollback().await?,
+            Ok(_) => savepoint.commit().await?,
+        }
+        applied
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), sqlx::Error> {
+        self.jtx.commit().await
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), sqlx::Error> {
+        self.jtx.rollback().await
+    }
+}
+
+/// A categorized tag, as looked up.
+#[derive(sqlx::FromRow)]
+struct CategorizedRow {
+    display_name: String,
+    category: String,
+    multi_valued: bool,
+}
+
+#[async_trait]
+impl TagStore for PgStore {
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "tag_names"))]
+    async fn tag_names(&self) -> Result<Vec<String>, sqlx::Error> {
+        let _timer = metrics::query_timer("tag_names");
+        sqlx::query_scalar("SELECT name FROM tags ORDER BY name;")
+            .fetch_all(&self.db)
+            .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "rename_tag"))]
+    async fn rename_tag(&self, from: &str, into: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("rename_tag");
+        let mut jtx = self.db.begin().await?;
+        let merged = merge_tags_with(&mut jtx, from, into).await?;
+        jtx.commit().await?;
+        Ok(merged)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "merge_tags"))]
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<usize, sqlx::Error> {
+        let _timer = metrics::query_timer("merge_tags");
+        let mut jtx = self.db.begin().await?;
+        let mut merged = 0;
+        for alias in from {
+            if put_alias_with(&mut jtx, alias, into).await? {
+                merged += 1;
+            }
+        }
+        jtx.commit().await?;
+        Ok(merged)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "tag_counts"))]
+    async fn tag_counts(&self) -> Result<Vec<tags::TagCount>, sqlx::Error> {
+        let _timer = metrics::query_timer("tag_counts");
+        let rows: Vec<(String, String, i64)> = sqlx::query_as(
+            r#"
+            SELECT name, display_name, COUNT(recipe_id)
+            FROM tags LEFT JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+            GROUP BY tags.id
+            ORDER BY name;
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows.into_iter().map(tag_count).collect())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "facets"))]
+    async fn facets(&self, selected: &[String]) -> Result<tags::Facets, sqlx::Error> {
+        let _timer = metrics::query_timer("facets");
+        let names = tags::canonical_all(selected.iter().map(String::as_str));
+        // Read both counts from one snapshot.
+        let mut jtx = self.db.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY;")
+            .execute(&mut *jtx)
+            .await?;
+        // An unknown name selects a null tag, which no recipe carries.
+        let matching: i64 = sqlx::query_scalar(
+            r#"
+            WITH selected AS (
+                SELECT DISTINCT tag_names.tag_id FROM UNNEST($1::text[]) AS requested (name)
+                LEFT JOIN tag_names ON tag_names.name = requested.name)
+            SELECT COUNT(*) FROM recipes
+            WHERE (SELECT COUNT(*) FROM selected) = (
+                SELECT COUNT(*) FROM recipe_tags
+                WHERE recipe_id = recipes.id AND tag_id IN (SELECT tag_id FROM selected));
+            "#,
+        )
+        .bind(&names)
+        .fetch_one(&mut *jtx)
+        .await?;
+        let rows: Vec<(String, String, i64)> = sqlx::query_as(
+            r#"
+            WITH selected AS (
+                SELECT DISTINCT tag_names.tag_id FROM UNNEST($1::text[]) AS requested (name)
+                LEFT JOIN tag_names ON tag_names.name = requested.name),
+            matching AS (
+                SELECT id FROM recipes
+                WHERE (SELECT COUNT(*) FROM selected) = (
+                    SELECT COUNT(*) FROM recipe_tags
+                    WHERE recipe_id = recipes.id AND tag_id IN (SELECT tag_id FROM selected)))
+            SELECT name, display_name, COUNT(*)
+            FROM recipe_tags JOIN tags ON tags.id = recipe_tags.tag_id
+            WHERE recipe_id IN (SELECT id FROM matching)
+              AND tag_id NOT IN (SELECT tag_id FROM selected WHERE tag_id IS NOT NULL)
+            GROUP BY tags.id
+            ORDER BY name;
+            "#,
+        )
+        .bind(&names)
+        .fetch_all(&mut *jtx)
+        .await?;
+        jtx.commit().await?;
+        let tags = rows.into_iter().map(tag_count).collect();
+        Ok(tags::Facets { matching, tags })
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "tag_edges"))]
+    async fn tag_edges(&self) -> Result<Vec<tags::TagEdge>, sqlx::Error> {
+        let _timer = metrics::query_timer("tag_edges");
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT child.name, parent.name FROM tag_parents
+            JOIN tags AS child ON child.id = tag_parents.tag_id
+            JOIN tags AS parent ON parent.id = tag_parents.parent_id
+            ORDER BY child.name, parent.name;
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let edges = rows
+            .into_iter()
+            .map(|(tag, parent)| tags::TagEdge { tag, parent })
+            .collect();
+        Ok(edges)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "add_tag_parent"))]
+    async fn add_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("add_tag_parent");
+        let mut jtx = self.db.begin().await?;
+        // Two concurrent additions could each pass the cycle check below and
+        // together form a cycle, so they take turns.
+        sqlx::query("LOCK TABLE tag_parents IN SHARE ROW EXCLUSIVE MODE;")
+            .execute(&mut *jtx)
+            .await?;
+        let tag_id = resolve_or_insert_with(&mut jtx, tag).await?;
+        let parent_id = resolve_or_insert_with(&mut jtx, parent).await?;
+        let cycle: bool = sqlx::query_scalar(
+            r#"
+            WITH RECURSIVE below (tag_id) AS (
+                SELECT $1::bigint
+                UNION
+                SELECT tag_parents.tag_id FROM tag_parents
+                JOIN below ON tag_parents.parent_id = below.tag_id)
+            SELECT EXISTS (SELECT 1 FROM below WHERE tag_id = $2);
+            "#,
+        )
+        .bind(tag_id)
+        .bind(parent_id)
+        .fetch_one(&mut *jtx)
+        .await?;
+        if cycle {
+            return Ok(false);
+        }
+        sqlx::query(
+            "INSERT INTO tag_parents (tag_id, parent_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+        )
+        .bind(tag_id)
+        .bind(parent_id)
+        .execute(&mut *jtx)
+        .await?;
+        jtx.commit().await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "remove_tag_parent"))]
+    async fn remove_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("remove_tag_parent");
+        let deleted = sqlx::query(
+            r#"
+            DELETE FROM tag_parents
+            WHERE tag_id = (SELECT tag_id FROM tag_names WHERE name = $1)
+              AND parent_id = (SELECT tag_id FROM tag_names WHERE name = $2);
+            "#,
+        )
+        .bind(tag)
+        .bind(parent)
+        .execute(&self.db)
+        .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "aliases"))]
+    async fn aliases(&self) -> Result<Vec<tags::Alias>, sqlx::Error> {
+        let _timer = metrics::query_timer("aliases");
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT alias, name FROM tag_aliases
+            JOIN tags ON tags.id = tag_aliases.tag_id
+            ORDER BY alias;
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let aliases = rows
+            .into_iter()
+            .map(|(alias, tag)| tags::Alias { alias, tag })
+            .collect();
+        Ok(aliases)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "put_alias"))]
+    async fn put_alias(&self, alias: &str, tag: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("put_alias");
+        let mut jtx = self.db.begin().await?;
+        let put = put_alias_with(&mut jtx, alias, tag).await?;
+        jtx.commit().await?;
+        Ok(put)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "delete_alias"))]
+    async fn delete_alias(&self, alias: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("delete_alias");
+        let deleted = sqlx::query("DELETE FROM tag_aliases WHERE alias = $1;")
+            .bind(alias)
+            .execute(&self.db)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "categories"))]
+    async fn categories(&self) -> Result<Vec<tags::Category>, sqlx::Error> {
+        let _timer = metrics::query_timer("categories");
+        let rows: Vec<(String, bool, String, Vec<String>)> = sqlx::query_as(
+            r#"
+            SELECT name, multi_valued, description,
+                   ARRAY(SELECT display_name FROM tags
+                         JOIN categorized_tags ON categorized_tags.tag_id = tags.id
+                         WHERE category = tag_categories.name
+                         ORDER BY tags.name)
+            FROM tag_categories
+            ORDER BY name;
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        let categories = rows
+            .into_iter()
+            .map(|(name, multi_valued, description, tags)| tags::Category {
+                name,
+                multi_valued,
+                description,
+                tags,
+            })
+            .collect();
+        Ok(categories)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "put_category"))]
+    async fn put_category(
+        &self,
+        name: &str,
+        multi_valued: bool,
+        description: &str,
+    ) -> Result<(), sqlx::Error> {
+        let _timer = metrics::query_timer("put_category");
+        sqlx::query(
+            r#"
+            INSERT INTO tag_categories (name, multi_valued, description) VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE
+            SET multi_valued = excluded.multi_valued, description = excluded.description;
+            "#,
+        )
+        .bind(name)
+        .bind(multi_valued)
+        .bind(description)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "delete_category"))]
+    async fn delete_category(&self, name: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("delete_category");
+        // Memberships go with the category, by `ON DELETE CASCADE`.
+        let deleted = sqlx::query("DELETE FROM tag_categories WHERE name = $1;")
+            .bind(name)
+            .execute(&self.db)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "set_tag_category"))]
+    async fn set_tag_category(
+        &self,
+        tag: &str,
+        category: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("set_tag_category");
+        let name = tags::canonical(tag);
+        let mut jtx = self.db.begin().await?;
+        match category {
+            Some(category) => {
+                let found: Option<String> =
+                    sqlx::query_scalar("SELECT name FROM tag_categories WHERE name = $1;")
+                        .bind(category)
+                        .fetch_optional(&mut *jtx)
+                        .await?;
+                if found.is_none() {
+                    return Ok(false);
+                }
+                sqlx::query(
+                    r#"
+                    INSERT INTO tags (name, display_name)
+                    SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM tag_aliases WHERE alias = $1)
+                    ON CONFLICT (name) DO NOTHING;
+                    "#,
+                )
+                .bind(&name)
+                .bind(tags::display_name(tag))
+                .execute(&mut *jtx)
+                .await?;
+                sqlx::query(
+                    r#"
+                    INSERT INTO categorized_tags (tag_id, category)
+                    SELECT tag_id, $2 FROM tag_names WHERE name = $1
+                    ON CONFLICT (tag_id) DO UPDATE SET category = excluded.category;
+                    "#,
+                )
+                .bind(&name)
+                .bind(category)
+                .execute(&mut *jtx)
+                .await?;
+            }
+            None => {
+                sqlx::query(
+                    r#"
+                    DELETE FROM categorized_tags
+                    WHERE tag_id = (SELECT tag_id FROM tag_names WHERE name = $1);
+                    "#,
+                )
+                .bind(&name)
+                .execute(&mut *jtx)
+                .await?;
+            }
+        }
+        jtx.commit().await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "postgresql", db.operation.name = "categorized"))]
+    async fn categorized(&self, tags: &[String]) -> Result<Vec<tags::Categorized>, sqlx::Error> {
+        let _timer = metrics::query_timer("categorized");
+        let mut conn = self.db.acquire().await?;
+        categorized_with(&mut conn, tags).await
+    }
+}
+
+#[async_trait]
+impl UserStore for PgStore {
+    async fn add_user(&self, email: &str, full_name: &str, role: Role) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO users (email, full_name, role) VALUES ($1, $2, $3);")
+            .bind(email)
+            .bind(full_name)
+            .bind(role)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, sqlx::Error> {
+        sqlx::query_as("SELECT email, full_name, role, created_at FROM users ORDER BY email;")
+            .fetch_all(&self.db)
+            .await
+    }
+
+    async fn set_role(&self, email: &str, role: Role) -> Result<bool, sqlx::Error> {
+        let updated = sqlx::query("UPDATE users SET role = $2 WHERE email = $1;")
+            .bind(email)
+            .bind(role)
+            .execute(&self.db)
+            .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn add_api_key(
+        &self,
+        id: &str,
+        email: &str,
+        key_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let added = sqlx::query(
+            r#"
+            INSERT INTO api_keys (id, user_email, key_hash)
+            SELECT $1, email, $3 FROM users WHERE email = $2;
+            "#,
+        )
+        .bind(id)
+        .bind(email)
+        .bind(key_hash)
+        .execute(&self.db)
+        .await?;
+        Ok(added.rows_affected() > 0)
+    }
+
+    async fn revoke_api_key(&self, id: &str) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query(
+            r#"
+            UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND revoked_at IS NULL;
+            "#,
+        )
+        .bind(id)
+        .execute(&self.db)
+        .await?;
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    async fn api_key_user(&self, key_hash: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as(
+            r#"
+            SELECT users.email, users.full_name, users.role, users.created_at
+            FROM api_keys JOIN users ON api_keys.user_email = users.email
+            WHERE api_keys.key_hash = $1 AND api_keys.revoked_at IS NULL;
+            "#,
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.db)
+        .await
+    }
+}
+
+#[async_trait]
+impl Maintenance for PgStore {
+    fn system(&self) -> &'static str {
+        "postgresql"
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.db).await?;
+        renormalize_tags(self).await?;
+        Ok(())
+    }
+
+    async fn undo(&self, target: i64) -> Result<(), MigrateError> {
+        MIGRATOR.undo(&self.db, target).await
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<i64>, sqlx::Error> {
+        let mut conn = self.db.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.into_iter().map(|m| m.version).collect())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1;").execute(&self.db).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.db.size(),
+            idle: self.db.num_idle(),
+            max: self.db.options().get_max_connections(),
+        }
+    }
+
+    /// Not supported: PostgreSQL databases are backed up with `pg_dump`.
+    async fn snapshot(&self, _path: &str) -> Result<(), sqlx::Error> {
+        Err(sqlx::Error::Configuration(
+            "online backups are only supported for SQLite; use pg_dump for PostgreSQL".into(),
+        ))
+    }
+
+    async fn close(&self) {
+        if tokio::time::timeout(CLOSE_TIMEOUT, self.db.close())
+            .await
+            .is_err()
+        {
+            tracing::warn!("Database connections still busy; closing anyway");
+        }
+    }
+}
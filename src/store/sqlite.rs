@@ -0,0 +1,1188 @@
+//! SQLite backend.
+//!
+//! Queries are checked at compile time against the schema built from
+//! `migrations/sqlite`, with the results cached in `.sqlx` for offline
+//! builds.
+
+use super::*;
+
+use config::{JournalMode, Synchronous};
+use futures_util::StreamExt;
+use sqlx::migrate::{Migrate, MigrateDatabase};
+use sqlx::sqlite::{
+    Sqlite, SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteSynchronous,
+};
+use sqlx::{Connection, Row, SqlitePool, Transaction};
+
+/// Database migrations embedded from `migrations/sqlite`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Longest wait for busy database connections at shutdown.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Extract file path from SQLite URI.
+pub fn extract_db_path(db_uri: &str) -> Result<&str, RecipeServerError> {
+    if db_uri.starts_with("sqlite://") && db_uri.ends_with(".db") {
+        let start = db_uri.find(':').unwrap() + 3;
+        Ok(&db_uri[start..])
+    } else {
+        Err(RecipeServerError::InvalidDbUri(db_uri.to_string()))
+    }
+}
+
+/// Extract directory path from SQLite URI.
+fn extract_db_dir(db_uri: &str) -> Result<&str, RecipeServerError> {
+    let path = extract_db_path(db_uri)?;
+    match path.rfind('/') {
+        Some(end) => Ok(&path[..end]),
+        None => Ok(""),
+    }
+}
+
+/// Recipes and users in an SQLite database file.
+pub struct SqliteStore {
+    db: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Connect to the SQLite database, creating it if it does not exist.
+    ///
+    /// Every connection enforces foreign keys and uses the journal mode,
+    /// `synchronous` level and busy timeout from `config`.
+    pub async fn connect(
+        config: &config::DatabaseConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let db_uri = config.uri.as_str();
+        if !Sqlite::database_exists(db_uri).await? {
+            let db_dir = extract_db_dir(db_uri)?;
+            std::fs::create_dir_all(db_dir)?;
+            Sqlite::create_database(db_uri).await?
+        }
+        let journal_mode = match config.journal_mode {
+            JournalMode::Delete => SqliteJournalMode::Delete,
+            JournalMode::Truncate => SqliteJournalMode::Truncate,
+            JournalMode::Persist => SqliteJournalMode::Persist,
+            JournalMode::Wal => SqliteJournalMode::Wal,
+        };
+        let synchronous = match config.synchronous {
+            Synchronous::Off => SqliteSynchronous::Off,
+            Synchronous::Normal => SqliteSynchronous::Normal,
+            Synchronous::Full => SqliteSynchronous::Full,
+            Synchronous::Extra => SqliteSynchronous::Extra,
+        };
+        let options = db_uri
+            .parse::<SqliteConnectOptions>()?
+            .journal_mode(journal_mode)
+            .synchronous(synchronous)
+            .busy_timeout(Duration::from_millis(config.busy_timeout_ms))
+            .foreign_keys(true);
+        let db = pool_options(config).connect_with(options).await?;
+        Ok(Self { db })
+    }
+
+    /// Start a transaction that takes the write lock at once.
+    ///
+    /// A deferred transaction that reads before writing cannot wait for a
+    /// concurrent writer: upgrading its lock fails at once with
+    /// `SQLITE_BUSY`. Taking the lock up front lets the busy timeout apply.
+    async fn begin_write(&self) -> Result<Transaction<'static, Sqlite>, sqlx::Error> {
+        self.db.begin_with("BEGIN IMMEDIATE").await
+    }
+}
+
+/// Check whether a recipe with the given ID exists, within a transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "exists_with"))]
+async fn exists_with(conn: &mut SqliteConnection, recipe_id: &str) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::query_timer("exists_with");
+    let found = sqlx::query_scalar!("SELECT id FROM recipes WHERE id = $1;", recipe_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(found.is_some())
+}
+
+/// Insert a new recipe and its tags, within a transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "insert_with"))]
+async fn insert_with(conn: &mut SqliteConnection, recipe: &JsonRecipe) -> Result<(), sqlx::Error> {
+    let _timer = metrics::query_timer("insert_with");
+    let (recipe, tags) = recipe.to_recipe();
+    sqlx::query!(
+        r#"
+        INSERT INTO recipes (id, dish_name, ingredients, time_to_prepare, source, updated_at)
+        VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP);
+        "#,
+        recipe.id,
+        recipe.dish_name,
+        recipe.ingredients,
+        recipe.time_to_prepare,
+        recipe.source,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    insert_tags_with(conn, &recipe.id, tags).await
+}
+
+/// Fetch a recipe and its tags by ID.
+async fn get_with(
+    conn: &mut SqliteConnection,
+    recipe_id: &str,
+) -> Result<(Recipe, Vec<String>), sqlx::Error> {
+    let recipe = sqlx::query_as!(
+        Recipe,
+        r#"
+        SELECT id, dish_name, ingredients, time_to_prepare, source,
+               updated_at as "updated_at: DateTime<Utc>"
+        FROM recipes
+        WHERE id = $1;
+        "#,
+        recipe_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let tags: Vec<String> = sqlx::query_scalar!(
+        r#"
+        SELECT display_name FROM tags
+        JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+        WHERE recipe_id = $1
+        ORDER BY name;
+        "#,
+        recipe_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok((recipe, tags))
+}
+
+/// Returns `true` if the stored recipe `recipe_id` passes `precondition`,
+/// or there is none, within a transaction.
+async fn precondition_holds_with(
+    conn: &mut SqliteConnection,
+    recipe_id: &str,
+    precondition: Precondition<'_>,
+) -> Result<bool, sqlx::Error> {
+    let Some(precondition) = precondition else {
+        return Ok(true);
+    };
+    match get_with(conn, recipe_id).await {
+        Ok((recipe, tags)) => Ok(precondition(&JsonRecipe::new(recipe, tags))),
+        Err(sqlx::Error::RowNotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace an existing recipe and its tags, within a transaction.
+///
+/// Returns `false` if no recipe with the given ID exists.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "update_with"))]
+async fn update_with(
+    conn: &mut SqliteConnection,
+    recipe: &JsonRecipe,
+) -> Result<bool, sqlx::Error> {
+    let _timer = metrics::query_timer("update_with");
+    let (recipe, tags) = recipe.to_recipe();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE recipes
+        SET dish_name = $2, ingredients = $3, time_to_prepare = $4, source = $5,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1;
+        "#,
+        recipe.id,
+        recipe.dish_name,
+        recipe.ingredients,
+        recipe.time_to_prepare,
+        recipe.source,
+    )
+    .execute(&mut *conn)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!("DELETE FROM recipe_tags WHERE recipe_id = $1;", recipe.id)
+        .execute(&mut *conn)
+        .await?;
+    insert_tags_with(conn, &recipe.id, tags).await?;
+    Ok(true)
+}
+
+/// Insert the tags of a recipe, resolving aliases and adding any tags not
+/// seen before, within a transaction.
+async fn insert_tags_with<'a>(
+    conn: &mut SqliteConnection,
+    recipe_id: &str,
+    tags: impl Iterator<Item = &'a str>,
+) -> Result<(), sqlx::Error> {
+    for (name, display_name) in tags::entries(tags) {
+        sqlx::query!(
+            r#"
+            INSERT INTO tags (name, display_name)
+            SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM tag_aliases WHERE alias = $1)
+            ON CONFLICT (name) DO NOTHING;
+            "#,
+            name,
+            display_name,
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO recipe_tags (recipe_id, tag_id)
+            SELECT $1, tag_id FROM tag_names WHERE name = $2
+            ON CONFLICT DO NOTHING;
+            "#,
+            recipe_id,
+            name,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The categorized tags among `tags`, in category order and canonical
+/// order within each, within a transaction.
+async fn categorized_with(
+    conn: &mut SqliteConnection,
+    tags: &[String],
+) -> Result<Vec<tags::Categorized>, sqlx::Error> {
+    let names = tags::canonical_all(tags.iter().map(String::as_str));
+    let names = serde_json::to_string(&names).map_err(|e| sqlx::Error::Encode(e.into()))?;
+    let rows = sqlx::query!(
+        r#"
+        SELECT display_name, category, multi_valued as "multi_valued: bool"
+        FROM tags
+        JOIN categorized_tags ON categorized_tags.tag_id = tags.id
+        JOIN tag_categories ON tag_categories.name = categorized_tags.category
+        WHERE tags.id IN (
+            SELECT tag_id FROM tag_names WHERE name IN (SELECT value FROM json_each($1)))
+        ORDER BY category, tags.name;
+        "#,
+        names,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let categorized = rows
+        .into_iter()
+        .map(|row| tags::Categorized {
+            tag: row.display_name,
+            category: row.category,
+            multi_valued: row.multi_valued,
+        })
+        .collect();
+    Ok(categorized)
+}
+
+/// Make `alias` an alias of the tag `tag` resolves to, creating it if
+/// new and merging any tag stored under `alias` into it, within a
+/// transaction. Returns `false` if `tag` resolves to `alias` itself.
+async fn put_alias_with(
+    conn: &mut SqliteConnection,
+    alias: &str,
+    tag: &str,
+) -> Result<bool, sqlx::Error> {
+    let target = resolve_or_insert_with(conn, tag).await?;
+    let existing = sqlx::query_scalar!("SELECT id FROM tags WHERE name = $1;", alias)
+        .fetch_optional(&mut *conn)
+        .await?;
+    match existing {
+        Some(existing) if existing == target => return Ok(false),
+        Some(_) => {
+            merge_tags_with(conn, alias, tag).await?;
+        }
+        None => {}
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO tag_aliases (alias, tag_id) VALUES ($1, $2)
+        ON CONFLICT (alias) DO UPDATE SET tag_id = excluded.tag_id;
+        "#,
+        alias,
+        target,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(true)
+}
+
+/// Merge tag `from` into the tag `into` resolves to, or rename it `into`,
+/// within a transaction. Returns `false` if there is no tag `from`.
+async fn merge_tags_with(
+    conn: &mut SqliteConnection,
+    from: &str,
+    into: &str,
+) -> Result<bool, sqlx::Error> {
+    let Some(from_id) = sqlx::query_scalar!("SELECT id FROM tags WHERE name = $1;", from)
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(false);
+    };
+    let into_id = sqlx::query_scalar!(
+        r#"SELECT tag_id as "tag_id!: i64" FROM tag_names WHERE name = $1;"#,
+        into
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match into_id {
+        None => {
+            sqlx::query!("UPDATE tags SET name = $2 WHERE id = $1;", from_id, into)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Some(into_id) if into_id == from_id => {}
+        Some(into_id) => {
+            sqlx::query!(
+                r#"
+                UPDATE recipes SET updated_at = CURRENT_TIMESTAMP
+                WHERE id IN (SELECT recipe_id FROM recipe_tags WHERE tag_id = $1);
+                "#,
+                from_id,
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query!(
+                r#"
+                INSERT INTO recipe_tags (recipe_id, tag_id)
+                SELECT recipe_id, $2 FROM recipe_tags WHERE tag_id = $1
+                ON CONFLICT DO NOTHING;
+                "#,
+                from_id,
+                into_id,
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query!(
+                r#"
+                INSERT INTO categorized_tags (tag_id, category)
+                SELECT $2, category FROM categorized_tags WHERE tag_id = $1
+                ON CONFLICT DO NOTHING;
+                "#,
+                from_id,
+                into_id,
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query!(
+                "UPDATE tag_aliases SET tag_id = $2 WHERE tag_id = $1;",
+                from_id,
+                into_id,
+            )
+            .execute(&mut *conn)
+            .await?;
+            sqlx::query!(
+                r#"
+                INSERT INTO tag_parents (tag_id, parent_id)
+                SELECT $2, parent_id FROM tag_parents WHERE tag_id = $1 AND parent_id <> $2
+                UNION
+                SELECT tag_id, $2 FROM tag_parents WHERE parent_id = $1 AND tag_id <> $2
+                ON CONFLICT DO NOTHING;
+                "#,
+                from_id,
+                into_id,
+            )
+            .execute(&mut *conn)
+            .await?;
+            // The rest of `from` goes by `ON DELETE CASCADE`.
+            sqlx::query!("DELETE FROM tags WHERE id = $1;", from_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
+/// The id of the tag `name` resolves to, adding a tag of that name if
+/// there is none, within a transaction.
+async fn resolve_or_insert_with(
+    conn: &mut SqliteConnection,
+    name: &str,
+) -> Result<i64, sqlx::Error> {
+    let id = sqlx::query_scalar!(
+        r#"SELECT tag_id as "tag_id!: i64" FROM tag_names WHERE name = $1;"#,
+        name
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match id {
+        Some(id) => Ok(id),
+        None => {
+            sqlx::query_scalar!(
+                "INSERT INTO tags (name, display_name) VALUES ($1, $1) RETURNING id;",
+                name
+            )
+            .fetch_one(&mut *conn)
+            .await
+        }
+    }
+}
+
+/// Remove every recipe and tag, within a transaction.
+#[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "delete_all_with"))]
+async fn delete_all_with(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let _timer = metrics::query_timer("delete_all_with");
+    sqlx::query!("DELETE FROM recipes;")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl RecipeStore for SqliteStore {
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "get"))]
+    async fn get(&self, recipe_id: &str) -> Result<(Recipe, Vec<String>), sqlx::Error> {
+        let _timer = metrics::query_timer("get");
+        let mut conn = self.db.acquire().await?;
+        get_with(&mut conn, recipe_id).await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "get_tagged"))]
+    async fn get_tagged(&self, tags: &[String]) -> Result<Option<String>, sqlx::Error> {
+        let _timer = metrics::query_timer("get_tagged");
+        let mut jtx = self.db.begin().await?;
+        sqlx::query("DROP TABLE IF EXISTS qtags;")
+            .execute(&mut *jtx)
+            .await?;
+        sqlx::query("CREATE TEMPORARY TABLE qtags (tag VARCHR(200));")
+            .execute(&mut *jtx)
+            .await?;
+        for tag in tags::canonical_all(tags.iter().map(String::as_str)) {
+            sqlx::query("INSERT INTO qtags VALUES ($1);")
+                .bind(tag)
+                .execute(&mut *jtx)
+                .await?;
+        }
+        let recipe_ids = sqlx::query(
+            r#"
+            WITH RECURSIVE matched (tag_id) AS (
+                SELECT tag_id FROM tag_names JOIN qtags ON tag_names.name = qtags.tag
+                UNION
+                SELECT tag_parents.tag_id FROM tag_parents
+                JOIN matched ON tag_parents.parent_id = matched.tag_id)
+            SELECT DISTINCT recipe_id FROM recipe_tags
+            WHERE tag_id IN (SELECT tag_id FROM matched)
+            ORDER BY RANDOM() LIMIT 1;
+            "#,
+        )
+        .fetch_all(&mut *jtx)
+        .await?;
+        let nrecipe_ids = recipe_ids.len();
+        let result = if nrecipe_ids == 1 {
+            Some(recipe_ids[0].get(0))
+        } else {
+            None
+        };
+        jtx.commit().await?;
+
+        Ok(result)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "get_random"))]
+    async fn get_random(&self) -> Result<String, sqlx::Error> {
+        let _timer = metrics::query_timer("get_random");
+        sqlx::query_scalar!("SELECT id FROM recipes ORDER BY RANDOM() LIMIT 1;")
+            .fetch_one(&self.db)
+            .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "add"))]
+    async fn add(&self, recipe: JsonRecipe) -> Result<(), sqlx::Error> {
+        let _timer = metrics::query_timer("add");
+        let mut jtx = self.begin_write().await?;
+        insert_with(&mut jtx, &recipe).await?;
+        jtx.commit().await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "update"))]
+    async fn update(
+        &self,
+        recipe: JsonRecipe,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error> {
+        let _timer = metrics::query_timer("update");
+        // `BEGIN IMMEDIATE` holds off other writers from the check onwards.
+        let mut jtx = self.begin_write().await?;
+        if !precondition_holds_with(&mut jtx, recipe.id(), precondition).await? {
+            return Ok(Written::PreconditionFailed);
+        }
+        if !update_with(&mut jtx, &recipe).await? {
+            jtx.rollback().await?;
+            return Ok(Written::NotFound);
+        }
+        jtx.commit().await?;
+        Ok(Written::Done)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "delete"))]
+    async fn delete(
+        &self,
+        recipe_id: &str,
+        precondition: Precondition<'_>,
+    ) -> Result<Written, sqlx::Error> {
+        let _timer = metrics::query_timer("delete");
+        let mut jtx = self.begin_write().await?;
+        if !precondition_holds_with(&mut jtx, recipe_id, precondition).await? {
+            return Ok(Written::PreconditionFailed);
+        }
+        // Tags go with the recipe, by `ON DELETE CASCADE`.
+        let deleted = sqlx::query!("DELETE FROM recipes WHERE id = $1;", recipe_id)
+            .execute(&mut *jtx)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Ok(Written::NotFound);
+        }
+        jtx.commit().await?;
+        Ok(Written::Done)
+    }
+
+    fn list<'a>(
+        &'a self,
+        filter: &'a RecipeFilter,
+    ) -> BoxStream<'a, Result<JsonRecipe, sqlx::Error>> {
+        // Held by the stream, so the whole listing is timed and spanned.
+        let timer = metrics::query_timer("list");
+        let span = tracing::debug_span!("list", db.system = "sqlite", db.operation.name = "list");
+        sqlx::query!(
+            r#"
+            WITH RECURSIVE matched (tag_id) AS (
+                SELECT tag_id FROM tag_names WHERE name IN (SELECT value FROM json_each($1))
+                UNION
+                SELECT tag_parents.tag_id FROM tag_parents
+                JOIN matched ON tag_parents.parent_id = matched.tag_id)
+            SELECT id, dish_name, ingredients, time_to_prepare, source,
+                   updated_at as "updated_at: DateTime<Utc>",
+                   (SELECT json_group_array(display_name) FROM tags
+                    JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+                    WHERE recipe_id = recipes.id) as "tags!: String"
+            FROM recipes
+            WHERE ($1 IS NULL OR EXISTS (
+                      SELECT 1 FROM recipe_tags
+                      WHERE recipe_id = recipes.id AND tag_id IN (SELECT tag_id FROM matched)))
+              AND ($2 IS NULL OR datetime(updated_at) >= datetime($2))
+            ORDER BY id;
+            "#,
+            filter.tags_json,
+            filter.since,
+        )
+        .fetch(&self.db)
+        .map(move |row| {
+            let _ = (&timer, &span);
+            let row = row?;
+            let tags =
+                serde_json::from_str(&row.tags).map_err(|e| sqlx::Error::Decode(e.into()))?;
+            let recipe = Recipe {
+                id: row.id,
+                dish_name: row.dish_name,
+                ingredients: row.ingredients,
+                time_to_prepare: row.time_to_prepare,
+                source: row.source,
+                updated_at: row.updated_at,
+            };
+            Ok(JsonRecipe::new(recipe, tags))
+        })
+        .boxed()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "search"))]
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<JsonRecipe>, sqlx::Error> {
+        let _timer = metrics::query_timer("search");
+        let pattern = like_pattern(text);
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, dish_name, ingredients, time_to_prepare, source,
+                   updated_at as "updated_at: DateTime<Utc>",
+                   (SELECT json_group_array(display_name) FROM tags
+                    JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+                    WHERE recipe_id = recipes.id) as "tags!: String"
+            FROM recipes
+            WHERE dish_name LIKE $1 ESCAPE '\'
+               OR ingredients LIKE $1 ESCAPE '\'
+               OR EXISTS (SELECT 1 FROM recipe_tags JOIN tags ON tags.id = recipe_tags.tag_id
+                          WHERE recipe_id = recipes.id AND display_name LIKE $1 ESCAPE '\')
+            ORDER BY id
+            LIMIT $2;
+            "#,
+            pattern,
+            limit,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                let tags =
+                    serde_json::from_str(&row.tags).map_err(|e| sqlx::Error::Decode(e.into()))?;
+                let recipe = Recipe {
+                    id: row.id,
+                    dish_name: row.dish_name,
+                    ingredients: row.ingredients,
+                    time_to_prepare: row.time_to_prepare,
+                    source: row.source,
+                    updated_at: row.updated_at,
+                };
+                Ok(JsonRecipe::new(recipe, tags))
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "totals"))]
+    async fn totals(&self) -> Result<(i64, i64), sqlx::Error> {
+        let _timer = metrics::query_timer("totals");
+        let totals = sqlx::query!(
+            r#"
+            SELECT (SELECT COUNT(*) FROM recipes) as "recipes!: i64",
+                   (SELECT COUNT(DISTINCT tag_id) FROM recipe_tags) as "tags!: i64";
+            "#
+        )
+        .fetch_one(&self.db)
+        .await?;
+        Ok((totals.recipes, totals.tags))
+    }
+
+    async fn begin_import(&self, replace: bool) -> Result<Box<dyn ImportTx>, sqlx::Error> {
+        let mut jtx = self.begin_write().await?;
+        if replace {
+            delete_all_with(&mut jtx).await?;
+        }
+        Ok(Box::new(SqliteImport { jtx }))
+    }
+}
+
+/// A bulk import into SQLite.
+struct SqliteImport {
+    jtx: Transaction<'static, Sqlite>,
+}
+
+#[async_trait]
+impl ImportTx for SqliteImport {
+    async fn apply(
+        &mut self,
+        mode: import::ImportMode,
+        recipe: &JsonRecipe,
+    ) -> Result<import::ImportStatus, sqlx::Error> {
+        let mut savepoint = self.jtx.begin().await?;
+        let applied = async {
+            let exists = exists_with(&mut savepoint, recipe.id()).await?;
+            if exists && mode == import::ImportMode::Insert {
+                return Ok(import::ImportStatus::Skipped);
+            }
+            let tags: Vec<String> = recipe
+                .sorted_tags()
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            let categorized = categorized_with(&mut savepoint, &tags).await?;
+            tags::check_categories(&categorized).map_err(sqlx::Error::InvalidArgument)?;
+            if exists {
+                update_with(&mut savepoint, recipe).await?;
+                Ok(import::ImportStatus::Updated)
+            } else {
+                insert_with(&mut savepoint, recipe).await?;
+                Ok(import::ImportStatus::Inserted)
+            }
+        }
+        .await;
+        match applied {
+            Ok(import::ImportStatus::Skipped) | Err(_) => savepoint.rollback().await?,
+            Ok(_) => savepoint.commit().await?,
+        }
+        applied
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), sqlx::Error> {
+        self.jtx.commit().await
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), sqlx::Error> {
+        self.jtx.rollback().await
+    }
+}
+
+#[async_trait]
+impl TagStore for SqliteStore {
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "tag_names"))]
+    async fn tag_names(&self) -> Result<Vec<String>, sqlx::Error> {
+        let _timer = metrics::query_timer("tag_names");
+        sqlx::query_scalar!("SELECT name FROM tags ORDER BY name;")
+            .fetch_all(&self.db)
+            .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "rename_tag"))]
+    async fn rename_tag(&self, from: &str, into: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("rename_tag");
+        let mut jtx = self.begin_write().await?;
+        let merged = merge_tags_with(&mut jtx, from, into).await?;
+        jtx.commit().await?;
+        Ok(merged)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "merge_tags"))]
+    async fn merge_tags(&self, from: &[String], into: &str) -> Result<usize, sqlx::Error> {
+        let _timer = metrics::query_timer("merge_tags");
+        let mut jtx = self.begin_write().await?;
+        let mut merged = 0;
+        for alias in from {
+            if put_alias_with(&mut jtx, alias, into).await? {
+                merged += 1;
+            }
+        }
+        jtx.commit().await?;
+        Ok(merged)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "tag_counts"))]
+    async fn tag_counts(&self) -> Result<Vec<tags::TagCount>, sqlx::Error> {
+        let _timer = metrics::query_timer("tag_counts");
+        sqlx::query_as!(
+            tags::TagCount,
+            r#"
+            SELECT name, display_name, COUNT(recipe_id) as "recipes!: i64"
+            FROM tags LEFT JOIN recipe_tags ON recipe_tags.tag_id = tags.id
+            GROUP BY tags.id
+            ORDER BY name;
+            "#
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "facets"))]
+    async fn facets(&self, selected: &[String]) -> Result<tags::Facets, sqlx::Error> {
+        let _timer = metrics::query_timer("facets");
+        let names = tags::canonical_all(selected.iter().map(String::as_str));
+        let names = serde_json::to_string(&names).map_err(|e| sqlx::Error::Encode(e.into()))?;
+        // Read both counts from one snapshot.
+        let mut jtx = self.db.begin().await?;
+        // An unknown name selects a null tag, which no recipe carries.
+        let matching = sqlx::query_scalar!(
+            r#"
+            WITH selected AS (
+                SELECT DISTINCT tag_names.tag_id FROM json_each($1)
+                LEFT JOIN tag_names ON tag_names.name = json_each.value)
+            SELECT COUNT(*) as "matching!: i64" FROM recipes
+            WHERE (SELECT COUNT(*) FROM selected) = (
+                SELECT COUNT(*) FROM recipe_tags
+                WHERE recipe_id = recipes.id AND tag_id IN (SELECT tag_id FROM selected));
+            "#,
+            names,
+        )
+        .fetch_one(&mut *jtx)
+        .await?;
+        let tags = sqlx::query_as!(
+            tags::TagCount,
+            r#"
+            WITH selected AS (
+                SELECT DISTINCT tag_names.tag_id FROM json_each($1)
+                LEFT JOIN tag_names ON tag_names.name = json_each.value),
+            matching AS (
+                SELECT id FROM recipes
+                WHERE (SELECT COUNT(*) FROM selected) = (
+                    SELECT COUNT(*) FROM recipe_tags
+                    WHERE recipe_id = recipes.id AND tag_id IN (SELECT tag_id FROM selected)))
+            SELECT name, display_name, COUNT(*) as "recipes!: i64"
+            FROM recipe_tags JOIN tags ON tags.id = recipe_tags.tag_id
+            WHERE recipe_id IN (SELECT id FROM matching)
+              AND tag_id NOT IN (SELECT tag_id FROM selected WHERE tag_id IS NOT NULL)
+            GROUP BY tags.id
+            ORDER BY name;
+            "#,
+            names,
+        )
+        .fetch_all(&mut *jtx)
+        .await?;
+        jtx.commit().await?;
+        Ok(tags::Facets { matching, tags })
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "tag_edges"))]
+    async fn tag_edges(&self) -> Result<Vec<tags::TagEdge>, sqlx::Error> {
+        let _timer = metrics::query_timer("tag_edges");
+        sqlx::query_as!(
+            tags::TagEdge,
+            r#"
+            SELECT child.name as tag, parent.name as parent FROM tag_parents
+            JOIN tags AS child ON child.id = tag_parents.tag_id
+            JOIN tags AS parent ON parent.id = tag_parents.parent_id
+            ORDER BY child.name, parent.name;
+            "#
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "add_tag_parent"))]
+    async fn add_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("add_tag_parent");
+        let mut jtx = self.begin_write().await?;
+        let tag_id = resolve_or_insert_with(&mut jtx, tag).await?;
+        let parent_id = resolve_or_insert_with(&mut jtx, parent).await?;
+        let cycle = sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE below (tag_id) AS (
+                SELECT $1
+                UNION
+                SELECT tag_parents.tag_id FROM tag_parents
+                JOIN below ON tag_parents.parent_id = below.tag_id)
+            SELECT EXISTS (SELECT 1 FROM below WHERE tag_id = $2) as "cycle!: bool";
+            "#,
+            tag_id,
+            parent_id,
+        )
+        .fetch_one(&mut *jtx)
+        .await?;
+        if cycle {
+            return Ok(false);
+        }
+        sqlx::query!(
+            "INSERT INTO tag_parents (tag_id, parent_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+            tag_id,
+            parent_id,
+        )
+        .execute(&mut *jtx)
+        .await?;
+        jtx.commit().await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "remove_tag_parent"))]
+    async fn remove_tag_parent(&self, tag: &str, parent: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("remove_tag_parent");
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM tag_parents
+            WHERE tag_id = (SELECT tag_id FROM tag_names WHERE name = $1)
+              AND parent_id = (SELECT tag_id FROM tag_names WHERE name = $2);
+            "#,
+            tag,
+            parent,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    async fn aliases(&self) -> Result<Vec<tags::Alias>, sqlx::Error> {
+        let _timer = metrics::query_timer("aliases");
+        sqlx::query_as!(
+            tags::Alias,
+            r#"
+            SELECT alias, name as tag FROM tag_aliases
+            JOIN tags ON tags.id = tag_aliases.tag_id
+            ORDER BY alias;
+            "#
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "put_alias"))]
+    async fn put_alias(&self, alias: &str, tag: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("put_alias");
+        let mut jtx = self.begin_write().await?;
+        let put = put_alias_with(&mut jtx, alias, tag).await?;
+        jtx.commit().await?;
+        Ok(put)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "delete_alias"))]
+    async fn delete_alias(&self, alias: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("delete_alias");
+        let deleted = sqlx::query!("DELETE FROM tag_aliases WHERE alias = $1;", alias)
+            .execute(&self.db)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "categories"))]
+    async fn categories(&self) -> Result<Vec<tags::Category>, sqlx::Error> {
+        let _timer = metrics::query_timer("categories");
+        let mut categories: Vec<tags::Category> = sqlx::query!(
+            r#"
+            SELECT name, multi_valued as "multi_valued: bool", description
+            FROM tag_categories
+            ORDER BY name;
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|row| tags::Category {
+            name: row.name,
+            multi_valued: row.multi_valued,
+            description: row.description,
+            tags: Vec::new(),
+        })
+        .collect();
+        let members = sqlx::query!(
+            r#"
+            SELECT category, display_name FROM categorized_tags
+            JOIN tags ON tags.id = categorized_tags.tag_id
+            ORDER BY category, tags.name;
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+        for member in members {
+            if let Some(category) = categories.iter_mut().find(|c| c.name == member.category) {
+                category.tags.push(member.display_name);
+            }
+        }
+        Ok(categories)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "put_category"))]
+    async fn put_category(
+        &self,
+        name: &str,
+        multi_valued: bool,
+        description: &str,
+    ) -> Result<(), sqlx::Error> {
+        let _timer = metrics::query_timer("put_category");
+        sqlx::query!(
+            r#"
+            INSERT INTO tag_categories (name, multi_valued, description) VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE
+            SET multi_valued = excluded.multi_valued, description = excluded.description;
+            "#,
+            name,
+            multi_valued,
+            description,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "delete_category"))]
+    async fn delete_category(&self, name: &str) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("delete_category");
+        // Memberships go with the category, by `ON DELETE CASCADE`.
+        let deleted = sqlx::query!("DELETE FROM tag_categories WHERE name = $1;", name)
+            .execute(&self.db)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "set_tag_category"))]
+    async fn set_tag_category(
+        &self,
+        tag: &str,
+        category: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::query_timer("set_tag_category");
+        let name = tags::canonical(tag);
+        let mut jtx = self.begin_write().await?;
+        match category {
+            Some(category) => {
+                let found = sqlx::query_scalar!(
+                    "SELECT name FROM tag_categories WHERE name = $1;",
+                    category
+                )
+                .fetch_optional(&mut *jtx)
+                .await?;
+                if found.is_none() {
+                    return Ok(false);
+                }
+                let display_name = tags::display_name(tag);
+                sqlx::query!(
+                    r#"
+                    INSERT INTO tags (name, display_name)
+                    SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM tag_aliases WHERE alias = $1)
+                    ON CONFLICT (name) DO NOTHING;
+                    "#,
+                    name,
+                    display_name,
+                )
+                .execute(&mut *jtx)
+                .await?;
+                sqlx::query!(
+                    r#"
+                    INSERT INTO categorized_tags (tag_id, category)
+                    SELECT tag_id, $2 FROM tag_names WHERE name = $1
+                    ON CONFLICT (tag_id) DO UPDATE SET category = excluded.category;
+                    "#,
+                    name,
+                    category,
+                )
+                .execute(&mut *jtx)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM categorized_tags
+                    WHERE tag_id = (SELECT tag_id FROM tag_names WHERE name = $1);
+                    "#,
+                    name,
+                )
+                .execute(&mut *jtx)
+                .await?;
+            }
+        }
+        jtx.commit().await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(db.system = "sqlite", db.operation.name = "categorized"))]
+    async fn categorized(&self, tags: &[String]) -> Result<Vec<tags::Categorized>, sqlx::Error> {
+        let _timer = metrics::query_timer("categorized");
+        let mut conn = self.db.acquire().await?;
+        categorized_with(&mut conn, tags).await
+    }
+}
+
+#[async_trait]
+impl UserStore for SqliteStore {
+    async fn add_user(&self, email: &str, full_name: &str, role: Role) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO users (email, full_name, role) VALUES ($1, $2, $3);",
+            email,
+            full_name,
+            role,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT email, full_name, role as "role: Role",
+                   created_at as "created_at: DateTime<Utc>"
+            FROM users
+            ORDER BY email;
+            "#
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    async fn set_role(&self, email: &str, role: Role) -> Result<bool, sqlx::Error> {
+        let updated = sqlx::query!("UPDATE users SET role = $2 WHERE email = $1;", email, role)
+            .execute(&self.db)
+            .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    async fn add_api_key(
+        &self,
+        id: &str,
+        email: &str,
+        key_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let found = sqlx::query_scalar!("SELECT email FROM users WHERE email = $1;", email)
+            .fetch_optional(&self.db)
+            .await?;
+        if found.is_none() {
+            return Ok(false);
+        }
+        sqlx::query!(
+            "INSERT INTO api_keys (id, user_email, key_hash) VALUES ($1, $2, $3);",
+            id,
+            email,
+            key_hash,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(true)
+    }
+
+    async fn revoke_api_key(&self, id: &str) -> Result<bool, sqlx::Error> {
+        let revoked = sqlx::query!(
+            r#"
+            UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND revoked_at IS NULL;
+            "#,
+            id,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    async fn api_key_user(&self, key_hash: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT users.email, users.full_name, users.role as "role: Role",
+                   users.created_at as "created_at: DateTime<Utc>"
+            FROM api_keys JOIN users ON api_keys.user_email = users.email
+            WHERE api_keys.key_hash = $1 AND api_keys.revoked_at IS NULL;
+            "#,
+            key_hash,
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+}
+
+#[async_trait]
+impl Maintenance for SqliteStore {
+    fn system(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.db).await?;
+        renormalize_tags(self).await?;
+        Ok(())
+    }
+
+    async fn undo(&self, target: i64) -> Result<(), MigrateError> {
+        MIGRATOR.undo(&self.db, target).await
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<i64>, sqlx::Error> {
+        let mut conn = self.db.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.into_iter().map(|m| m.version).collect())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1;").execute(&self.db).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.db.size(),
+            idle: self.db.num_idle(),
+            max: self.db.options().get_max_connections(),
+        }
+    }
+
+    async fn snapshot(&self, path: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("VACUUM INTO $1;")
+            .bind(path)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Checkpoints the write-ahead log into the database file before
+    /// closing.
+    async fn close(&self) {
+        if let Err(e) = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE);")
+            .execute(&self.db)
+            .await
+        {
+            tracing::warn!("WAL checkpoint failed: {}", e);
+        }
+        if tokio::time::timeout(CLOSE_TIMEOUT, self.db.close())
+            .await
+            .is_err()
+        {
+            tracing::warn!("Database connections still busy; closing anyway");
+        }
+    }
+}
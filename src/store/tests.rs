@@ -0,0 +1,525 @@
+//! Conformance tests, run against every backend through `Arc<dyn Store>`.
+//!
+//! SQLite runs on a fresh temporary file and the in-memory store always
+//! runs. PostgreSQL runs only when `TEST_DATABASE_URL` names a database;
+//! its schema is dropped and recreated first, so it must be one the tests
+//! may wipe.
+
+use super::*;
+use import::{ImportMode, ImportStatus};
+
+use futures_util::TryStreamExt;
+use std::path::PathBuf;
+
+/// A temporary SQLite database file, removed with its journal on drop.
+pub(crate) struct TempDb {
+    path: PathBuf,
+}
+
+impl TempDb {
+    /// A database file for test `name` that does not exist yet.
+    pub(crate) fn new(name: &str) -> Self {
+        let file = format!("recipe_server-{}-{}.db", name, std::process::id());
+        let db = Self {
+            path: std::env::temp_dir().join(file),
+        };
+        db.remove();
+        db
+    }
+
+    /// Settings for connecting to the database.
+    pub(crate) fn config(&self) -> config::DatabaseConfig {
+        config::DatabaseConfig {
+            uri: format!("sqlite://{}", self.path.display()),
+            ..config::DatabaseConfig::default()
+        }
+    }
+
+    /// Path of the database file.
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// A recipe with id `id` carrying `tags`.
+pub(crate) fn recipe(id: &str, tags: &[&str]) -> JsonRecipe {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "dish_name": format!("Dish {}", id),
+        "ingredients": "flour, eggs, milk",
+        "time_to_prepare": "10m",
+        "tags": tags,
+        "source": "test",
+    }))
+    .unwrap()
+}
+
+/// Owned strings from `names`.
+fn strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// Ids of the recipes selected by `filter`.
+async fn listed(store: &dyn Store, filter: &RecipeFilter) -> Vec<String> {
+    let recipes: Vec<JsonRecipe> = store.list(filter).try_collect().await.unwrap();
+    recipes
+        .iter()
+        .map(|recipe| recipe.id().to_string())
+        .collect()
+}
+
+/// Sorted tags of recipe `id`, as stored.
+async fn tags_of(store: &dyn Store, id: &str) -> Vec<String> {
+    let (_, mut tags) = store.get(id).await.unwrap();
+    tags.sort();
+    tags
+}
+
+/// Runs every check against `store`, which must be freshly migrated and
+/// hold no recipes.
+async fn conformance(store: Arc<dyn Store>) {
+    recipes(store.as_ref()).await;
+    preconditions(store.as_ref()).await;
+    imports(store.as_ref()).await;
+    tag_names(store.as_ref()).await;
+    hierarchy(store.as_ref()).await;
+    categories(store.as_ref()).await;
+    users(store.as_ref()).await;
+}
+
+async fn recipes(store: &dyn Store) {
+    assert!(matches!(
+        store.get("r1").await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+    assert!(store.get_random().await.is_err());
+
+    store
+        .add(recipe("r1", &["Soup", "Easy To Make"]))
+        .await
+        .unwrap();
+    store.add(recipe("r2", &["Salad"])).await.unwrap();
+    let duplicate = store.add(recipe("r1", &[])).await.unwrap_err();
+    let kind = duplicate.as_database_error().map(|e| e.kind());
+    assert_eq!(kind, Some(sqlx::error::ErrorKind::UniqueViolation));
+
+    let (stored, _) = store.get("r1").await.unwrap();
+    assert_eq!(stored.dish_name, "Dish r1");
+    assert_eq!(tags_of(store, "r1").await, ["easy-to-make", "soup"]);
+
+    assert_eq!(store.totals().await.unwrap().0, 2);
+    let all = RecipeFilter::new(&[], None);
+    assert_eq!(listed(store, &all).await, ["r1", "r2"]);
+    let soup = RecipeFilter::new(&strings(&["SOUP"]), None);
+    assert_eq!(listed(store, &soup).await, ["r1"]);
+    let later = RecipeFilter::new(&[], Some(Utc::now() + TimeDelta::hours(1)));
+    assert!(listed(store, &later).await.is_empty());
+
+    let tagged = store.get_tagged(&strings(&["salad"])).await.unwrap();
+    assert_eq!(tagged.as_deref(), Some("r2"));
+    assert_eq!(
+        store.get_tagged(&strings(&["nothing"])).await.unwrap(),
+        None
+    );
+    assert!(["r1", "r2"].contains(&store.get_random().await.unwrap().as_str()));
+
+    let found = store.search("DISH R", 10).await.unwrap();
+    let ids: Vec<&str> = found.iter().map(JsonRecipe::id).collect();
+    assert_eq!(ids, ["r1", "r2"]);
+    assert_eq!(store.search("dish r", 1).await.unwrap().len(), 1);
+    assert!(store.search("%", 10).await.unwrap().is_empty());
+
+    let written = store
+        .update(recipe("r2", &["Salad", "Cheap"]), None)
+        .await
+        .unwrap();
+    assert_eq!(written, Written::Done);
+    assert_eq!(tags_of(store, "r2").await, ["cheap", "salad"]);
+    let written = store.update(recipe("missing", &[]), None).await.unwrap();
+    assert_eq!(written, Written::NotFound);
+
+    assert_eq!(store.delete("r2", None).await.unwrap(), Written::Done);
+    assert_eq!(store.delete("r2", None).await.unwrap(), Written::NotFound);
+    assert_eq!(listed(store, &all).await, ["r1"]);
+}
+
+async fn preconditions(store: &dyn Store) {
+    store.add(recipe("p1", &[])).await.unwrap();
+    let (stored, _) = store.get("p1").await.unwrap();
+    let seen = stored.updated_at;
+    let unchanged = move |current: &JsonRecipe| current.updated_at() == Some(seen);
+    let precondition: Precondition = Some(&unchanged);
+    let fail = |_: &JsonRecipe| false;
+
+    let written = store
+        .update(recipe("p1", &["Fresh"]), Some(&fail))
+        .await
+        .unwrap();
+    assert_eq!(written, Written::PreconditionFailed);
+    assert!(tags_of(store, "p1").await.is_empty());
+
+    // Stored times may be truncated, so wait for a distinct one.
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let written = store
+        .update(recipe("p1", &["Fresh"]), precondition)
+        .await
+        .unwrap();
+    assert_eq!(written, Written::Done);
+    let (stored, _) = store.get("p1").await.unwrap();
+    assert!(stored.updated_at > seen);
+
+    // The precondition saw the old time, so it now fails.
+    let written = store.update(recipe("p1", &[]), precondition).await.unwrap();
+    assert_eq!(written, Written::PreconditionFailed);
+    assert_eq!(
+        store.delete("p1", precondition).await.unwrap(),
+        Written::PreconditionFailed
+    );
+    let pass = |_: &JsonRecipe| true;
+    let written = store.delete("missing", Some(&pass)).await.unwrap();
+    assert_eq!(written, Written::PreconditionFailed);
+    assert_eq!(
+        store.delete("p1", Some(&pass)).await.unwrap(),
+        Written::Done
+    );
+}
+
+async fn imports(store: &dyn Store) {
+    let mut jtx = store.begin_import(false).await.unwrap();
+    let status = jtx
+        .apply(ImportMode::Insert, &recipe("i1", &["Pie"]))
+        .await
+        .unwrap();
+    assert_eq!(status, ImportStatus::Inserted);
+    let status = jtx
+        .apply(ImportMode::Insert, &recipe("i1", &[]))
+        .await
+        .unwrap();
+    assert_eq!(status, ImportStatus::Skipped);
+    let status = jtx
+        .apply(ImportMode::Upsert, &recipe("r1", &["Stew"]))
+        .await
+        .unwrap();
+    assert_eq!(status, ImportStatus::Updated);
+    let clash = recipe("i2", &["Cheap", "Expensive"]);
+    let error = jtx.apply(ImportMode::Insert, &clash).await.unwrap_err();
+    assert!(error.to_string().contains("cost"), "{}", error);
+    let status = jtx
+        .apply(ImportMode::Insert, &recipe("i3", &[]))
+        .await
+        .unwrap();
+    assert_eq!(status, ImportStatus::Inserted);
+    jtx.commit().await.unwrap();
+    let all = RecipeFilter::new(&[], None);
+    assert_eq!(listed(store, &all).await, ["i1", "i3", "r1"]);
+    assert_eq!(tags_of(store, "r1").await, ["Stew"]);
+
+    let mut jtx = store.begin_import(false).await.unwrap();
+    jtx.apply(ImportMode::Insert, &recipe("i4", &[]))
+        .await
+        .unwrap();
+    jtx.rollback().await.unwrap();
+    assert_eq!(listed(store, &all).await, ["i1", "i3", "r1"]);
+
+    let mut jtx = store.begin_import(true).await.unwrap();
+    let status = jtx
+        .apply(ImportMode::Replace, &recipe("i1", &[]))
+        .await
+        .unwrap();
+    assert_eq!(status, ImportStatus::Inserted);
+    jtx.commit().await.unwrap();
+    assert_eq!(listed(store, &all).await, ["i1"]);
+}
+
+async fn tag_names(store: &dyn Store) {
+    store.add(recipe("t1", &["Veggie", "Soup"])).await.unwrap();
+    store
+        .add(recipe("t2", &["Vegetarian", "Quick", "Soup"]))
+        .await
+        .unwrap();
+    // Seeded aliases resolve on the way in.
+    assert_eq!(tags_of(store, "t1").await, ["soup", "vegetarian"]);
+
+    let counts = store.tag_counts().await.unwrap();
+    let soup = counts.iter().find(|count| count.name == "soup").unwrap();
+    assert_eq!(soup.recipes, 2);
+
+    let facets = store.facets(&strings(&["soup"])).await.unwrap();
+    assert_eq!(facets.matching, 2);
+    let others: Vec<(&str, i64)> = facets
+        .tags
+        .iter()
+        .map(|count| (count.name.as_str(), count.recipes))
+        .collect();
+    assert_eq!(others, [("quick", 1), ("vegetarian", 2)]);
+    let facets = store.facets(&strings(&["soup", "unknown"])).await.unwrap();
+    assert_eq!(facets.matching, 0);
+    assert_eq!(store.facets(&[]).await.unwrap().matching, 3);
+
+    assert!(store.put_alias("fast", "quick").await.unwrap());
+    assert!(!store.put_alias("quick", "fast").await.unwrap());
+    let soupy = RecipeFilter::new(&strings(&["Fast"]), None);
+    assert_eq!(listed(store, &soupy).await, ["t2"]);
+    let aliases = store.aliases().await.unwrap();
+    assert!(aliases.contains(&tags::Alias {
+        alias: "fast".to_string(),
+        tag: "quick".to_string(),
+    }));
+    assert!(store.delete_alias("fast").await.unwrap());
+    assert!(!store.delete_alias("fast").await.unwrap());
+
+    // Merging moves recipes and leaves each name behind as an alias.
+    let merged = store
+        .merge_tags(&strings(&["quick", "soup", "broth"]), "soup")
+        .await
+        .unwrap();
+    assert_eq!(merged, 2);
+    assert_eq!(tags_of(store, "t2").await, ["soup", "vegetarian"]);
+    let names = store.tag_names().await.unwrap();
+    assert!(!names.contains(&"quick".to_string()));
+    let quick = RecipeFilter::new(&strings(&["quick"]), None);
+    assert_eq!(listed(store, &quick).await, ["t1", "t2"]);
+
+    // Renaming onto an existing tag merges, and aliases follow.
+    assert!(store.rename_tag("vegetarian", "soup").await.unwrap());
+    assert!(!store.rename_tag("vegetarian", "soup").await.unwrap());
+    assert_eq!(tags_of(store, "t1").await, ["soup"]);
+    let veggie = RecipeFilter::new(&strings(&["veggie"]), None);
+    assert_eq!(listed(store, &veggie).await, ["t1", "t2"]);
+}
+
+async fn hierarchy(store: &dyn Store) {
+    store.add(recipe("h1", &["Carbonara"])).await.unwrap();
+    assert!(store.add_tag_parent("carbonara", "pasta").await.unwrap());
+    let edges = store.tag_edges().await.unwrap();
+    assert!(edges.contains(&tags::TagEdge {
+        tag: "carbonara".to_string(),
+        parent: "pasta".to_string(),
+    }));
+
+    // Seeded: pasta sits beneath italian.
+    let italian = RecipeFilter::new(&strings(&["italian"]), None);
+    assert_eq!(listed(store, &italian).await, ["h1"]);
+    let tagged = store.get_tagged(&strings(&["italian"])).await.unwrap();
+    assert_eq!(tagged.as_deref(), Some("h1"));
+
+    assert!(!store.add_tag_parent("italian", "carbonara").await.unwrap());
+    assert!(!store.add_tag_parent("pasta", "pasta").await.unwrap());
+    assert!(store.remove_tag_parent("carbonara", "pasta").await.unwrap());
+    assert!(!store.remove_tag_parent("carbonara", "pasta").await.unwrap());
+    assert!(listed(store, &italian).await.is_empty());
+}
+
+async fn categories(store: &dyn Store) {
+    store.put_category("spice", false, "How hot").await.unwrap();
+    assert!(store.set_tag_category("mild", Some("spice")).await.unwrap());
+    assert!(store.set_tag_category("hot", Some("spice")).await.unwrap());
+    assert!(!store.set_tag_category("hot", Some("nope")).await.unwrap());
+
+    let spice = store
+        .categories()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|category| category.name == "spice")
+        .unwrap();
+    assert!(!spice.multi_valued);
+    assert_eq!(spice.tags, ["hot", "mild"]);
+
+    let categorized = store
+        .categorized(&strings(&["Mild", "Hot", "Plain"]))
+        .await
+        .unwrap();
+    let names: Vec<(&str, &str)> = categorized
+        .iter()
+        .map(|c| (c.category.as_str(), c.tag.as_str()))
+        .collect();
+    assert_eq!(names, [("spice", "hot"), ("spice", "mild")]);
+    assert!(tags::check_categories(&categorized).is_err());
+
+    assert!(store.set_tag_category("hot", None).await.unwrap());
+    let categorized = store.categorized(&strings(&["mild", "hot"])).await.unwrap();
+    assert!(tags::check_categories(&categorized).is_ok());
+    assert!(store.delete_category("spice").await.unwrap());
+    assert!(!store.delete_category("spice").await.unwrap());
+    assert!(
+        store
+            .categorized(&strings(&["mild"]))
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+async fn users(store: &dyn Store) {
+    store
+        .add_user("b@x.org", "Bea", Role::Viewer)
+        .await
+        .unwrap();
+    store.add_user("a@x.org", "Al", Role::Editor).await.unwrap();
+    assert!(store.add_user("a@x.org", "Al", Role::Admin).await.is_err());
+    let emails: Vec<String> = store
+        .list_users()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.email)
+        .collect();
+    assert_eq!(emails, ["a@x.org", "b@x.org"]);
+
+    assert!(store.set_role("b@x.org", Role::Admin).await.unwrap());
+    assert!(!store.set_role("c@x.org", Role::Admin).await.unwrap());
+
+    assert!(store.add_api_key("k1", "b@x.org", "hash1").await.unwrap());
+    assert!(!store.add_api_key("k2", "c@x.org", "hash2").await.unwrap());
+    let owner = store.api_key_user("hash1").await.unwrap().unwrap();
+    assert_eq!((owner.email.as_str(), owner.role), ("b@x.org", Role::Admin));
+    assert!(store.api_key_user("hash2").await.unwrap().is_none());
+    assert!(store.revoke_api_key("k1").await.unwrap());
+    assert!(!store.revoke_api_key("k1").await.unwrap());
+    assert!(store.api_key_user("hash1").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn sqlite_conforms() {
+    let db = TempDb::new("conformance");
+    let store = connect(&db.config()).await.unwrap();
+    store.migrate().await.unwrap();
+    conformance(store.clone()).await;
+    store.close().await;
+}
+
+#[tokio::test]
+async fn memory_conforms() {
+    let config = config::DatabaseConfig {
+        uri: "memory://".to_string(),
+        ..config::DatabaseConfig::default()
+    };
+    let store = connect(&config).await.unwrap();
+    conformance(store).await;
+}
+
+#[tokio::test]
+async fn postgres_conforms() {
+    let Ok(uri) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set; skipping PostgreSQL");
+        return;
+    };
+    let config = config::DatabaseConfig {
+        uri,
+        ..config::DatabaseConfig::default()
+    };
+    let store = connect(&config).await.unwrap();
+    store.undo(0).await.unwrap();
+    store.migrate().await.unwrap();
+    conformance(store.clone()).await;
+    store.close().await;
+}
+
+/// Faceted counts from `store` for every selection of up to two tags
+/// from a random catalogue, after loading it.
+async fn facet_counts(store: &dyn Store) -> Vec<tags::Facets> {
+    const TAGS: &[&str] = &[
+        "Soup",
+        "Salad",
+        "Cheap",
+        "Quick",
+        "Vegetarian",
+        "Veggie",
+        "Spicy",
+        "Pasta",
+    ];
+    let mut rng = fastrand::Rng::with_seed(47);
+    for n in 0..40 {
+        let tags: Vec<&str> = TAGS.iter().copied().filter(|_| rng.bool()).collect();
+        store.add(recipe(&format!("f{}", n), &tags)).await.unwrap();
+    }
+    let mut selections = vec![vec![], vec!["Unknown".to_string()]];
+    for (i, a) in TAGS.iter().enumerate() {
+        selections.push(vec![a.to_string()]);
+        for b in &TAGS[i + 1..] {
+            selections.push(vec![a.to_string(), b.to_string()]);
+        }
+    }
+    let mut facets = Vec::new();
+    for selected in &selections {
+        facets.push(store.facets(selected).await.unwrap());
+    }
+    facets
+}
+
+#[tokio::test]
+async fn sqlite_facets_match_memory() {
+    let db = TempDb::new("facets");
+    let store = connect(&db.config()).await.unwrap();
+    store.migrate().await.unwrap();
+    let memory = memory::MemoryStore::new();
+    let expected = facet_counts(&memory).await;
+    assert_eq!(facet_counts(store.as_ref()).await, expected);
+    store.close().await;
+}
+
+/// Many tasks writing at once must neither fail with `SQLITE_BUSY` nor
+/// lose a write.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn sqlite_concurrent_writes() {
+    const TASKS: usize = 8;
+    const WRITES: usize = 20;
+
+    let db = TempDb::new("stress");
+    let store = connect(&db.config()).await.unwrap();
+    store.migrate().await.unwrap();
+
+    let writers: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for n in 0..WRITES {
+                    let id = format!("s{}-{}", task, n);
+                    store.add(recipe(&id, &["Stress", "Fresh"])).await?;
+                    let written = store.update(recipe(&id, &["Stress"]), None).await?;
+                    assert_eq!(written, Written::Done);
+                    store.totals().await?;
+                }
+                let mut jtx = store.begin_import(false).await?;
+                for n in 0..WRITES {
+                    let imported = recipe(&format!("i{}-{}", task, n), &["Stress"]);
+                    jtx.apply(ImportMode::Insert, &imported).await?;
+                }
+                jtx.commit().await?;
+                let written = store.delete(&format!("s{}-0", task), None).await?;
+                assert_eq!(written, Written::Done);
+                Ok::<_, sqlx::Error>(())
+            })
+        })
+        .collect();
+    let mut errors = Vec::new();
+    for writer in writers {
+        if let Err(error) = writer.await.unwrap() {
+            errors.push(error.to_string());
+        }
+    }
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    let expected = TASKS * (2 * WRITES - 1);
+    assert_eq!(store.totals().await.unwrap().0 as usize, expected);
+    let stress = RecipeFilter::new(&strings(&["stress"]), None);
+    assert_eq!(listed(store.as_ref(), &stress).await.len(), expected);
+    let fresh = RecipeFilter::new(&strings(&["fresh"]), None);
+    assert!(listed(store.as_ref(), &fresh).await.is_empty());
+    store.close().await;
+}
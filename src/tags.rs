@@ -0,0 +1,576 @@
+//! Tag names.
+//!
+//! Each tag is stored once, under a canonical name used for matching, with
+//! the spelling it was first given kept for display. Further names may be
+//! kept as aliases of a tag, and resolve to it wherever a tag is given.
+//! Tags may be grouped into categories, each single- or multi-valued.
+//! Tags may sit under parent tags, forming a directed acyclic graph.
+//! Tags are suggested for partial or misspelt names by prefix and edit
+//! distance.
+
+use crate::store::TagStore;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use utoipa::ToSchema;
+
+/// The canonical name of `tag`, under which it is stored and matched.
+/// Empty if `tag` has no letters or digits.
+///
+/// The name is a slug: compatibility-normalized, lowercased, stripped of
+/// accents on Latin letters, with each run of other characters between
+/// letters and digits replaced by a single hyphen. So "Easy to make",
+/// "easy_to_make" and "EASY-TO-MAKE" are all `easy-to-make`, and
+/// "Crème brûlée" is `creme-brulee`.
+pub fn canonical(tag: &str) -> String {
+    let mut slug = String::with_capacity(tag.len());
+    let mut base = ' ';
+    let mut gap = false;
+    for c in tag.nfkd() {
+        if is_combining_mark(c) {
+            if !base.is_ascii() {
+                slug.push(c);
+            }
+            continue;
+        }
+        base = c;
+        if !c.is_alphanumeric() {
+            gap = true;
+            continue;
+        }
+        if gap && !slug.is_empty() {
+            slug.push('-');
+        }
+        gap = false;
+        slug.extend(c.to_lowercase());
+    }
+    slug.nfc().collect()
+}
+
+/// The spelling of `tag` kept for display.
+pub fn display_name(tag: &str) -> &str {
+    tag.trim()
+}
+
+/// Canonical names of `tags`, leaving out blanks and duplicates.
+pub fn canonical_all<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut names: Vec<String> = tags
+        .into_iter()
+        .map(canonical)
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// Canonical and display names of `tags` in canonical order, leaving out
+/// blanks. Of several spellings of one tag, the least is kept.
+pub fn entries<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<(String, &'a str)> {
+    let mut entries: BTreeMap<String, &str> = BTreeMap::new();
+    for tag in tags {
+        let name = canonical(tag);
+        if name.is_empty() {
+            continue;
+        }
+        let display = display_name(tag);
+        entries
+            .entry(name)
+            .and_modify(|kept| *kept = (*kept).min(display))
+            .or_insert(display);
+    }
+    entries.into_iter().collect()
+}
+
+/// A tag and the number of recipes carrying it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TagCount {
+    /// Canonical name of the tag.
+    #[schema(example = "easy-to-make")]
+    pub name: String,
+    /// Display name of the tag.
+    pub display_name: String,
+    /// Number of recipes carrying it.
+    pub recipes: i64,
+}
+
+/// Counts for narrowing a selection of recipes by tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Facets {
+    /// Number of recipes carrying every selected tag.
+    pub matching: i64,
+    /// Each other tag carried by some of those recipes, with the number
+    /// that would match were it selected too, in canonical order.
+    pub tags: Vec<TagCount>,
+}
+
+/// A tag placed under a parent tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TagEdge {
+    /// Canonical name of the tag.
+    #[schema(example = "pasta")]
+    pub tag: String,
+    /// Canonical name of its parent.
+    #[schema(example = "italian")]
+    pub parent: String,
+}
+
+/// A tag with the tags beneath it. A tag with several parents appears
+/// under each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TagNode {
+    /// Canonical name of the tag.
+    pub name: String,
+    /// Display name of the tag.
+    pub display_name: String,
+    /// Number of recipes carrying the tag itself.
+    pub recipes: i64,
+    /// Child tags, in canonical order.
+    #[schema(no_recursion)]
+    pub children: Vec<TagNode>,
+}
+
+/// Arranges `tag_counts` into trees by `edges`, with the tags that have
+/// no parent at the roots.
+pub fn build_tree(tag_counts: &[TagCount], edges: &[TagEdge]) -> Vec<TagNode> {
+    let mut children: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for edge in edges {
+        children.entry(&edge.parent).or_default().push(&edge.tag);
+    }
+    for names in children.values_mut() {
+        names.sort_unstable();
+    }
+    let counts: BTreeMap<&str, &TagCount> = tag_counts
+        .iter()
+        .map(|tag| (tag.name.as_str(), tag))
+        .collect();
+    let mut path = Vec::new();
+    tag_counts
+        .iter()
+        .filter(|tag| !edges.iter().any(|edge| edge.tag == tag.name))
+        .map(|tag| tree_node(tag, &counts, &children, &mut path))
+        .collect()
+}
+
+/// The tree beneath `tag`, leaving out any tag already on `path` should
+/// merged tags have formed a cycle.
+fn tree_node<'a>(
+    tag: &'a TagCount,
+    counts: &BTreeMap<&str, &'a TagCount>,
+    children: &BTreeMap<&str, Vec<&str>>,
+    path: &mut Vec<&'a str>,
+) -> TagNode {
+    path.push(&tag.name);
+    let mut child_nodes = Vec::new();
+    for name in children.get(tag.name.as_str()).into_iter().flatten() {
+        if !path.contains(name)
+            && let Some(child) = counts.get(name)
+        {
+            child_nodes.push(tree_node(child, counts, children, path));
+        }
+    }
+    path.pop();
+    TagNode {
+        name: tag.name.clone(),
+        display_name: tag.display_name.clone(),
+        recipes: tag.recipes,
+        children: child_nodes,
+    }
+}
+
+/// A name resolving to another tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Alias {
+    /// Canonical name of the alias.
+    #[schema(example = "veggie")]
+    pub alias: String,
+    /// Canonical name of the tag it resolves to.
+    #[schema(example = "vegetarian")]
+    pub tag: String,
+}
+
+/// A group of tags stating one kind of fact about a recipe, such as its
+/// difficulty or cuisine. Its tags form a controlled vocabulary: a tag
+/// belongs to at most one category, and only admins assign them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Category {
+    /// Canonical name of the category.
+    #[schema(example = "difficulty")]
+    pub name: String,
+    /// Whether a recipe may carry several of its tags.
+    pub multi_valued: bool,
+    /// What the category describes.
+    pub description: String,
+    /// Display names of its tags, in canonical order.
+    pub tags: Vec<String>,
+}
+
+/// A tag of a recipe that belongs to a category.
+#[derive(Debug, Clone)]
+pub struct Categorized {
+    /// Display name of the tag.
+    pub tag: String,
+    /// Name of its category.
+    pub category: String,
+    /// Whether that category allows several tags per recipe.
+    pub multi_valued: bool,
+}
+
+/// Checks that tags `categorized`, in category order, hold at most one
+/// tag of each single-valued category, describing each violation if not.
+pub fn check_categories(categorized: &[Categorized]) -> Result<(), String> {
+    let problems: Vec<String> = categorized
+        .chunk_by(|a, b| a.category == b.category)
+        .filter(|group| group.len() > 1 && !group[0].multi_valued)
+        .map(|group| {
+            let tags: Vec<&str> = group.iter().map(|c| c.tag.as_str()).collect();
+            format!(
+                "{} takes a single tag, not {}",
+                group[0].category,
+                tags.join(", ")
+            )
+        })
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+/// Most suggestions offered for each tag of a lookup that found nothing.
+const DID_YOU_MEAN_PER_TAG: usize = 3;
+
+/// Most suggestions offered for a lookup that found nothing.
+const DID_YOU_MEAN_LIMIT: usize = 5;
+
+/// Most edits a name of `len` characters may be from a tag to suggest it.
+fn typo_allowance(len: usize) -> usize {
+    match len {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+/// The optimal string alignment distance between `a` and `b`: the number
+/// of characters inserted, deleted, replaced or swapped with a neighbour.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// How far `text` is from `name`: 0 if it is a prefix, otherwise the
+/// fewer edits turning it into `name` or into a prefix of it as long.
+fn typo_distance(text: &str, name: &str) -> usize {
+    if name.starts_with(text) {
+        return 0;
+    }
+    let text: Vec<char> = text.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let prefix = &name[..text.len().min(name.len())];
+    edit_distance(&text, &name).min(edit_distance(&text, prefix))
+}
+
+/// Ranks the tags in `tag_counts` carried by some recipe whose name, or
+/// an alias of it, begins with `text` or is a few edits from doing so.
+/// Closer matches come first, then more popular ones. At most `limit` are
+/// returned.
+pub fn rank_suggestions(
+    text: &str,
+    tag_counts: &[TagCount],
+    aliases: &[Alias],
+    limit: usize,
+) -> Vec<TagCount> {
+    let text = canonical(text);
+    let allowance = typo_allowance(text.chars().count());
+    let names = tag_counts
+        .iter()
+        .map(|tag| (&tag.name, &tag.name))
+        .chain(aliases.iter().map(|alias| (&alias.alias, &alias.tag)));
+    let mut distances: BTreeMap<&str, usize> = BTreeMap::new();
+    for (name, tag) in names {
+        let distance = typo_distance(&text, name);
+        if distance <= allowance {
+            distances
+                .entry(tag)
+                .and_modify(|best| *best = (*best).min(distance))
+                .or_insert(distance);
+        }
+    }
+    let mut ranked: Vec<(usize, &TagCount)> = tag_counts
+        .iter()
+        .filter(|tag| tag.recipes > 0)
+        .filter_map(|tag| Some((*distances.get(tag.name.as_str())?, tag)))
+        .collect();
+    ranked.sort_by(|(da, a), (db, b)| {
+        da.cmp(db)
+            .then(b.recipes.cmp(&a.recipes))
+            .then(a.name.cmp(&b.name))
+    });
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|(_, tag)| tag.clone())
+        .collect()
+}
+
+/// Suggests at most `limit` tags for the partial or misspelt name `text`.
+pub async fn suggest(
+    store: &dyn TagStore,
+    text: &str,
+    limit: usize,
+) -> Result<Vec<TagCount>, sqlx::Error> {
+    let tag_counts = store.tag_counts().await?;
+    let aliases = store.aliases().await?;
+    Ok(rank_suggestions(text, &tag_counts, &aliases, limit))
+}
+
+/// Suggests tags that might have been meant by `tags`, none of which is
+/// carried by any recipe.
+pub async fn did_you_mean(
+    store: &dyn TagStore,
+    tags: &[String],
+) -> Result<Vec<TagCount>, sqlx::Error> {
+    let tag_counts = store.tag_counts().await?;
+    let aliases = store.aliases().await?;
+    let mut suggestions: Vec<TagCount> = Vec::new();
+    for tag in canonical_all(tags.iter().map(String::as_str)) {
+        for suggestion in rank_suggestions(&tag, &tag_counts, &aliases, DID_YOU_MEAN_PER_TAG) {
+            if !suggestions.iter().any(|s| s.name == suggestion.name) {
+                suggestions.push(suggestion);
+            }
+        }
+    }
+    suggestions.truncate(DID_YOU_MEAN_LIMIT);
+    Ok(suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Categorized `tags`, each given as category, tag and whether the
+    /// category is multi-valued.
+    fn categorized(tags: &[(&str, &str, bool)]) -> Vec<Categorized> {
+        tags.iter()
+            .map(|&(category, tag, multi_valued)| Categorized {
+                tag: tag.to_string(),
+                category: category.to_string(),
+                multi_valued,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_valued_categories_take_one_tag() {
+        assert!(check_categories(&[]).is_ok());
+        let tags = categorized(&[
+            ("cost", "Cheap", false),
+            ("course", "Soup", true),
+            ("course", "Main course", true),
+            ("difficulty", "Easy to make", false),
+        ]);
+        assert!(check_categories(&tags).is_ok());
+
+        let tags = categorized(&[
+            ("cost", "Cheap", false),
+            ("cost", "Expensive", false),
+            ("course", "Soup", true),
+            ("course", "Salad", true),
+            ("difficulty", "Easy to make", false),
+            ("difficulty", "Hard to make", false),
+        ]);
+        assert_eq!(
+            check_categories(&tags).unwrap_err(),
+            "cost takes a single tag, not Cheap, Expensive; \
+             difficulty takes a single tag, not Easy to make, Hard to make"
+        );
+    }
+
+    #[test]
+    fn canonical_names_are_slugs() {
+        for tag in [
+            "Easy to make",
+            "easy_to_make",
+            "EASY-TO-MAKE",
+            " easy  to make! ",
+        ] {
+            assert_eq!(canonical(tag), "easy-to-make");
+        }
+        assert_eq!(canonical("Crème brûlée"), "creme-brulee");
+        assert_eq!(canonical("ﬁsh & chips"), "fish-chips");
+        assert_eq!(canonical("Σούπα"), "σούπα");
+        assert_eq!(canonical(" -- "), "");
+    }
+
+    #[test]
+    fn spellings_collapse_to_one_tag() {
+        assert_eq!(
+            canonical_all(["Soup", "soup", " ", "Quick meal", "quick_meal"]),
+            ["quick-meal", "soup"]
+        );
+        assert_eq!(
+            entries(["soup", " Soup ", "!", "Quick_meal"]),
+            [
+                ("quick-meal".to_string(), "Quick_meal"),
+                ("soup".to_string(), "Soup"),
+            ]
+        );
+    }
+
+    /// Tag counts for `tags`, each given as canonical name and number of
+    /// recipes.
+    fn tag_counts(tags: &[(&str, i64)]) -> Vec<TagCount> {
+        tags.iter()
+            .map(|&(name, recipes)| TagCount {
+                name: name.to_string(),
+                display_name: name.to_string(),
+                recipes,
+            })
+            .collect()
+    }
+
+    /// Names of `suggestions`.
+    fn names(suggestions: &[TagCount]) -> Vec<&str> {
+        suggestions.iter().map(|tag| tag.name.as_str()).collect()
+    }
+
+    #[test]
+    fn edit_distances_count_swaps_once() {
+        let distance = |a: &str, b: &str| {
+            let a: Vec<char> = a.chars().collect();
+            let b: Vec<char> = b.chars().collect();
+            edit_distance(&a, &b)
+        };
+        assert_eq!(distance("soup", "soup"), 0);
+        assert_eq!(distance("", "soup"), 4);
+        assert_eq!(distance("sopu", "soup"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("crème", "creme"), 1);
+
+        assert_eq!(typo_distance("sou", "soup"), 0);
+        assert_eq!(typo_distance("sopu", "soup"), 1);
+        assert_eq!(typo_distance("vegt", "vegetarian"), 1);
+        assert_eq!(typo_distance("soups", "soup"), 1);
+        assert_eq!(typo_allowance(2), 0);
+        assert_eq!(typo_allowance(5), 1);
+        assert_eq!(typo_allowance(6), 2);
+    }
+
+    #[test]
+    fn suggestions_rank_closer_then_popular() {
+        let counts = tag_counts(&[
+            ("salad", 3),
+            ("sorbet", 0),
+            ("soup", 5),
+            ("sour", 9),
+            ("soy-sauce", 2),
+        ]);
+        let aliases = [Alias {
+            alias: "broth".to_string(),
+            tag: "soup".to_string(),
+        }];
+        let suggest = |text| rank_suggestions(text, &counts, &aliases, 10);
+
+        // Tags no recipe carries are never suggested.
+        assert_eq!(names(&suggest("So")), ["sour", "soup", "soy-sauce"]);
+        assert_eq!(
+            names(&rank_suggestions("so", &counts, &aliases, 2)),
+            ["sour", "soup"]
+        );
+        assert_eq!(names(&suggest("Soup")), ["soup", "sour"]);
+        assert_eq!(names(&suggest("sopu")), ["soup"]);
+        assert_eq!(names(&suggest("brot")), ["soup"]);
+        // Short names must be exact prefixes.
+        assert!(suggest("xo").is_empty());
+        assert_eq!(suggest("").len(), 4);
+    }
+
+    #[tokio::test]
+    async fn did_you_mean_gathers_suggestions_once() {
+        use crate::store::RecipeStore;
+        use crate::store::tests::recipe;
+
+        let store = crate::store::memory::MemoryStore::new();
+        store.add(recipe("r1", &["Soup", "Salad"])).await.unwrap();
+        store.add(recipe("r2", &["Soup", "Sour"])).await.unwrap();
+        let tags = ["Sopu".to_string(), "soupe".to_string(), "salda".to_string()];
+        let suggestions = did_you_mean(&store, &tags).await.unwrap();
+        assert_eq!(names(&suggestions), ["salad", "soup"]);
+        let suggestions = did_you_mean(&store, &["zzzz".to_string()]).await;
+        assert!(suggestions.unwrap().is_empty());
+    }
+
+    /// Edges placing each tag under its parent.
+    fn edges(edges: &[(&str, &str)]) -> Vec<TagEdge> {
+        edges
+            .iter()
+            .map(|&(tag, parent)| TagEdge {
+                tag: tag.to_string(),
+                parent: parent.to_string(),
+            })
+            .collect()
+    }
+
+    /// `nodes` drawn as `name(children...)`.
+    fn outline(nodes: &[TagNode]) -> String {
+        let drawn: Vec<String> = nodes
+            .iter()
+            .map(|node| match node.children.as_slice() {
+                [] => node.name.clone(),
+                children => format!("{}({})", node.name, outline(children)),
+            })
+            .collect();
+        drawn.join(" ")
+    }
+
+    #[test]
+    fn trees_hang_tags_under_every_parent() {
+        let counts = tag_counts(&[
+            ("carbonara", 2),
+            ("italian", 0),
+            ("main-course", 1),
+            ("pasta", 4),
+            ("soup", 3),
+        ]);
+        let edges = edges(&[
+            ("pasta", "italian"),
+            ("pasta", "main-course"),
+            ("carbonara", "pasta"),
+            ("soup", "unknown"),
+        ]);
+        let tree = build_tree(&counts, &edges);
+        assert_eq!(
+            outline(&tree),
+            "italian(pasta(carbonara)) main-course(pasta(carbonara))"
+        );
+        assert_eq!(tree[0].children[0].recipes, 4);
+        assert_eq!(
+            outline(&build_tree(&counts, &[])),
+            "carbonara italian main-course pasta soup"
+        );
+    }
+
+    #[test]
+    fn trees_stop_at_cycles() {
+        let counts = tag_counts(&[("a", 1), ("b", 1), ("root", 1)]);
+        let edges = edges(&[("a", "root"), ("b", "a"), ("a", "b")]);
+        assert_eq!(outline(&build_tree(&counts, &edges)), "root(a(b))");
+    }
+}
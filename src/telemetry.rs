@@ -0,0 +1,141 @@
+//! OpenTelemetry trace export.
+//!
+//! When enabled, spans from the tracing subscriber are batched and sent to
+//! an OTLP collector over gRPC or HTTP. Incoming W3C `traceparent` headers
+//! make each request span a child of the caller's trace.
+
+use crate::*;
+
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Instrumentation scope reported with every span.
+const SCOPE: &str = "recipe_server";
+
+/// Builds the tracer provider and installs the `traceparent` propagator.
+///
+/// The provider must be shut down before exit to flush pending spans.
+pub fn init(
+    config: &config::TelemetryConfig,
+) -> Result<SdkTracerProvider, Box<dyn std::error::Error>> {
+    let endpoint = config.endpoint();
+    let exporter = match config.protocol {
+        config::OtlpProtocol::Grpc => opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?,
+        config::OtlpProtocol::Http => opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?,
+    };
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(provider)
+}
+
+/// Builds the tracing layer that forwards spans to `provider`.
+pub fn layer<S>(provider: &SdkTracerProvider) -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SCOPE))
+}
+
+/// Reads propagation headers from a request.
+struct HeaderExtractor<'a>(&'a http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Makes `span` a child of the trace named in the request's `traceparent`
+/// header, if any. Does nothing when export is disabled.
+pub fn set_parent(span: &tracing::Span, headers: &http::HeaderMap) {
+    if !headers.contains_key("traceparent") {
+        return;
+    }
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    let _ = span.set_parent(parent);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use prost::Message;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_spans_over_http() {
+        let (sender, mut exports) = tokio::sync::mpsc::unbounded_channel();
+        let collector = axum::Router::new().route(
+            "/v1/traces",
+            axum::routing::post(move |body: axum::body::Bytes| {
+                let _ = sender.send(body);
+                async {}
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, collector).await });
+
+        let config = config::TelemetryConfig {
+            enabled: true,
+            protocol: config::OtlpProtocol::Http,
+            endpoint: Some(endpoint),
+            service_name: "telemetry-test".to_string(),
+        };
+        let provider = init(&config).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer(&provider));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("exported").in_scope(|| {});
+        });
+        // Shutting down flushes through the blocking HTTP client, which
+        // must not run on a runtime thread.
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let body = tokio::time::timeout(Duration::from_secs(10), exports.recv())
+            .await
+            .expect("no export arrived")
+            .unwrap();
+        let request = ExportTraceServiceRequest::decode(body).unwrap();
+        let resource = request.resource_spans[0].resource.as_ref().unwrap();
+        let service = resource
+            .attributes
+            .iter()
+            .find(|attribute| attribute.key == "service.name")
+            .and_then(|attribute| attribute.value.clone()?.value);
+        assert_eq!(service, Some(Value::StringValue("telemetry-test".into())));
+        let names: Vec<&str> = request
+            .resource_spans
+            .iter()
+            .flat_map(|spans| &spans.scope_spans)
+            .flat_map(|scope| &scope.spans)
+            .map(|span| span.name.as_str())
+            .collect();
+        assert_eq!(names, ["exported"]);
+    }
+}
@@ -18,6 +18,15 @@ pub struct IndexTemplate {
 
     /// A comma-separated list of tags associated with the recipe.
     tags: String,
+
+    /// Tags to browse by, with their recipe counts.
+    tag_counts: Vec<tags::TagCount>,
+
+    /// Tags asked for that no recipe carries, if any, as entered.
+    unmatched: String,
+
+    /// Tags that might have been meant by `unmatched`.
+    suggestions: Vec<tags::TagCount>,
 }
 
 impl IndexTemplate {
@@ -37,6 +46,27 @@ impl IndexTemplate {
             recipe,
             stylesheet: "/recipe-server.css",
             tags,
+            tag_counts: Vec::new(),
+            unmatched: String::new(),
+            suggestions: Vec::new(),
         }
     }
+
+    /// Notes that no recipe carries the tags `unmatched`, suggesting tags
+    /// that might have been meant.
+    pub fn with_suggestions(mut self, unmatched: String, suggestions: Vec<tags::TagCount>) -> Self {
+        self.unmatched = unmatched;
+        self.suggestions = suggestions;
+        self
+    }
+
+    /// Lists the tags carried by any recipe, with their counts, for
+    /// browsing.
+    pub fn with_tag_counts(mut self, tag_counts: Vec<tags::TagCount>) -> Self {
+        self.tag_counts = tag_counts
+            .into_iter()
+            .filter(|tag| tag.recipes > 0)
+            .collect();
+        self
+    }
 }
@@ -0,0 +1,202 @@
+//! TLS termination.
+//!
+//! [`TlsListener`] wraps a TCP listener so that `axum::serve` receives
+//! already-decrypted streams. Handshakes run in their own tasks, so a slow
+//! client cannot hold up others. The certificate and key are re-read when
+//! either file changes on disk; new connections use the new certificate
+//! while established ones carry on undisturbed.
+
+use crate::*;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock as SyncRwLock;
+use std::time::SystemTime;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{self, ServerConfig, pki_types::pem::PemObject};
+use tokio_rustls::server::TlsStream;
+
+/// Longest a client may take to complete a handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken connections waiting to be served.
+const ACCEPT_BACKLOG: usize = 64;
+
+/// Loads a certificate chain and private key into a server configuration.
+fn load_server_config(cert: &Path, key: &Path) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let chain = rustls::pki_types::CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", cert.display(), e))?;
+    if chain.is_empty() {
+        return Err(format!("{}: no certificates found", cert.display()).into());
+    }
+    let key_der = rustls::pki_types::PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("{}: {}", key.display(), e))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(chain, key_der)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// A certificate and key kept in step with their files.
+#[derive(Clone)]
+pub struct Certificates {
+    cert: PathBuf,
+    key: PathBuf,
+    current: Arc<SyncRwLock<Arc<ServerConfig>>>,
+}
+
+impl Certificates {
+    /// Loads the certificate and key, failing if either is unusable.
+    pub fn load(config: &config::TlsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let (Some(cert), Some(key)) = (&config.cert_path, &config.key_path) else {
+            return Err("tls.cert_path and tls.key_path are both required".into());
+        };
+        let server_config = load_server_config(cert, key)?;
+        Ok(Self {
+            cert: cert.clone(),
+            key: key.clone(),
+            current: Arc::new(SyncRwLock::new(Arc::new(server_config))),
+        })
+    }
+
+    /// Acceptor for the current certificate.
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Modification times of the certificate and key files.
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((modified(&self.cert)?, modified(&self.key)?))
+    }
+
+    /// Polls the files every `interval`, reloading them after a change.
+    ///
+    /// A file that fails to load is logged and the previous certificate
+    /// stays in use.
+    pub async fn watch(self, interval: Duration) {
+        let mut seen = self.modified();
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let modified = self.modified();
+            if modified.is_none() || modified == seen {
+                continue;
+            }
+            seen = modified;
+            match load_server_config(&self.cert, &self.key) {
+                Ok(server_config) => {
+                    *self.current.write().unwrap() = Arc::new(server_config);
+                    tracing::info!("Reloaded TLS certificate {}", self.cert.display());
+                }
+                Err(e) => tracing::error!("TLS certificate reload failed: {}", e),
+            }
+        }
+    }
+}
+
+/// A listener that accepts TCP connections and completes TLS handshakes.
+pub struct TlsListener {
+    local_addr: std::net::SocketAddr,
+    accepted: mpsc::Receiver<(TlsStream<TcpStream>, std::net::SocketAddr)>,
+}
+
+impl TlsListener {
+    /// Starts accepting on `listener` with `certificates`, until
+    /// `shutdown` says to stop accepting.
+    pub fn new(
+        listener: TcpListener,
+        certificates: Certificates,
+        shutdown: &shutdown::Shutdown,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, accepted) = mpsc::channel(ACCEPT_BACKLOG);
+        let tasks = shutdown.clone();
+        let stop = shutdown.clone().stop_accepting();
+        shutdown.spawn(async move {
+            tokio::pin!(stop);
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    () = &mut stop => break,
+                };
+                let (stream, addr) = match accepted {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        tracing::warn!("TCP accept failed: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                if tx.is_closed() {
+                    break;
+                }
+                let acceptor = certificates.acceptor();
+                let tx = tx.clone();
+                tasks.spawn(async move {
+                    let handshake =
+                        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+                    match handshake.await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            local_addr,
+            accepted,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted.recv().await {
+            Some(connection) => connection,
+            // The accept task stops at shutdown or once this receiver is gone;
+            // the server is draining by then and no longer accepting.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Router answering every plain-HTTP request with a permanent redirect to
+/// the same path on the HTTPS port.
+pub fn redirect_router(https_port: u16) -> axum::Router {
+    axum::Router::new().fallback(move |headers: http::HeaderMap, uri: http::Uri| async move {
+        let host = headers
+            .get(http::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| host.parse::<http::uri::Authority>().ok());
+        let Some(host) = host else {
+            return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+        };
+        let authority = if https_port == 443 {
+            host.host().to_string()
+        } else {
+            format!("{}:{}", host.host(), https_port)
+        };
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        let location = format!("https://{}{}", authority, path);
+        response::Redirect::permanent(&location).into_response()
+    })
+}
@@ -0,0 +1,96 @@
+//! Users and API keys.
+//!
+//! Users are managed from the command line and carry a role. Each user may
+//! hold any number of API keys, which are accepted wherever a JWT is and
+//! are stored only as SHA-256 hashes.
+
+use crate::*;
+
+use sha2::{Digest, Sha256};
+
+/// Prefix identifying a bearer token as an API key rather than a JWT.
+const API_KEY_PREFIX: &str = "rsk_";
+
+/// What a user is allowed to do.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    ToSchema,
+    clap::ValueEnum,
+    sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    /// May read recipes.
+    #[default]
+    Viewer,
+    /// May also add, change and delete recipes.
+    Editor,
+    /// May also administer the server.
+    Admin,
+}
+
+/// A registered user.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct User {
+    pub email: String,
+    pub full_name: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A newly created API key. The key itself is only ever shown once.
+#[derive(Debug, Serialize)]
+pub struct NewApiKey {
+    pub id: String,
+    pub key: String,
+}
+
+/// Formats bytes as lowercase hexadecimal.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hashes an API key for storage and lookup.
+fn hash_key(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+/// Create an API key for a user. Returns `None` if there is no such user.
+pub async fn create_api_key(
+    store: &dyn store::UserStore,
+    email: &str,
+) -> Result<Option<NewApiKey>, Box<dyn std::error::Error>> {
+    let mut id = [0u8; 4];
+    let mut secret = [0u8; 32];
+    getrandom::fill(&mut id)?;
+    getrandom::fill(&mut secret)?;
+    let id = to_hex(&id);
+    let key = format!("{}{}_{}", API_KEY_PREFIX, id, to_hex(&secret));
+    if !store.add_api_key(&id, email, &hash_key(&key)).await? {
+        return Ok(None);
+    }
+    Ok(Some(NewApiKey { id, key }))
+}
+
+/// Returns `true` if a bearer token looks like an API key.
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+/// Look up the user owning an active API key.
+pub async fn verify_api_key(
+    store: &dyn store::UserStore,
+    key: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    store.api_key_user(&hash_key(key)).await
+}
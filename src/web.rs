@@ -14,7 +14,9 @@ pub struct GetRecipeParams {
 /// Handles the `GET /` route and renders an HTML recipe page.
 ///
 /// This handler checks for query parameters:
-/// - If `id` is provided, it fetches the specific recipe and renders it.
+/// - If `id` is provided, it fetches the specific recipe and renders it,
+///   answering `304 Not Modified` if the client's cached page, identified
+///   by its ETag, is current.
 /// - If `tags` are provided, it tries to find a matching recipe and redirects to it.
 /// - If neither is provided, a random recipe is selected.
 ///
@@ -25,22 +27,30 @@ pub struct GetRecipeParams {
 pub async fn get_recipe(
     State(app_state): State<Arc<RwLock<AppState>>>,
     Query(params): Query<GetRecipeParams>,
+    headers: http::HeaderMap,
 ) -> Result<response::Response, http::StatusCode> {
     let mut app_writer = app_state.write().await;
-    let db = app_writer.db.clone();
+    let store = app_writer.store.clone();
 
     // Fetch recipe by ID if specified
     if let GetRecipeParams { id: Some(id), .. } = params {
-        let recipe_result = recipe::get(&db, &id).await;
+        let recipe_result = store.get(&id).await;
         let result = match recipe_result {
             Ok((recipe, tags)) => {
-                let tag_string = tags.join(", ");
                 app_writer.current_recipe = recipe.clone();
-                let recipe = IndexTemplate::new(recipe.clone(), tag_string);
-                Ok(response::Html(recipe.to_string()).into_response())
+                let tag_string = tags.join(", ");
+                let page = IndexTemplate::new(recipe, tag_string)
+                    .with_tag_counts(tag_counts(store.as_ref()).await)
+                    .to_string();
+                // The page shows tag counts too, so it is validated as rendered.
+                let validators = Validators::for_page(page.as_bytes());
+                if validators.not_modified(&headers) {
+                    return Ok(validators.not_modified_response());
+                }
+                Ok(validators.apply(response::Html(page).into_response()))
             }
             Err(e) => {
-                log::warn!("Recipe fetch failed: {}", e);
+                tracing::warn!("Recipe fetch failed: {}", e);
                 Err(http::StatusCode::NOT_FOUND)
             }
         };
@@ -52,41 +62,57 @@ pub async fn get_recipe(
         tags: Some(tags), ..
     } = params
     {
-        log::info!("Recipe tags: {}", tags);
+        tracing::info!("Recipe tags: {}", tags);
 
-        let mut tags_string = String::new();
-        for c in tags.chars() {
-            if c.is_alphabetic() || c == ',' {
-                let cl: String = c.to_lowercase().collect();
-                tags_string.push_str(&cl);
-            }
-        }
-
-        let recipe_result = recipe::get_tagged(&db, tags_string.split(',')).await;
+        // The store normalizes tag names itself.
+        let tags: Vec<String> = tags.split(',').map(str::to_string).collect();
+        let recipe_result = store.get_tagged(&tags).await;
         match recipe_result {
             Ok(Some(id)) => {
                 let uri = format!("/?id={}", id);
                 return Ok(response::Redirect::to(&uri).into_response());
             }
             Ok(None) => {
-                log::info!("Tagged recipe selection was empty");
+                tracing::info!("Tagged recipe selection was empty");
+                let suggestions = tags::did_you_mean(store.as_ref(), &tags)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Tag suggestion failed: {}", e);
+                        Vec::new()
+                    });
+                // Show a random recipe, noting the miss rather than hiding it.
+                if let Ok(id) = store.get_random().await
+                    && let Ok((recipe, recipe_tags)) = store.get(&id).await
+                {
+                    app_writer.current_recipe = recipe.clone();
+                    let unmatched: Vec<&str> = tags
+                        .iter()
+                        .map(|tag| tag.trim())
+                        .filter(|tag| !tag.is_empty())
+                        .collect();
+                    let unmatched = unmatched.join(", ");
+                    let page = IndexTemplate::new(recipe, recipe_tags.join(", "))
+                        .with_tag_counts(tag_counts(store.as_ref()).await)
+                        .with_suggestions(unmatched, suggestions);
+                    return Ok(Html(page.to_string()).into_response());
+                }
             }
             Err(e) => {
-                log::error!("Tagged recipe selection database error: {}", e);
+                tracing::error!("Tagged recipe selection database error: {}", e);
                 panic!("Tagged recipe selection database error");
             }
         }
     }
 
     // Otherwise, fallback to a random recipe
-    let recipe_result = recipe::get_random(&db).await;
+    let recipe_result = store.get_random().await;
     match recipe_result {
         Ok(id) => {
             let uri = format!("/?id={}", id);
             Ok(response::Redirect::to(&uri).into_response())
         }
         Err(e) => {
-            log::error!("Random recipe selection failed: {}", e);
+            tracing::error!("Random recipe selection failed: {}", e);
             let tag_string = "Empty".to_string();
             let recipe = app_writer.current_recipe.clone();
             let recipe = IndexTemplate::new(recipe, tag_string);
@@ -95,6 +121,15 @@ pub async fn get_recipe(
     }
 }
 
+/// Tags with their recipe counts for the page's tag list, or none if they
+/// cannot be read.
+async fn tag_counts(store: &dyn store::Store) -> Vec<tags::TagCount> {
+    store.tag_counts().await.unwrap_or_else(|e| {
+        tracing::warn!("Tag count failed: {}", e);
+        Vec::new()
+    })
+}
+
 use crate::recipe::Recipe;
 use crate::templates::IndexTemplate;
 
@@ -112,6 +147,7 @@ pub async fn serve_leptos_ui() -> Html<String> {
         ingredients: "ingredient1, ingredient2".to_string(),
         time_to_prepare: "30 minutes".to_string(),
         source: "https://example.com".to_string(),
+        updated_at: Utc::now(),
     };
 
     let tags = String::from("sample,example");